@@ -0,0 +1,256 @@
+//! `loro`: inspect and manipulate the files exported by [LoroDoc]
+//!
+//! All the commands that take several files import them into one document,
+//! so a snapshot can be given together with the updates after it.
+
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Parser, Subcommand};
+use loro_common::{HasCounterSpan, IdSpanVector, ID};
+use loro_internal::{
+    decode_header_info, read_envelope_metadata, version::Frontiers, LoroDoc, PayloadMode, ToJson,
+};
+use serde_json::Value;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(
+    name = "loro",
+    version,
+    about = "Inspect and manipulate Loro document files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header of a file and the size info of its changes
+    Inspect { file: PathBuf },
+    /// Print the deep value of the document as JSON
+    Value {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// List the changes and the frontiers of the document
+    Log {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Merge the files into one snapshot
+    Merge {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Checkout the document to a version. Print its value, or export it as a snapshot
+    Checkout {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// The frontiers of the version, e.g. `3@1,10@A2`. The peers are in hex
+        #[arg(long)]
+        at: String,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Compare the versions and the values of two files.
+    /// Exit with 1 if they're different
+    Diff { a: PathBuf, b: PathBuf },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let ans = match cli.command {
+        Command::Inspect { file } => inspect(&file),
+        Command::Value { files } => value(&files),
+        Command::Log { files } => log(&files),
+        Command::Merge { files, output } => merge(&files, &output),
+        Command::Checkout { files, at, output } => checkout(&files, &at, output.as_deref()),
+        Command::Diff { a, b } => diff(&a, &b),
+    };
+    match ans {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e).into())
+}
+
+fn load(files: &[PathBuf]) -> Result<LoroDoc> {
+    let doc = LoroDoc::new();
+    for file in files {
+        doc.import(&read(file)?)
+            .map_err(|e| format!("cannot import {}: {}", file.display(), e))?;
+    }
+    Ok(doc)
+}
+
+fn fmt_frontiers(frontiers: &Frontiers) -> String {
+    let ids: Vec<String> = frontiers.iter().map(|x| x.to_string()).collect();
+    format!("[{}]", ids.join(", "))
+}
+
+fn parse_frontiers(s: &str) -> Result<Frontiers> {
+    let mut ids = Vec::new();
+    for id in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        ids.push(ID::try_from(id).map_err(|_| format!("invalid id {}", id))?);
+    }
+    Ok(ids.into())
+}
+
+fn fmt_spans(spans: &IdSpanVector) -> Vec<String> {
+    let mut spans: Vec<_> = spans.iter().collect();
+    spans.sort_by_key(|(peer, _)| **peer);
+    spans
+        .into_iter()
+        .map(|(peer, span)| format!("{:X}: {}..{}", peer, span.start, span.end))
+        .collect()
+}
+
+fn inspect(file: &Path) -> Result<ExitCode> {
+    let bytes = read(file)?;
+    let header = decode_header_info(&bytes)?;
+    println!("schema version: {}", header.schema_version);
+    println!("mode: {:?}", header.mode);
+    if let Some(codec) = header.codec {
+        println!("codec: {}", codec);
+    }
+    if let Some(checksum) = header.checksum {
+        println!("checksum: {:#010x}", checksum);
+    }
+    println!("size: {}", bytes.len());
+    println!("payload size: {}", header.payload_len);
+    println!("signed spans: {}", header.signed_spans.len());
+    if header.mode == PayloadMode::Encrypted {
+        let meta = read_envelope_metadata(&bytes)?;
+        println!("key id: {}", meta.key_id);
+        println!("encrypted snapshot: {}", meta.is_snapshot);
+        for span in meta.peer_spans {
+            println!(
+                "encrypted changes: {:X}: {}..{}",
+                span.client_id, span.counter.start, span.counter.end
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let doc = load(&[file.to_path_buf()])?;
+    doc.diagnose_size();
+    println!("frontiers: {}", fmt_frontiers(&doc.oplog_frontiers()));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn value(files: &[PathBuf]) -> Result<ExitCode> {
+    let doc = load(files)?;
+    println!("{}", doc.get_deep_value().to_json_pretty());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn log(files: &[PathBuf]) -> Result<ExitCode> {
+    let doc = load(files)?;
+    let oplog = doc.oplog().lock().unwrap();
+    let mut changes: Vec<_> = oplog.changes().values().flatten().collect();
+    changes.sort_by_key(|c| (c.lamport, c.id.peer));
+    for change in changes {
+        println!(
+            "{} lamport: {} timestamp: {} deps: {} ops: {} atoms: {}",
+            change.id,
+            change.lamport,
+            change.timestamp,
+            fmt_frontiers(&change.deps),
+            change.ops.len(),
+            change.ctr_end() - change.id.counter,
+        );
+    }
+    drop(oplog);
+    println!("frontiers: {}", fmt_frontiers(&doc.oplog_frontiers()));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn merge(files: &[PathBuf], output: &Path) -> Result<ExitCode> {
+    let doc = load(files)?;
+    fs::write(output, doc.export_snapshot())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn checkout(files: &[PathBuf], at: &str, output: Option<&Path>) -> Result<ExitCode> {
+    let mut doc = load(files)?;
+    let frontiers = parse_frontiers(at)?;
+    match output {
+        Some(output) => fs::write(output, doc.export_snapshot_at(&frontiers)?)?,
+        None => {
+            doc.checkout(&frontiers)?;
+            println!("{}", doc.get_deep_value().to_json_pretty());
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn diff(a: &Path, b: &Path) -> Result<ExitCode> {
+    let doc_a = load(&[a.to_path_buf()])?;
+    let doc_b = load(&[b.to_path_buf()])?;
+    let vv_diff = doc_a.oplog_vv().diff(&doc_b.oplog_vv());
+    let mut lines = Vec::new();
+    for span in fmt_spans(&vv_diff.left) {
+        lines.push(format!("only in {}: {}", a.display(), span));
+    }
+    for span in fmt_spans(&vv_diff.right) {
+        lines.push(format!("only in {}: {}", b.display(), span));
+    }
+    diff_json(
+        "",
+        &doc_a.get_deep_value().to_json_value(),
+        &doc_b.get_deep_value().to_json_value(),
+        &mut lines,
+    );
+    for line in lines.iter() {
+        println!("{}", line);
+    }
+    Ok(if lines.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Push the differences of the values as lines of `- path: old` and `+ path: new`
+fn diff_json(path: &str, a: &Value, b: &Value, lines: &mut Vec<String>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let path = format!("{}/{}", path, key);
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => diff_json(&path, x, y, lines),
+                    (Some(x), None) => lines.push(format!("- {}: {}", path, x)),
+                    (None, Some(y)) => lines.push(format!("+ {}: {}", path, y)),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Value::Array(x), Value::Array(y)) if x.len() == y.len() => {
+            for (i, (x, y)) in x.iter().zip(y.iter()).enumerate() {
+                diff_json(&format!("{}/{}", path, i), x, y, lines);
+            }
+        }
+        _ if a == b => {}
+        _ => {
+            lines.push(format!("- {}: {}", path, a));
+            lines.push(format!("+ {}: {}", path, b));
+        }
+    }
+}
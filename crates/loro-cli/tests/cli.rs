@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use loro_internal::LoroDoc;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("loro-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn loro(args: &[&dyn AsRef<std::ffi::OsStr>]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_loro"))
+        .args(args.iter().map(|x| x.as_ref()))
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn json(output: &Output) -> serde_json::Value {
+    serde_json::from_str(&stdout(output)).unwrap()
+}
+
+/// Peer 1 writes "hello", then peer 2 appends " world" concurrently with peer 1 setting a map key
+fn write_docs(dir: &TempDir) -> (PathBuf, PathBuf, PathBuf) {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    a.get_text("text").insert_(0, "hello").unwrap();
+    a.commit_then_renew();
+    let snapshot = dir.write("snapshot.loro", &a.export_snapshot());
+
+    let b = LoroDoc::new_auto_commit();
+    b.set_peer_id(2).unwrap();
+    b.import(&a.export_snapshot()).unwrap();
+    b.get_text("text").insert_(5, " world").unwrap();
+    b.commit_then_renew();
+    let update_b = dir.write("b.loro", &b.export_from(&a.oplog_vv()));
+
+    a.get_map("map").insert_("key", 1.into()).unwrap();
+    a.commit_then_renew();
+    let update_a = dir.write("a.loro", &a.export_from(&b.oplog_vv()));
+    (snapshot, update_a, update_b)
+}
+
+#[test]
+fn value_and_log() {
+    let dir = TempDir::new("value");
+    let (snapshot, a, b) = write_docs(&dir);
+    assert_eq!(
+        json(&loro(&[&"value", &snapshot, &a, &b])),
+        serde_json::json!({"text": "hello world", "map": {"key": 1}})
+    );
+
+    let log = stdout(&loro(&[&"log", &snapshot, &a, &b]));
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", log);
+    assert!(lines[0].starts_with("0@1 lamport: 0"));
+    assert!(lines[1].contains("deps: [4@1]"));
+    assert_eq!(lines[3], "frontiers: [5@1, 5@2]");
+}
+
+#[test]
+fn inspect() {
+    let dir = TempDir::new("inspect");
+    let (snapshot, _, _) = write_docs(&dir);
+    let out = stdout(&loro(&[&"inspect", &snapshot]));
+    assert!(out.contains("mode: Snapshot"), "{}", out);
+    assert!(out.contains("total changes: 1"), "{}", out);
+    assert!(out.contains("frontiers: [4@1]"), "{}", out);
+
+    let invalid = dir.write("invalid.loro", b"not a loro file");
+    let output = loro(&[&"inspect", &invalid]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn merge_and_checkout() {
+    let dir = TempDir::new("merge");
+    let (snapshot, a, b) = write_docs(&dir);
+    let merged = dir.0.join("merged.loro");
+    stdout(&loro(&[&"merge", &snapshot, &a, &b, &"-o", &merged]));
+    let doc = LoroDoc::from_snapshot(&fs::read(&merged).unwrap()).unwrap();
+    assert_eq!(
+        doc.get_text("text")
+            .get_value()
+            .as_string()
+            .unwrap()
+            .as_str(),
+        "hello world"
+    );
+
+    assert_eq!(
+        json(&loro(&[&"checkout", &merged, &"--at", &"5@2"])),
+        serde_json::json!({"text": "hello world", "map": {}})
+    );
+    let old = dir.0.join("old.loro");
+    stdout(&loro(&[&"checkout", &merged, &"--at", &"5@1", &"-o", &old]));
+    assert_eq!(
+        json(&loro(&[&"value", &old])),
+        serde_json::json!({"text": "hello", "map": {"key": 1}})
+    );
+}
+
+#[test]
+fn diff() {
+    let dir = TempDir::new("diff");
+    let (snapshot, a, b) = write_docs(&dir);
+    let merged = dir.0.join("merged.loro");
+    stdout(&loro(&[&"merge", &snapshot, &a, &b, &"-o", &merged]));
+    let merged_a = dir.0.join("merged_a.loro");
+    stdout(&loro(&[&"merge", &snapshot, &a, &"-o", &merged_a]));
+
+    let output = loro(&[&"diff", &merged, &merged]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = loro(&[&"diff", &merged_a, &merged]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = String::from_utf8(output.stdout).unwrap();
+    let expected = format!(
+        "only in {}: 2: 0..6\n- /text: \"hello\"\n+ /text: \"hello world\"\n",
+        display(&merged)
+    );
+    assert_eq!(out, expected);
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
@@ -40,6 +40,16 @@ pub enum LoroError {
     AutoCommitNotStarted,
     #[error("The doc is already dropped")]
     DocDropError,
+    #[error("Checksum mismatch (expected {expected:#010x}, found {found:#010x}). The data may be truncated or corrupted")]
+    ChecksumMismatch { expected: u32, found: u32 },
+    #[error("Encryption error ({0})")]
+    EncryptError(Box<str>),
+    #[error("Decryption error ({0})")]
+    DecryptError(Box<str>),
+    #[error("Signature error ({0})")]
+    SignatureError(Box<str>),
+    #[error("The change {id} is concurrent to the current version. It cannot be applied without the history")]
+    ConcurrentChange { id: ID },
     // #[error("the data for key `{0}` is not available")]
     // Redaction(String),
     // #[error("invalid header (expected {expected:?}, found {found:?})")]
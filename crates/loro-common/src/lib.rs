@@ -260,6 +260,17 @@ mod container {
     }
 }
 
+/// The id of the reserved map that stores the version tags of a doc.
+///
+/// It's a normal container id at the null peer, which is never the peer of an op, so no
+/// container of the users can have it. It's replicated like the other containers, but it
+/// isn't a child of any container, so it's hidden from the values of the state.
+pub const VERSION_TAGS_CONTAINER: ContainerID = ContainerID::Normal {
+    peer: PeerID::MAX,
+    counter: 0,
+    container_type: ContainerType::Map,
+};
+
 /// In movable tree, we use a specific [`TreeID`] to represent the root of **ALL** non-existent tree nodes.
 ///
 /// When we create some tree node and then we checkout the previous vision, we need to delete it from the state.
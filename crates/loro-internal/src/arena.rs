@@ -7,7 +7,7 @@ use std::{
 
 use append_only_bytes::BytesSlice;
 use fxhash::FxHashMap;
-use loro_common::PeerID;
+use loro_common::{PeerID, VERSION_TAGS_CONTAINER};
 
 use crate::{
     change::Lamport,
@@ -86,6 +86,9 @@ impl<'a> OpConverter<'a> {
                 self.root_c_idx.push(idx);
                 self.parents.insert(idx, None);
                 self.container_idx_depth.push(1);
+            } else if *id == VERSION_TAGS_CONTAINER {
+                self.parents.insert(idx, None);
+                self.container_idx_depth.push(1);
             } else {
                 self.container_idx_depth.push(0);
             }
@@ -206,6 +209,10 @@ impl SharedArena {
             self.inner.root_c_idx.lock().unwrap().push(idx);
             self.inner.parents.lock().unwrap().insert(idx, None);
             self.inner.depth.lock().unwrap().push(1);
+        } else if *id == VERSION_TAGS_CONTAINER {
+            // it's at the top level like a root container, but it's not one of them
+            self.inner.parents.lock().unwrap().insert(idx, None);
+            self.inner.depth.lock().unwrap().push(1);
         } else {
             self.inner.depth.lock().unwrap().push(0);
         }
@@ -0,0 +1,452 @@
+//! Ephemeral states of the peers, such as cursors, user names and online status.
+//!
+//! They're not persisted in the [crate::OpLog]. Each peer can only set its own state,
+//! and the newest state of each peer wins. The states not updated within the timeout
+//! are removed by [Awareness::remove_outdated].
+//!
+//! A peer can also remove its state by [Awareness::remove_local_state]. The removal is
+//! encoded like a state, so the other peers don't need to wait for the timeout.
+
+use std::sync::Arc;
+
+use fxhash::FxHashMap;
+use loro_common::{Counter, LoroValue, PeerID};
+use serde::{Deserialize, Serialize};
+
+use crate::{change::get_sys_timestamp_ms, obs::SubID, LoroError};
+
+/// The state of a peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub state: LoroValue,
+    /// Increased by the peer every time it sets its state
+    pub counter: Counter,
+    /// The local time in milliseconds when the state was last updated
+    pub timestamp: i64,
+}
+
+/// The peers whose states are changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AwarenessEvent {
+    pub added: Vec<PeerID>,
+    pub updated: Vec<PeerID>,
+    pub removed: Vec<PeerID>,
+    /// Whether it's caused by [Awareness::set_local_state]
+    pub local: bool,
+}
+
+impl AwarenessEvent {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+pub type AwarenessSubscriber = Arc<dyn Fn(&AwarenessEvent) + Send + Sync>;
+
+/// Return the current time in milliseconds
+pub type Clock = Arc<dyn Fn() -> i64 + Send + Sync>;
+
+#[derive(Serialize, Deserialize)]
+struct EncodedPeerInfo {
+    peer: PeerID,
+    counter: Counter,
+    /// `None` if the state is removed by the peer
+    state: Option<LoroValue>,
+}
+
+/// A last-writer-wins map from peers to their ephemeral states.
+///
+/// It shares nothing with [crate::LoroDoc], the encoded states can be sent over the
+/// same channel as the updates of the doc.
+pub struct Awareness {
+    peer: PeerID,
+    /// In milliseconds
+    timeout: i64,
+    peers: FxHashMap<PeerID, PeerInfo>,
+    /// The counters of the removed states and the local time when they're removed.
+    /// They're kept until the timeout, so that the older states are ignored
+    removed: FxHashMap<PeerID, (Counter, i64)>,
+    /// The counter of the next local state. It keeps increasing after the local state
+    /// is removed, so that the new state overrides the removal on the other peers
+    counter: Counter,
+    clock: Clock,
+    subscribers: FxHashMap<SubID, AwarenessSubscriber>,
+    next_sub_id: u32,
+}
+
+impl Awareness {
+    /// `timeout` is in milliseconds
+    pub fn new(peer: PeerID, timeout: i64) -> Self {
+        Self::with_clock(peer, timeout, Arc::new(get_sys_timestamp_ms))
+    }
+
+    /// Use the given clock instead of the system time to timestamp the states.
+    ///
+    /// `timeout` is in milliseconds
+    pub fn with_clock(peer: PeerID, timeout: i64, clock: Clock) -> Self {
+        Self {
+            peer,
+            timeout,
+            peers: Default::default(),
+            removed: Default::default(),
+            counter: 0,
+            clock,
+            subscribers: Default::default(),
+            next_sub_id: 0,
+        }
+    }
+
+    #[inline]
+    pub fn peer(&self) -> PeerID {
+        self.peer
+    }
+
+    /// Set the state of the local peer.
+    ///
+    /// It should be called periodically, even if the state is not changed,
+    /// so that the other peers don't remove it as outdated.
+    pub fn set_local_state(&mut self, state: impl Into<LoroValue>) {
+        let peer = self.peer;
+        let info = PeerInfo {
+            state: state.into(),
+            counter: self.counter,
+            timestamp: (self.clock)(),
+        };
+        self.counter += 1;
+        self.removed.remove(&peer);
+        let mut event = AwarenessEvent {
+            local: true,
+            ..Default::default()
+        };
+        if self.peers.insert(peer, info).is_some() {
+            event.updated.push(peer);
+        } else {
+            event.added.push(peer);
+        }
+        self.emit(&event);
+    }
+
+    /// Remove the state of the local peer, e.g. when the user goes offline.
+    ///
+    /// The removal is sent to the other peers by encoding the local peer, like a new state.
+    pub fn remove_local_state(&mut self) {
+        let peer = self.peer;
+        if self.peers.remove(&peer).is_none() {
+            return;
+        }
+
+        self.removed.insert(peer, (self.counter, (self.clock)()));
+        self.counter += 1;
+        self.emit(&AwarenessEvent {
+            removed: vec![peer],
+            local: true,
+            ..Default::default()
+        });
+    }
+
+    pub fn get_local_state(&self) -> Option<LoroValue> {
+        self.peers.get(&self.peer).map(|x| x.state.clone())
+    }
+
+    pub fn get_all_states(&self) -> &FxHashMap<PeerID, PeerInfo> {
+        &self.peers
+    }
+
+    /// Encode the states of the given peers, including the removed ones that are not outdated.
+    /// The unknown peers are skipped.
+    pub fn encode(&self, peers: &[PeerID]) -> Vec<u8> {
+        let encoded: Vec<_> = peers
+            .iter()
+            .filter_map(|peer| match self.peers.get(peer) {
+                Some(info) => Some(EncodedPeerInfo {
+                    peer: *peer,
+                    counter: info.counter,
+                    state: Some(info.state.clone()),
+                }),
+                None => self.removed.get(peer).map(|(counter, _)| EncodedPeerInfo {
+                    peer: *peer,
+                    counter: *counter,
+                    state: None,
+                }),
+            })
+            .collect();
+        postcard::to_allocvec(&encoded).unwrap()
+    }
+
+    pub fn encode_all(&self) -> Vec<u8> {
+        let mut peers: Vec<_> = self
+            .peers
+            .keys()
+            .chain(self.removed.keys())
+            .copied()
+            .collect();
+        peers.sort_unstable();
+        self.encode(&peers)
+    }
+
+    /// Apply the states encoded by a remote [Awareness]. The states older than
+    /// the known ones are ignored.
+    pub fn apply(&mut self, bytes: &[u8]) -> Result<(), LoroError> {
+        let encoded: Vec<EncodedPeerInfo> = postcard::from_bytes(bytes)
+            .map_err(|e| LoroError::DecodeError(e.to_string().into_boxed_str()))?;
+        let now = (self.clock)();
+        let mut event = AwarenessEvent::default();
+        for EncodedPeerInfo {
+            peer,
+            counter,
+            state,
+        } in encoded
+        {
+            // only the local peer can set its own state
+            if peer == self.peer {
+                continue;
+            }
+
+            let known = match self.peers.get(&peer) {
+                Some(old) => Some(old.counter),
+                None => self.removed.get(&peer).map(|(counter, _)| *counter),
+            };
+            if matches!(known, Some(known) if known >= counter) {
+                continue;
+            }
+
+            let Some(state) = state else {
+                if self.peers.remove(&peer).is_some() {
+                    event.removed.push(peer);
+                }
+                self.removed.insert(peer, (counter, now));
+                continue;
+            };
+
+            self.removed.remove(&peer);
+            let info = PeerInfo {
+                state,
+                counter,
+                timestamp: now,
+            };
+            if self.peers.insert(peer, info).is_some() {
+                event.updated.push(peer);
+            } else {
+                event.added.push(peer);
+            }
+        }
+
+        self.emit(&event);
+        Ok(())
+    }
+
+    /// Remove the states of the remote peers that are not updated within the timeout.
+    /// Return the removed peers.
+    pub fn remove_outdated(&mut self) -> Vec<PeerID> {
+        let now = (self.clock)();
+        let local = self.peer;
+        let timeout = self.timeout;
+        self.removed
+            .retain(|_, (_, timestamp)| now - *timestamp <= timeout);
+        let mut removed: Vec<PeerID> = self
+            .peers
+            .iter()
+            .filter(|(peer, info)| **peer != local && now - info.timestamp > timeout)
+            .map(|(peer, _)| *peer)
+            .collect();
+        removed.sort_unstable();
+        for peer in removed.iter() {
+            self.peers.remove(peer);
+        }
+
+        self.emit(&AwarenessEvent {
+            removed: removed.clone(),
+            ..Default::default()
+        });
+        removed
+    }
+
+    pub fn subscribe(&mut self, callback: AwarenessSubscriber) -> SubID {
+        let id = SubID::from_u32(self.next_sub_id);
+        self.next_sub_id += 1;
+        self.subscribers.insert(id, callback);
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubID) {
+        self.subscribers.remove(&id);
+    }
+
+    fn emit(&self, event: &AwarenessEvent) {
+        if event.is_empty() {
+            return;
+        }
+
+        for subscriber in self.subscribers.values() {
+            subscriber(event);
+        }
+    }
+}
+
+impl std::fmt::Debug for Awareness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Awareness")
+            .field("peer", &self.peer)
+            .field("timeout", &self.timeout)
+            .field("peers", &self.peers)
+            .field("removed", &self.removed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    };
+
+    use crate::awareness::{Awareness, AwarenessEvent, Clock};
+    use loro_common::LoroValue;
+
+    fn cursor(pos: i32) -> LoroValue {
+        let mut map = fxhash::FxHashMap::default();
+        map.insert("cursor".to_string(), pos.into());
+        map.into()
+    }
+
+    /// A clock that only moves when the test advances it
+    fn manual_clock() -> (Clock, Arc<AtomicI64>) {
+        let now = Arc::new(AtomicI64::new(0));
+        let now_clone = now.clone();
+        (Arc::new(move || now_clone.load(Ordering::SeqCst)), now)
+    }
+
+    #[test]
+    fn states_are_synced() {
+        let mut a = Awareness::new(1, 30_000);
+        let mut b = Awareness::new(2, 30_000);
+        a.set_local_state(cursor(0));
+        b.set_local_state(cursor(5));
+        b.apply(&a.encode_all()).unwrap();
+        a.apply(&b.encode(&[2])).unwrap();
+        assert_eq!(a.get_all_states().len(), 2);
+        assert_eq!(b.get_all_states()[&1].state, cursor(0));
+        assert_eq!(a.get_all_states()[&2].state, cursor(5));
+
+        // the stale state is ignored
+        let old = a.encode(&[1]);
+        a.set_local_state(cursor(3));
+        b.apply(&a.encode(&[1])).unwrap();
+        b.apply(&old).unwrap();
+        assert_eq!(b.get_all_states()[&1].state, cursor(3));
+        assert_eq!(b.get_all_states()[&1].counter, 1);
+
+        // the remote cannot override the local state
+        a.apply(&b.encode_all()).unwrap();
+        assert_eq!(a.get_local_state(), Some(cursor(3)));
+
+        assert!(b.apply(&[255, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn removal_is_sent_to_peers() {
+        let mut a = Awareness::new(1, 30_000);
+        let mut b = Awareness::new(2, 30_000);
+        a.set_local_state(cursor(0));
+        a.set_local_state(cursor(1));
+        let old = a.encode(&[1]);
+        b.apply(&old).unwrap();
+        assert_eq!(b.get_all_states()[&1].state, cursor(1));
+
+        a.remove_local_state();
+        let removal = a.encode_all();
+        b.apply(&removal).unwrap();
+        assert!(!b.get_all_states().contains_key(&1));
+        // the state before the removal is ignored
+        b.apply(&old).unwrap();
+        assert!(!b.get_all_states().contains_key(&1));
+
+        // the counter keeps increasing after the removal
+        a.set_local_state(cursor(2));
+        assert_eq!(a.get_all_states()[&1].counter, 3);
+        b.apply(&a.encode(&[1])).unwrap();
+        assert_eq!(b.get_all_states()[&1].state, cursor(2));
+        b.apply(&removal).unwrap();
+        assert_eq!(b.get_all_states()[&1].state, cursor(2));
+    }
+
+    #[test]
+    fn outdated_states_are_removed() {
+        let (clock, now) = manual_clock();
+        let mut a = Awareness::new(1, 30_000);
+        let mut b = Awareness::with_clock(2, 10, clock);
+        a.set_local_state(cursor(0));
+        b.set_local_state(cursor(1));
+        b.apply(&a.encode_all()).unwrap();
+        now.store(10, Ordering::SeqCst);
+        assert!(b.remove_outdated().is_empty());
+
+        now.store(11, Ordering::SeqCst);
+        assert_eq!(b.remove_outdated(), vec![1]);
+        // the local state is kept
+        assert_eq!(b.get_all_states().len(), 1);
+        assert_eq!(b.get_local_state(), Some(cursor(1)));
+
+        // the peer is added again once it sends a new state
+        b.apply(&a.encode_all()).unwrap();
+        assert_eq!(b.get_all_states().len(), 2);
+    }
+
+    #[test]
+    fn subscribe() {
+        let (clock, now) = manual_clock();
+        let mut a = Awareness::new(1, 30_000);
+        let mut b = Awareness::with_clock(2, 0, clock);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        let id = b.subscribe(Arc::new(move |e: &AwarenessEvent| {
+            events_clone.lock().unwrap().push(e.clone());
+        }));
+
+        b.set_local_state(cursor(0));
+        a.set_local_state(cursor(0));
+        b.apply(&a.encode_all()).unwrap();
+        a.set_local_state(cursor(1));
+        b.apply(&a.encode_all()).unwrap();
+        // nothing changed, no event
+        b.apply(&a.encode_all()).unwrap();
+        now.store(1, Ordering::SeqCst);
+        b.remove_outdated();
+        b.remove_local_state();
+        // there is no local state to remove
+        b.remove_local_state();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                AwarenessEvent {
+                    added: vec![2],
+                    local: true,
+                    ..Default::default()
+                },
+                AwarenessEvent {
+                    added: vec![1],
+                    ..Default::default()
+                },
+                AwarenessEvent {
+                    updated: vec![1],
+                    ..Default::default()
+                },
+                AwarenessEvent {
+                    removed: vec![1],
+                    ..Default::default()
+                },
+                AwarenessEvent {
+                    removed: vec![2],
+                    local: true,
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(b.get_local_state().is_none());
+
+        b.unsubscribe(id);
+        b.set_local_state(cursor(1));
+        assert_eq!(events.lock().unwrap().len(), 5);
+    }
+}
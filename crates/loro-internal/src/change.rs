@@ -199,18 +199,25 @@ impl Change {
     }
 }
 
-#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+/// The unix timestamp in seconds
 pub(crate) fn get_sys_timestamp() -> Timestamp {
+    get_sys_timestamp_ms() / 1000
+}
+
+/// The unix timestamp in milliseconds
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub(crate) fn get_sys_timestamp_ms() -> Timestamp {
     use std::time::{SystemTime, UNIX_EPOCH};
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs()
+        .as_millis()
         .as_()
 }
 
+/// The unix timestamp in milliseconds
 #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
-pub fn get_sys_timestamp() -> Timestamp {
+pub(crate) fn get_sys_timestamp_ms() -> Timestamp {
     use wasm_bindgen::prelude::wasm_bindgen;
     #[wasm_bindgen]
     extern "C" {
@@ -220,7 +227,7 @@ pub fn get_sys_timestamp() -> Timestamp {
         pub fn now() -> f64;
     }
 
-    (now() / 1000.0) as Timestamp
+    now() as Timestamp
 }
 
 #[cfg(test)]
@@ -16,8 +16,14 @@ pub(crate) mod richtext_state;
 mod style_range_map;
 mod tracker;
 
-use crate::{change::Lamport, delta::StyleMeta, utils::string_slice::StringSlice, InternalString};
+use crate::{
+    change::{Lamport, Timestamp},
+    delta::StyleMeta,
+    utils::string_slice::StringSlice,
+    InternalString,
+};
 use fugue_span::*;
+use fxhash::FxHashMap;
 use loro_common::{Counter, LoroValue, PeerID, ID};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -35,6 +41,29 @@ pub struct RichtextSpan {
     pub attributes: StyleMeta,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, serde::Serialize, serde::Deserialize)]
+pub enum TextChangeKind {
+    Inserted,
+    Deleted,
+    Unchanged,
+}
+
+/// A span of text in the changes between two versions.
+/// See [crate::TextHandler::changes_between].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TextChange {
+    pub kind: TextChangeKind,
+    pub text: String,
+    /// The op that produced the first char of the span.
+    ///
+    /// It's the deletion for the deleted text, and the insertion for the others.
+    /// If the text is removed because its insertion is not included in the target version,
+    /// it's the insertion.
+    pub id: ID,
+    pub lamport: Lamport,
+    pub timestamp: Timestamp,
+}
+
 /// This is used to communicate with the frontend.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Style {
@@ -357,6 +386,47 @@ impl TextStyleInfoFlag {
     }
 }
 
+/// The flags of the text styles by their keys.
+///
+/// It's used when the styles are applied by their keys only, e.g. by
+/// [crate::handler::TextHandler::apply_delta]. The keys that are not configured are
+/// treated as [TextStyleInfoFlag::BOLD].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyleConfigMap {
+    map: FxHashMap<InternalString, TextStyleInfoFlag>,
+}
+
+impl StyleConfigMap {
+    /// A config without any key
+    pub fn new() -> Self {
+        Self {
+            map: Default::default(),
+        }
+    }
+
+    pub fn insert(&mut self, key: InternalString, flag: TextStyleInfoFlag) {
+        self.map.insert(key, flag);
+    }
+
+    pub fn get(&self, key: &str) -> TextStyleInfoFlag {
+        self.map
+            .get(&InternalString::from(key))
+            .copied()
+            .unwrap_or(TextStyleInfoFlag::BOLD)
+    }
+}
+
+impl Default for StyleConfigMap {
+    /// `bold`, `link` and `comment` use the flags of the same names
+    fn default() -> Self {
+        let mut config = Self::new();
+        config.insert("bold".into(), TextStyleInfoFlag::BOLD);
+        config.insert("link".into(), TextStyleInfoFlag::LINK);
+        config.insert("comment".into(), TextStyleInfoFlag::COMMENT);
+        config
+    }
+}
+
 #[cfg(test)]
 mod test {
 
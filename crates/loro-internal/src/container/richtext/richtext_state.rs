@@ -37,13 +37,25 @@ use super::{
 
 pub(crate) use query::PosType;
 
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 pub(crate) struct RichtextState {
     tree: BTree<RichtextTreeTrait>,
     style_ranges: StyleRangeMap,
     cursor_cache: CursorCache,
 }
 
+impl Clone for RichtextState {
+    fn clone(&self) -> Self {
+        // `BTree::clone` doesn't keep the slots of the internal nodes in their parents,
+        // so the tree is rebuilt from its elements instead
+        Self {
+            tree: self.tree.iter().cloned().collect(),
+            style_ranges: self.style_ranges.clone(),
+            cursor_cache: Default::default(),
+        }
+    }
+}
+
 impl Display for RichtextState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         for span in self.tree.iter() {
@@ -2188,4 +2200,29 @@ mod test {
             }])
         );
     }
+
+    #[test]
+    fn edit_cloned_state() {
+        let mut wrapper = SimpleWrapper::default();
+        for i in 0..1000 {
+            wrapper.insert(0, "ab");
+            if i % 10 == 9 {
+                wrapper.mark(1..3, bold(i));
+            }
+        }
+
+        let mut cloned = wrapper.clone();
+        for w in [&mut wrapper, &mut cloned] {
+            for i in 0..100 {
+                w.insert(i * 7, "c");
+                w.delete(i * 5, 2);
+                w.mark(i * 3..i * 3 + 4, link(1000 + i as isize));
+            }
+        }
+
+        assert_eq!(
+            wrapper.state.get_richtext_value(),
+            cloned.state.get_richtext_value()
+        );
+    }
 }
@@ -23,12 +23,22 @@ use super::{StyleKey, StyleOp};
 /// This struct keep the mapping of ranges to numbers
 ///
 /// It's initialized with usize::MAX/2 length.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub(super) struct StyleRangeMap {
     pub(super) tree: BTree<RangeNumMapTrait>,
     has_style: bool,
 }
 
+impl Clone for StyleRangeMap {
+    fn clone(&self) -> Self {
+        // `BTree::clone` doesn't keep the slots of the internal nodes in their parents
+        Self {
+            tree: self.tree.iter().cloned().collect(),
+            has_style: self.has_style,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(super) struct RangeNumMapTrait;
 
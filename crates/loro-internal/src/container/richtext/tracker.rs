@@ -1,14 +1,16 @@
+use std::ops::Range;
+
 use generic_btree::LeafIndex;
-use loro_common::{Counter, IdSpan, PeerID, ID};
+use loro_common::{Counter, HasIdSpan, IdSpan, PeerID, ID};
 use rle::HasLength;
 
-use crate::VersionVector;
+use crate::{container::list::list_op::InnerListOp, VersionVector};
 
 use self::{crdt_rope::CrdtRope, id_to_cursor::IdToCursor};
 
 use super::{
-    fugue_span::{FugueSpan, Status},
-    RichtextChunk,
+    fugue_span::{DiffStatus, FugueSpan, RichtextChunkValue, Status},
+    AnchorType, RichtextChunk, TextChangeKind,
 };
 
 mod crdt_rope;
@@ -54,8 +56,7 @@ impl Tracker {
         this
     }
 
-    #[allow(unused)]
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             rope: CrdtRope::new(),
             id_to_cursor: IdToCursor::default(),
@@ -71,21 +72,11 @@ impl Tracker {
 
     pub(crate) fn insert(&mut self, op_id: ID, pos: usize, content: RichtextChunk) {
         if self.applied_vv.includes_id(op_id) {
-            let last_id = op_id.inc(content.len() as Counter - 1);
-            assert!(self.applied_vv.includes_id(last_id));
-            if !self.current_vv.includes_id(last_id) {
-                // PERF: may be slow
-                let mut updates = Default::default();
-                self.forward(
-                    IdSpan::new(
-                        op_id.peer,
-                        op_id.counter,
-                        op_id.counter + content.len() as Counter,
-                    ),
-                    &mut updates,
-                );
-                self.batch_update(updates, false);
-            }
+            self.forward_applied(IdSpan::new(
+                op_id.peer,
+                op_id.counter,
+                op_id.counter + content.len() as Counter,
+            ));
             return;
         }
 
@@ -129,17 +120,11 @@ impl Tracker {
     /// If `reverse` is true, the deletion happens from the end of the range to the start.
     pub(crate) fn delete(&mut self, op_id: ID, pos: usize, len: usize, reverse: bool) {
         if self.applied_vv.includes_id(op_id) {
-            let last_id = op_id.inc(len as Counter - 1);
-            assert!(self.applied_vv.includes_id(last_id));
-            if !self.current_vv.includes_id(last_id) {
-                // PERF: may be slow
-                let mut updates = Default::default();
-                self.forward(
-                    IdSpan::new(op_id.peer, op_id.counter, op_id.counter + len as Counter),
-                    &mut updates,
-                );
-                self.batch_update(updates, false);
-            }
+            self.forward_applied(IdSpan::new(
+                op_id.peer,
+                op_id.counter,
+                op_id.counter + len as Counter,
+            ));
             return;
         }
 
@@ -173,6 +158,79 @@ impl Tracker {
         self.applied_vv.extend_to_include_end_id(end_id);
     }
 
+    /// Make the applied span visible in the current version.
+    ///
+    /// Only the part that is not in the current version is forwarded, and the current
+    /// version is extended to include the span.
+    fn forward_applied(&mut self, span: IdSpan) {
+        let last_id = span.id_last();
+        assert!(self.applied_vv.includes_id(last_id));
+        let start = self
+            .current_vv
+            .get(&span.client_id)
+            .copied()
+            .unwrap_or(0)
+            .max(span.counter.start);
+        if start >= span.counter.end {
+            return;
+        }
+
+        // PERF: may be slow
+        let mut updates = Default::default();
+        self.forward(
+            IdSpan::new(span.client_id, start, span.counter.end),
+            &mut updates,
+        );
+        self.batch_update(updates, false);
+        self.current_vv.extend_to_include_end_id(span.id_end());
+    }
+
+    /// Apply a text op whose first atom has the id `op_id`.
+    ///
+    /// `style_id` is stored in the anchors inserted by [InnerListOp::StyleStart].
+    pub(crate) fn apply_list_op(&mut self, op_id: ID, op: &InnerListOp, style_id: u32) {
+        match op {
+            InnerListOp::Insert { slice, pos } => {
+                self.insert(op_id, *pos, RichtextChunk::new_text(slice.0.clone()));
+            }
+            InnerListOp::InsertText {
+                unicode_start,
+                unicode_len,
+                pos,
+                ..
+            } => {
+                self.insert(
+                    op_id,
+                    *pos as usize,
+                    RichtextChunk::new_text(*unicode_start..*unicode_start + *unicode_len),
+                );
+            }
+            InnerListOp::Delete(del) => {
+                self.delete(
+                    op_id,
+                    del.start() as usize,
+                    del.atom_len(),
+                    del.is_reversed(),
+                );
+            }
+            InnerListOp::StyleStart { start, end, .. } => {
+                debug_assert!(start < end, "start: {}, end: {}", start, end);
+                self.insert(
+                    op_id,
+                    *start as usize,
+                    RichtextChunk::new_style_anchor(style_id, AnchorType::Start),
+                );
+                self.insert(
+                    op_id.inc(1),
+                    // need to shift 1 because we insert the start style anchor before this pos
+                    *end as usize + 1,
+                    RichtextChunk::new_style_anchor(style_id, AnchorType::End),
+                );
+            }
+            InnerListOp::StyleEnd => {}
+        }
+    }
+
     #[inline]
     pub(crate) fn checkout(&mut self, vv: &VersionVector) {
         self._checkout(vv, false);
@@ -266,6 +324,57 @@ impl Tracker {
         }
     }
 
+    /// Iterate the visible text spans in the current version.
+    ///
+    /// It yields the id of the first char and the unicode range of the text in the arena.
+    /// Style anchors are skipped.
+    pub(crate) fn iter_visible_text(&self) -> impl Iterator<Item = (ID, Range<u32>)> + '_ {
+        self.rope.tree().iter().filter_map(|span| {
+            if !span.is_activated() {
+                return None;
+            }
+
+            match span.content.value() {
+                RichtextChunkValue::Text(range) => Some((span.id, range)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Iterate the text spans that are visible in `from` or `to` in the document order.
+    ///
+    /// It yields the kind of the change, the id of the first char and the unicode range
+    /// of the text in the arena.
+    pub(crate) fn iter_text_changes(
+        &mut self,
+        from: &VersionVector,
+        to: &VersionVector,
+    ) -> impl Iterator<Item = (TextChangeKind, ID, Range<u32>)> + '_ {
+        self._checkout(from, false);
+        self._checkout(to, true);
+        self.rope.tree().iter().filter_map(|span| {
+            let kind = match span.diff() {
+                DiffStatus::Created => TextChangeKind::Inserted,
+                DiffStatus::Deleted => TextChangeKind::Deleted,
+                DiffStatus::NotChanged if span.is_activated() => TextChangeKind::Unchanged,
+                DiffStatus::NotChanged => return None,
+            };
+
+            match span.content.value() {
+                RichtextChunkValue::Text(range) => Some((kind, span.id, range)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Iterate the deletions made by the ops in the given span.
+    ///
+    /// It yields the id of the first delete op and the id span deleted by it,
+    /// which may be reversed.
+    pub(crate) fn iter_deletions(&self, span: IdSpan) -> impl Iterator<Item = (ID, IdSpan)> + '_ {
+        self.id_to_cursor.iter_delete(span)
+    }
+
     pub(crate) fn diff(
         &mut self,
         from: &VersionVector,
@@ -150,6 +150,39 @@ impl IdToCursor {
         })
     }
 
+    /// Iterate the deletions in the given span of op ids.
+    ///
+    /// It yields the id of the first delete op and the id span deleted by it,
+    /// which may be reversed.
+    pub fn iter_delete(&self, mut span: IdSpan) -> impl Iterator<Item = (ID, IdSpan)> + '_ {
+        span.normalize_();
+        let list = self.map.get(&span.client_id).unwrap_or(&EMPTY_VEC);
+        let start = match list.binary_search_by_key(&span.counter.start, |x| x.counter) {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        };
+
+        list[start..]
+            .iter()
+            .take_while(move |f| f.counter < span.counter.end)
+            .filter_map(move |f| {
+                let Cursor::Delete(deleted) = &f.cursor else {
+                    return None;
+                };
+
+                let from = (span.counter.start - f.counter).max(0) as usize;
+                let to = ((span.counter.end - f.counter) as usize).min(deleted.atom_len());
+                if from >= to {
+                    return None;
+                }
+
+                Some((
+                    ID::new(span.client_id, f.counter + from as Counter),
+                    deleted.slice(from, to),
+                ))
+            })
+    }
+
     pub fn get_insert(&self, id: ID) -> Option<LeafIndex> {
         let list = self.map.get(&id.peer)?;
         let index = match list.binary_search_by_key(&id.counter, |x| x.counter) {
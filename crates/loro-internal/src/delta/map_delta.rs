@@ -13,6 +13,12 @@ use crate::{
 #[derive(Default, Debug, Clone, Serialize)]
 pub struct MapDelta {
     pub updated: FxHashMap<InternalString, MapValue>,
+    /// The values that are replaced by `updated`. It's `None` if the key didn't exist.
+    ///
+    /// It's filled when the delta is applied by `MapState::apply_diff_and_convert` and in the
+    /// events of local ops, where every key in `updated` has an entry here. The deltas
+    /// calculated by [crate::diff_calc::DiffCalculator] leave it empty.
+    pub old_values: FxHashMap<InternalString, Option<LoroValue>>,
 }
 
 impl MapDelta {
@@ -27,13 +33,22 @@ impl MapDelta {
                 updated.insert(k, v);
             }
         }
-        MapDelta { updated }
+        let mut old_values = self.old_values.clone();
+        for (k, v) in x.old_values.into_iter() {
+            // the value before both deltas is kept
+            old_values.entry(k).or_insert(v);
+        }
+        MapDelta {
+            updated,
+            old_values,
+        }
     }
 
     #[inline]
     pub fn new() -> Self {
         MapDelta {
             updated: FxHashMap::default(),
+            old_values: FxHashMap::default(),
         }
     }
 
@@ -42,6 +57,12 @@ impl MapDelta {
         self.updated.insert(key, map_value);
         self
     }
+
+    #[inline]
+    pub fn with_old_value(mut self, key: InternalString, old_value: Option<LoroValue>) -> Self {
+        self.old_values.insert(key, old_value);
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
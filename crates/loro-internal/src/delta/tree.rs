@@ -71,8 +71,9 @@ impl TreeDiffItem {
 }
 
 impl TreeDiff {
-    pub(crate) fn compose(self, _other: Self) -> Self {
-        unreachable!("tree compose")
+    /// The items are applied in order, so composing is appending
+    pub(crate) fn compose(self, other: Self) -> Self {
+        self.extend(other.diff)
     }
 
     pub(crate) fn extend<I: IntoIterator<Item = TreeDiffItem>>(mut self, other: I) -> Self {
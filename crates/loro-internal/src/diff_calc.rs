@@ -16,7 +16,7 @@ use crate::{
         idx::ContainerIdx,
         richtext::{
             richtext_state::{RichtextStateChunk, TextChunk},
-            AnchorType, CrdtRopeDelta, RichtextChunk, RichtextChunkValue, RichtextTracker, StyleOp,
+            CrdtRopeDelta, RichtextChunkValue, RichtextTracker, StyleOp,
         },
         text::tracker::Tracker,
         tree::tree_op::TreeOp,
@@ -38,7 +38,8 @@ use super::{event::InternalContainerDiff, oplog::OpLog};
 /// Calculate the diff between two versions. given [OpLog][super::oplog::OpLog]
 /// and [AppState][super::state::AppState].
 ///
-/// TODO: persist diffCalculator and skip processed version
+/// The states at some of the processed versions can be cached as checkpoints,
+/// see [crate::oplog::checkpoint].
 #[derive(Debug, Default)]
 pub struct DiffCalculator {
     /// ContainerIdx -> (depth, calculator)
@@ -58,6 +59,23 @@ impl DiffCalculator {
         }
     }
 
+    /// The number of the ops that need to be replayed to calculate the diff from
+    /// `before` to `after`. It's 0 if they're already processed.
+    pub(crate) fn replay_len(&self, before: &VersionVector, after: &VersionVector) -> usize {
+        if self.has_all && self.last_vv.includes_vv(before) && self.last_vv.includes_vv(after) {
+            return 0;
+        }
+
+        if !after.includes_vv(before) {
+            // the diff is calculated from the beginning, see [Self::calc_diff_internal]
+            let mut merged = before.clone();
+            merged.merge(after);
+            return merged.distance_to(&Default::default());
+        }
+
+        after.distance_to(before)
+    }
+
     // PERF: if the causal order is linear, we can skip some of the calculation
     #[allow(unused)]
     pub(crate) fn calc_diff(
@@ -433,7 +451,10 @@ impl DiffCalculatorTrait for MapDiffCalculator {
 
             updated.insert(key, value);
         }
-        InternalDiff::Map(MapDelta { updated })
+        InternalDiff::Map(MapDelta {
+            updated,
+            old_values: Default::default(),
+        })
     }
 }
 
@@ -588,69 +609,26 @@ impl DiffCalculatorTrait for RichtextDiffCalculator {
             self.tracker.checkout(vv);
         }
 
-        match &op.op().content {
-            crate::op::InnerContent::List(l) => match l {
-                crate::container::list::list_op::InnerListOp::Insert { slice, pos } => {
-                    self.tracker.insert(
-                        op.id_start(),
-                        *pos,
-                        RichtextChunk::new_text(slice.0.clone()),
-                    );
-                }
-                crate::container::list::list_op::InnerListOp::InsertText {
-                    slice: _,
-                    unicode_start,
-                    unicode_len: len,
-                    pos,
-                } => {
-                    self.tracker.insert(
-                        op.id_start(),
-                        *pos as usize,
-                        RichtextChunk::new_text(*unicode_start..*unicode_start + *len),
-                    );
-                }
-                crate::container::list::list_op::InnerListOp::Delete(del) => {
-                    self.tracker.delete(
-                        op.id_start(),
-                        del.start() as usize,
-                        del.atom_len(),
-                        del.is_reversed(),
-                    );
-                }
-                crate::container::list::list_op::InnerListOp::StyleStart {
-                    start,
-                    end,
-                    key,
-                    info,
-                    value,
-                } => {
-                    debug_assert!(start < end, "start: {}, end: {}", start, end);
-                    let style_id = self.styles.len();
-                    self.styles.push(StyleOp {
-                        lamport: op.lamport(),
-                        peer: op.peer,
-                        cnt: op.id_start().counter,
-                        key: key.clone(),
-                        value: value.clone(),
-                        info: *info,
-                    });
-                    self.tracker.insert(
-                        op.id_start(),
-                        *start as usize,
-                        RichtextChunk::new_style_anchor(style_id as u32, AnchorType::Start),
-                    );
-                    self.tracker.insert(
-                        op.id_start().inc(1),
-                        // need to shift 1 because we insert the start style anchor before this pos
-                        *end as usize + 1,
-                        RichtextChunk::new_style_anchor(style_id as u32, AnchorType::End),
-                    );
-                }
-                crate::container::list::list_op::InnerListOp::StyleEnd => {}
-            },
-            crate::op::InnerContent::Map(_) => unreachable!(),
-            crate::op::InnerContent::Tree(_) => unreachable!(),
+        let crate::op::InnerContent::List(l) = &op.op().content else {
+            unreachable!()
+        };
+
+        let style_id = self.styles.len() as u32;
+        if let crate::container::list::list_op::InnerListOp::StyleStart {
+            key, info, value, ..
+        } = l
+        {
+            self.styles.push(StyleOp {
+                lamport: op.lamport(),
+                peer: op.peer,
+                cnt: op.id_start().counter,
+                key: key.clone(),
+                value: value.clone(),
+                info: *info,
+            });
         }
+
+        self.tracker.apply_list_op(op.id_start(), l, style_id);
     }
 
     fn stop_tracking(&mut self, _oplog: &super::oplog::OpLog, _vv: &crate::VersionVector) {}
@@ -0,0 +1,206 @@
+//! Read-only views of historical versions.
+//!
+//! [crate::LoroDoc::checkout] moves the only state of the doc to the old version, so the doc
+//! can't be edited until it's attached again. A [DocView] owns a separate [DocState] at
+//! the given version instead. It shares the arena and the [OpLog] with the doc, so
+//! several views can coexist while the doc stays attached and editable.
+
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+};
+
+use loro_common::{ContainerType, LoroResult, LoroValue};
+
+use crate::{
+    arena::SharedArena,
+    container::{idx::ContainerIdx, IntoContainerId},
+    diff_calc::DiffCalculator,
+    event::InternalDocDiff,
+    state::DocState,
+    txn::Transaction,
+    version::Frontiers,
+    ListHandler, LoroError, MapHandler, OpLog, TextHandler, TreeHandler,
+};
+
+pub struct DocView {
+    frontiers: Frontiers,
+    state: Arc<Mutex<DocState>>,
+    arena: SharedArena,
+    oplog: Arc<Mutex<OpLog>>,
+    /// It's always `None`. The handlers of the view are read-only
+    txn: Arc<Mutex<Option<Transaction>>>,
+}
+
+impl DocView {
+    pub(crate) fn new(
+        oplog: Arc<Mutex<OpLog>>,
+        arena: SharedArena,
+        frontiers: &Frontiers,
+    ) -> LoroResult<Self> {
+        let mut state = DocState::new(arena.clone());
+        {
+            let oplog = oplog.lock().unwrap();
+            let Some(vv) = oplog.dag.frontiers_to_vv(frontiers) else {
+                return Err(LoroError::NotFoundError(
+                    format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
+                ));
+            };
+            let diff = DiffCalculator::new().calc_diff_internal(
+                &oplog,
+                &Default::default(),
+                Some(&Default::default()),
+                &vv,
+                Some(frontiers),
+            );
+            state.apply_diff(InternalDocDiff {
+                origin: "view".into(),
+                local: true,
+                diff: Cow::Owned(diff),
+                from_checkout: true,
+                new_version: Cow::Owned(frontiers.clone()),
+            });
+        }
+
+        Ok(Self {
+            frontiers: frontiers.clone(),
+            state: Arc::new(Mutex::new(state)),
+            arena,
+            oplog,
+            txn: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// The version of the view
+    #[inline]
+    pub fn frontiers(&self) -> &Frontiers {
+        &self.frontiers
+    }
+
+    /// The oplog shared with the doc, e.g. to get the blame of a text in the view
+    #[inline]
+    pub fn oplog(&self) -> &Arc<Mutex<OpLog>> {
+        &self.oplog
+    }
+
+    #[inline]
+    pub fn get_deep_value(&self) -> LoroValue {
+        self.state.lock().unwrap().get_deep_value()
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_text<I: IntoContainerId>(&self, id: I) -> TextHandler {
+        let idx = self.get_container_idx(id, ContainerType::Text);
+        TextHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_list<I: IntoContainerId>(&self, id: I) -> ListHandler {
+        let idx = self.get_container_idx(id, ContainerType::List);
+        ListHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_map<I: IntoContainerId>(&self, id: I) -> MapHandler {
+        let idx = self.get_container_idx(id, ContainerType::Map);
+        MapHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_tree<I: IntoContainerId>(&self, id: I) -> TreeHandler {
+        let idx = self.get_container_idx(id, ContainerType::Tree);
+        TreeHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    fn get_container_idx<I: IntoContainerId>(&self, id: I, c_type: ContainerType) -> ContainerIdx {
+        let id = id.into_container_id(&self.arena, c_type);
+        self.arena.register_container(&id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{version::Frontiers, LoroDoc};
+    use loro_common::{ContainerType, LoroError, ID};
+
+    #[test]
+    fn views_coexist_with_live_doc() {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_peer_id(1).unwrap();
+        let text = doc.get_text("text");
+        text.insert_(0, "hello").unwrap();
+        let map = doc.get_map("map");
+        let list = map
+            .insert_container_("list", ContainerType::List)
+            .unwrap()
+            .into_list()
+            .unwrap();
+        list.insert_(0, 1.into()).unwrap();
+        doc.commit_then_renew();
+        let v1 = doc.oplog_frontiers();
+        let v1_value = map.get_deep_value();
+
+        text.insert_(5, " world").unwrap();
+        list.insert_(1, 2.into()).unwrap();
+        doc.get_tree("tree").create_().unwrap();
+        doc.commit_then_renew();
+        let v2 = doc.oplog_frontiers();
+        let v2_value = doc.get_deep_value();
+
+        let view1 = doc.view_at(&v1).unwrap();
+        let view2 = doc.view_at(&v2).unwrap();
+        assert_eq!(view1.frontiers(), &v1);
+        assert_eq!(view1.get_map("map").get_deep_value(), v1_value);
+        assert_eq!(view2.get_deep_value(), v2_value);
+        assert_eq!(
+            view1
+                .get_text("text")
+                .get_value()
+                .as_string()
+                .unwrap()
+                .as_str(),
+            "hello"
+        );
+        assert_eq!(view1.get_tree("tree").nodes().len(), 0);
+        assert_eq!(view2.get_tree("tree").nodes().len(), 1);
+        let view_list = view1.get_map("map").get("list").unwrap();
+        assert_eq!(view_list.into_container().unwrap(), list.id(),);
+        assert_eq!(view1.get_list(list.id()).len(), 1);
+
+        // the doc stays attached and editable
+        assert!(!doc.is_detached());
+        text.insert_(0, "> ").unwrap();
+        doc.commit_then_renew();
+        assert_eq!(
+            text.get_value().as_string().unwrap().as_str(),
+            "> hello world"
+        );
+        assert_eq!(view1.get_map("map").get_deep_value(), v1_value);
+        assert_eq!(view2.get_deep_value(), v2_value);
+
+        // the handlers of the views are read-only
+        assert!(matches!(
+            view1.get_text("text").insert_(0, "x"),
+            Err(LoroError::AutoCommitNotStarted)
+        ));
+        assert!(view2.get_map("map").insert_("key", 1.into()).is_err());
+        assert_eq!(view2.get_deep_value(), v2_value);
+    }
+
+    #[test]
+    fn view_at_unknown_version() {
+        let doc = LoroDoc::new_auto_commit();
+        doc.get_text("text").insert_(0, "abc").unwrap();
+        doc.commit_then_renew();
+        assert!(matches!(
+            doc.view_at(&ID::new(doc.peer_id(), 10).into()),
+            Err(LoroError::NotFoundError(_))
+        ));
+        let view = doc.view_at(&Frontiers::default()).unwrap();
+        assert_eq!(view.get_text("text").len_unicode(), 0);
+    }
+}
@@ -1,21 +1,40 @@
 use fxhash::FxHashMap;
-use loro_common::PeerID;
+use loro_common::{IdSpan, PeerID};
 
 use crate::{change::Change, op::RemoteOp};
 
 pub(crate) type RemoteClientChanges<'a> = FxHashMap<PeerID, Vec<Change<RemoteOp<'a>>>>;
 
+pub(crate) mod codec;
 mod encode_enhanced;
+mod encode_json;
 pub(crate) mod encode_snapshot;
 mod encode_updates;
+pub(crate) mod encrypt;
+
+use std::borrow::Cow;
 
 use rle::HasLength;
 
-use crate::{oplog::OpLog, LoroError, VersionVector};
+use crate::{
+    oplog::{
+        signature::{to_static_change, SignatureStore, SignedSpan},
+        OpLog,
+    },
+    LoroDoc, LoroError, LoroValue, VersionVector,
+};
 
-use self::encode_updates::decode_oplog_updates;
+use self::{
+    codec::{get_codec, CodecId, CompressionCodec, Deflate, NoCompression},
+    encode_enhanced::decode_changes_v2,
+    encode_snapshot::decode_app_snapshot,
+    encode_updates::{decode_oplog_updates, decode_updates},
+    encrypt::{decode_envelope_metadata, encrypt_envelope, Aead, EnvelopeMetadata, KeyId},
+};
 
 pub(crate) use encode_enhanced::{decode_oplog_v2, encode_oplog_v2};
+pub(crate) use encode_json::{decode_oplog_json, encode_oplog_json};
+pub(crate) use encode_snapshot::ContainerStateLoader;
 pub(crate) use encode_updates::encode_oplog_updates;
 
 pub(crate) const COMPRESS_RLE_THRESHOLD: usize = 20 * 1024;
@@ -25,7 +44,260 @@ pub(crate) const UPDATE_ENCODE_THRESHOLD: usize = 32;
 #[cfg(test)]
 pub(crate) const UPDATE_ENCODE_THRESHOLD: usize = 16;
 pub(crate) const MAGIC_BYTES: [u8; 4] = [0x6c, 0x6f, 0x72, 0x6f];
-pub(crate) const ENCODE_SCHEMA_VERSION: u8 = 0;
+/// The schema version used by default when exporting data.
+pub const ENCODE_SCHEMA_VERSION: u8 = 3;
+/// The oldest schema version that can still be imported and exported.
+pub const MIN_ENCODE_SCHEMA_VERSION: u8 = 0;
+const CHECKSUM_LEN: usize = 4;
+/// Used by [EncodeMode::Auto] when the updates are large
+static DEFAULT_DEFLATE: Deflate = Deflate { level: 7 };
+
+type WriteFn = fn(&mut Vec<u8>, EncodeMode, &dyn CompressionCodec, &[u8]) -> Result<(), LoroError>;
+type ReadHeaderFn = fn(&[u8]) -> Result<(EncodeMode, Cow<'_, [u8]>), LoroError>;
+type DecodeOplogFn = fn(&mut OpLog, EncodeMode, &[u8], SignatureStore) -> Result<(), LoroError>;
+type DecodeSnapshotFn = fn(&LoroDoc, &[u8], bool, SignatureStore) -> Result<(), LoroError>;
+type ParsedHeader<'a> = (&'static Schema, EncodeMode, Cow<'a, [u8]>, SignatureStore);
+
+/// Describes how to read and write the data of a schema version.
+///
+/// The encoded data always starts with `MAGIC_BYTES | schema version`.
+/// Everything after that is up to the schema.
+pub(crate) struct Schema {
+    pub(crate) version: u8,
+    /// Whether the payload can be compressed by a [CompressionCodec]
+    supports_codec: bool,
+    /// Whether the payload starts with the signatures of the changes.
+    /// See [crate::oplog::signature]
+    supports_signatures: bool,
+    /// Write the rest of the header and the payload
+    write: WriteFn,
+    /// Read the rest of the header. Return the mode and the decompressed payload
+    read: ReadHeaderFn,
+    pub(crate) decode_oplog: DecodeOplogFn,
+    pub(crate) decode_snapshot: DecodeSnapshotFn,
+}
+
+/// All the schema versions this build can read, starting from [MIN_ENCODE_SCHEMA_VERSION].
+///
+/// When the format evolves, a new schema should be appended here instead of
+/// changing the old ones, so that the data exported by old clients can always
+/// be imported, and data can still be exported for peers that haven't upgraded.
+static SCHEMAS: [Schema; 4] = [
+    // v0: `mode | payload`
+    Schema {
+        version: 0,
+        supports_codec: false,
+        supports_signatures: false,
+        write: |ans, mode, _codec, payload| {
+            ans.push(mode.to_byte());
+            ans.extend_from_slice(payload);
+            Ok(())
+        },
+        read: |input| {
+            let mode: EncodeMode = input[0].try_into()?;
+            Ok((mode, Cow::Borrowed(&input[1..])))
+        },
+        decode_oplog: decode_oplog_payload,
+        decode_snapshot: decode_snapshot_payload,
+    },
+    // v1: `mode | crc32(payload) | payload`
+    Schema {
+        version: 1,
+        supports_codec: false,
+        supports_signatures: false,
+        write: |ans, mode, _codec, payload| {
+            ans.push(mode.to_byte());
+            ans.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+            ans.extend_from_slice(payload);
+            Ok(())
+        },
+        read: |input| {
+            let mode: EncodeMode = input[0].try_into()?;
+            Ok((mode, Cow::Borrowed(verify_checksum(&input[1..])?)))
+        },
+        decode_oplog: decode_oplog_payload,
+        decode_snapshot: decode_snapshot_payload,
+    },
+    // v2: `mode | codec id | crc32(compressed payload) | compressed payload`
+    Schema {
+        version: 2,
+        supports_codec: true,
+        supports_signatures: false,
+        write: write_compressed,
+        read: read_compressed,
+        decode_oplog: decode_oplog_payload,
+        decode_snapshot: decode_snapshot_payload,
+    },
+    // v3: the same as v2, but the payload is `postcard(signatures) | payload`
+    Schema {
+        version: 3,
+        supports_codec: true,
+        supports_signatures: true,
+        write: write_compressed,
+        read: read_compressed,
+        decode_oplog: decode_oplog_payload,
+        decode_snapshot: decode_snapshot_payload,
+    },
+];
+
+/// `mode | codec id | crc32(compressed payload) | compressed payload`
+fn write_compressed(
+    ans: &mut Vec<u8>,
+    mode: EncodeMode,
+    codec: &dyn CompressionCodec,
+    payload: &[u8],
+) -> Result<(), LoroError> {
+    ans.push(mode.to_byte());
+    ans.push(codec.id());
+    let compressed = codec.compress(payload)?;
+    ans.extend_from_slice(&crc32fast::hash(&compressed).to_le_bytes());
+    ans.extend_from_slice(&compressed);
+    Ok(())
+}
+
+fn read_compressed(input: &[u8]) -> Result<(EncodeMode, Cow<'_, [u8]>), LoroError> {
+    let mode: EncodeMode = input[0].try_into()?;
+    let codec = get_codec(input[1])?;
+    let compressed = verify_checksum(&input[2..])?;
+    if codec.id() == NoCompression.id() {
+        return Ok((mode, Cow::Borrowed(compressed)));
+    }
+
+    Ok((mode, Cow::Owned(codec.decompress(compressed)?)))
+}
+
+fn verify_checksum(input: &[u8]) -> Result<&[u8], LoroError> {
+    if input.len() < CHECKSUM_LEN {
+        return Err(LoroError::DecodeError("Invalid import data".into()));
+    }
+
+    let (checksum, payload) = input.split_at(CHECKSUM_LEN);
+    let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+    let found = crc32fast::hash(payload);
+    if expected != found {
+        return Err(LoroError::ChecksumMismatch { expected, found });
+    }
+
+    Ok(payload)
+}
+
+pub(crate) fn get_schema(version: u8) -> Result<&'static Schema, LoroError> {
+    version
+        .checked_sub(MIN_ENCODE_SCHEMA_VERSION)
+        .and_then(|i| SCHEMAS.get(i as usize))
+        .ok_or_else(|| {
+            LoroError::DecodeError(
+                format!("Unsupported schema version {}", version).into_boxed_str(),
+            )
+        })
+}
+
+/// Pick the schema version to use when exporting data for a peer whose newest
+/// supported schema version is `remote_version`.
+///
+/// Every version since v0 can still be exported, so any peer can be served.
+pub fn negotiate_schema_version(remote_version: u8) -> u8 {
+    remote_version.min(ENCODE_SCHEMA_VERSION)
+}
+
+fn get_schema_for_export(version: u8) -> Result<&'static Schema, LoroError> {
+    get_schema(version).map_err(|_| {
+        LoroError::ArgErr(format!("Unsupported schema version {}", version).into_boxed_str())
+    })
+}
+
+/// Prepend the header of the given schema version to the encoded payload.
+///
+/// The payload is compressed by `codec` if it's given.
+/// Only the schemas since v2 support compression codecs.
+///
+/// The `signatures` of the changes in the payload are dropped if the schema doesn't support them.
+pub(crate) fn encode_with_header(
+    mode: EncodeMode,
+    payload: &[u8],
+    version: u8,
+    codec: Option<&dyn CompressionCodec>,
+    signatures: &[SignedSpan],
+) -> Result<Vec<u8>, LoroError> {
+    let schema = get_schema_for_export(version)?;
+    let codec = codec.unwrap_or(&NoCompression);
+    if !schema.supports_codec && codec.id() != NoCompression.id() {
+        return Err(LoroError::ArgErr(
+            format!(
+                "Schema version {} doesn't support compression codecs",
+                version
+            )
+            .into_boxed_str(),
+        ));
+    }
+
+    let payload = if schema.supports_signatures {
+        let mut ans = postcard::to_allocvec(signatures).unwrap();
+        ans.extend_from_slice(payload);
+        Cow::Owned(ans)
+    } else {
+        Cow::Borrowed(payload)
+    };
+    let mut ans = Vec::with_capacity(MAGIC_BYTES.len() + 3 + CHECKSUM_LEN + payload.len());
+    ans.extend_from_slice(&MAGIC_BYTES);
+    ans.push(schema.version);
+    (schema.write)(&mut ans, mode, codec, &payload)?;
+    Ok(ans)
+}
+
+/// Encode the changes that are included in `to` but not in `from` in [EncodeMode::Updates].
+pub(crate) fn encode_updates_between(
+    oplog: &OpLog,
+    from: &VersionVector,
+    to: &VersionVector,
+) -> Vec<u8> {
+    let encoded = encode_oplog_updates(oplog, from, to);
+    encode_with_header(
+        EncodeMode::Updates,
+        &encoded,
+        ENCODE_SCHEMA_VERSION,
+        None,
+        &[],
+    )
+    .unwrap()
+}
+
+/// Parse the header of the encoded data.
+/// Return the schema of the data, the encode mode and the payload.
+///
+/// If the data carries a checksum, it's verified before returning.
+/// If the payload is compressed, it's decompressed before returning.
+/// If the payload carries signatures, they're split from the payload.
+pub(crate) fn parse_header(bytes: &[u8]) -> Result<ParsedHeader<'_>, LoroError> {
+    if bytes.len() <= 6 {
+        return Err(LoroError::DecodeError("Invalid import data".into()));
+    }
+
+    let (magic_bytes, input) = bytes.split_at(4);
+    let magic_bytes: [u8; 4] = magic_bytes.try_into().unwrap();
+    if magic_bytes != MAGIC_BYTES {
+        return Err(LoroError::DecodeError("Invalid header bytes".into()));
+    }
+
+    let schema = get_schema(input[0])?;
+    let (mode, payload) = (schema.read)(&input[1..])?;
+    if !schema.supports_signatures {
+        return Ok((schema, mode, payload, Default::default()));
+    }
+
+    let (spans, rest) = postcard::take_from_bytes::<Vec<SignedSpan>>(&payload)
+        .map_err(|e| LoroError::DecodeError(e.to_string().into_boxed_str()))?;
+    let offset = payload.len() - rest.len();
+    let mut signatures = SignatureStore::default();
+    for span in spans {
+        signatures.insert(span);
+    }
+    let payload = match payload {
+        Cow::Borrowed(payload) => Cow::Borrowed(&payload[offset..]),
+        Cow::Owned(payload) => Cow::Owned(payload[offset..].to_vec()),
+    };
+    Ok((schema, mode, payload, signatures))
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum EncodeMode {
@@ -35,6 +307,8 @@ pub(crate) enum EncodeMode {
     Snapshot = 1,
     RleUpdates = 2,
     CompressedRleUpdates = 3,
+    /// The payload is an encrypted envelope of other exported data
+    Encrypted = 4,
 }
 
 impl EncodeMode {
@@ -45,6 +319,7 @@ impl EncodeMode {
             EncodeMode::Snapshot => 1,
             EncodeMode::RleUpdates => 2,
             EncodeMode::CompressedRleUpdates => 3,
+            EncodeMode::Encrypted => 4,
         }
     }
 }
@@ -58,16 +333,132 @@ impl TryFrom<u8> for EncodeMode {
             1 => Ok(EncodeMode::Snapshot),
             2 => Ok(EncodeMode::RleUpdates),
             3 => Ok(EncodeMode::CompressedRleUpdates),
+            4 => Ok(EncodeMode::Encrypted),
             _ => Err(LoroError::DecodeError("Unknown encode mode".into())),
         }
     }
 }
 
-pub(crate) fn encode_oplog(oplog: &OpLog, vv: &VersionVector, mode: EncodeMode) -> Vec<u8> {
-    let version = ENCODE_SCHEMA_VERSION;
-    let mut ans = Vec::from(MAGIC_BYTES);
-    // maybe u8 is enough
-    ans.push(version);
+/// How the payload of the exported data is encoded. See [HeaderInfo]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadMode {
+    Updates,
+    Snapshot,
+    RleUpdates,
+    CompressedRleUpdates,
+    /// The payload is an encrypted envelope of other exported data, see [read_envelope_metadata]
+    Encrypted,
+}
+
+impl From<EncodeMode> for PayloadMode {
+    fn from(mode: EncodeMode) -> Self {
+        match mode {
+            EncodeMode::Updates => PayloadMode::Updates,
+            EncodeMode::Snapshot => PayloadMode::Snapshot,
+            EncodeMode::RleUpdates => PayloadMode::RleUpdates,
+            EncodeMode::CompressedRleUpdates => PayloadMode::CompressedRleUpdates,
+            EncodeMode::Encrypted => PayloadMode::Encrypted,
+            EncodeMode::Auto => unreachable!(),
+        }
+    }
+}
+
+/// The header of the data exported by [LoroDoc]. See [decode_header_info]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderInfo {
+    pub schema_version: u8,
+    pub mode: PayloadMode,
+    /// It's `None` if the schema doesn't support compression codecs
+    pub codec: Option<CodecId>,
+    /// The CRC32 checksum of the (compressed) payload
+    pub checksum: Option<u32>,
+    /// The size of the decompressed payload, excluding the signatures
+    pub payload_len: usize,
+    /// The spans of the signed changes in the payload
+    pub signed_spans: Vec<IdSpan>,
+}
+
+/// Read the header of the data exported by [LoroDoc] without importing it.
+///
+/// The checksum is verified and the payload is decompressed to get its size.
+pub fn decode_header_info(bytes: &[u8]) -> Result<HeaderInfo, LoroError> {
+    let (schema, mode, payload, signatures) = parse_header(bytes)?;
+    let input = &bytes[MAGIC_BYTES.len() + 1..];
+    let read_checksum =
+        |at: usize| u32::from_le_bytes(input[at..at + CHECKSUM_LEN].try_into().unwrap());
+    let (codec, checksum) = match schema.version {
+        0 => (None, None),
+        1 => (None, Some(read_checksum(1))),
+        _ => (Some(input[1]), Some(read_checksum(2))),
+    };
+    Ok(HeaderInfo {
+        schema_version: schema.version,
+        mode: mode.into(),
+        codec,
+        checksum,
+        payload_len: payload.len(),
+        signed_spans: signatures.id_spans(),
+    })
+}
+
+/// Read the deep value of the state from the snapshot exported by [LoroDoc::export_snapshot],
+/// without building a document. If `root` is given, only the value of that root container
+/// is returned.
+pub fn decode_snapshot_state(bytes: &[u8], root: Option<&str>) -> Result<LoroValue, LoroError> {
+    let (_, mode, payload, _) = parse_header(bytes)?;
+    if mode != EncodeMode::Snapshot {
+        return Err(LoroError::DecodeError(
+            "Invalid encode mode".to_string().into(),
+        ));
+    }
+
+    match root {
+        Some(root) => loro_preload::decode_root_state(&payload, root),
+        None => loro_preload::decode_state(&payload),
+    }
+}
+
+/// Decode the exported updates into changes without importing them into an [OpLog].
+///
+/// The lamports of the changes are not reliable, they should be calculated by the caller.
+pub(crate) fn decode_changes(input: &[u8]) -> Result<Vec<Change<RemoteOp<'static>>>, LoroError> {
+    let (_, mode, decoded, _) = parse_header(input)?;
+    let decode_rle = |input: &[u8]| {
+        decode_changes_v2(input, |changes| {
+            Ok(changes.iter().map(to_static_change).collect())
+        })
+    };
+    match mode {
+        EncodeMode::Updates => Ok(decode_updates(&decoded)?.into_values().flatten().collect()),
+        EncodeMode::RleUpdates => decode_rle(&decoded),
+        EncodeMode::CompressedRleUpdates => miniz_oxide::inflate::decompress_to_vec(&decoded)
+            .map_err(|_| LoroError::DecodeError("Invalid compressed data".into()))
+            .and_then(|bytes| decode_rle(&bytes)),
+        EncodeMode::Snapshot => Err(LoroError::DecodeError(
+            "Snapshot cannot be decoded into changes".into(),
+        )),
+        EncodeMode::Encrypted => Err(LoroError::DecryptError(
+            "Encrypted data can only be imported by LoroDoc with a key provider".into(),
+        )),
+        EncodeMode::Auto => unreachable!(),
+    }
+}
+
+pub(crate) fn encode_oplog(
+    oplog: &OpLog,
+    vv: &VersionVector,
+    mode: EncodeMode,
+    version: u8,
+    codec: Option<&dyn CompressionCodec>,
+) -> Result<Vec<u8>, LoroError> {
+    let schema = get_schema_for_export(version)?;
+    let mut codec = codec;
+    let vv = &if schema.supports_signatures {
+        // export the signed changes as a whole, so that they can be verified
+        oplog.signatures.align_to_span_start(vv)
+    } else {
+        vv.clone()
+    };
     let mode = match mode {
         EncodeMode::Auto => {
             let self_vv = oplog.vv();
@@ -83,6 +474,9 @@ pub(crate) fn encode_oplog(oplog: &OpLog, vv: &VersionVector, mode: EncodeMode)
                 EncodeMode::Updates
             } else if update_total_len <= COMPRESS_RLE_THRESHOLD {
                 EncodeMode::RleUpdates
+            } else if schema.supports_codec {
+                codec = codec.or(Some(&DEFAULT_DEFLATE));
+                EncodeMode::RleUpdates
             } else {
                 EncodeMode::CompressedRleUpdates
             }
@@ -91,7 +485,7 @@ pub(crate) fn encode_oplog(oplog: &OpLog, vv: &VersionVector, mode: EncodeMode)
     };
 
     let encoded = match &mode {
-        EncodeMode::Updates => encode_oplog_updates(oplog, vv),
+        EncodeMode::Updates => encode_oplog_updates(oplog, vv, oplog.vv()),
         EncodeMode::RleUpdates => encode_oplog_v2(oplog, vv),
         EncodeMode::CompressedRleUpdates => {
             let bytes = encode_oplog_v2(oplog, vv);
@@ -99,35 +493,80 @@ pub(crate) fn encode_oplog(oplog: &OpLog, vv: &VersionVector, mode: EncodeMode)
         }
         _ => unreachable!(),
     };
-    ans.push(mode.to_byte());
-    ans.extend(encoded);
-    ans
+    let signatures = if schema.supports_signatures {
+        oplog.signatures.spans_between(vv, oplog.vv())
+    } else {
+        Vec::new()
+    };
+    encode_with_header(mode, &encoded, version, codec, &signatures)
 }
 
 pub(crate) fn decode_oplog(oplog: &mut OpLog, input: &[u8]) -> Result<(), LoroError> {
-    if input.len() < 6 {
-        return Err(LoroError::DecodeError("".into()));
-    }
-
-    let (magic_bytes, input) = input.split_at(4);
-    let magic_bytes: [u8; 4] = magic_bytes.try_into().unwrap();
-    if magic_bytes != MAGIC_BYTES {
-        return Err(LoroError::DecodeError("Invalid header bytes".into()));
-    }
-    let (version, input) = input.split_at(1);
-    if version != [ENCODE_SCHEMA_VERSION] {
-        return Err(LoroError::DecodeError("Invalid version".into()));
-    }
+    let (schema, mode, decoded, signatures) = parse_header(input)?;
+    (schema.decode_oplog)(oplog, mode, &decoded, signatures)
+}
 
-    let mode: EncodeMode = input[0].try_into()?;
-    let decoded = &input[1..];
+/// Decode the payload whose header is already parsed by [parse_header]
+fn decode_oplog_payload(
+    oplog: &mut OpLog,
+    mode: EncodeMode,
+    decoded: &[u8],
+    signatures: SignatureStore,
+) -> Result<(), LoroError> {
     match mode {
-        EncodeMode::Updates => decode_oplog_updates(oplog, decoded),
+        EncodeMode::Updates => decode_oplog_updates(oplog, decoded, signatures),
         EncodeMode::Snapshot => unimplemented!(),
-        EncodeMode::RleUpdates => decode_oplog_v2(oplog, decoded),
+        EncodeMode::RleUpdates => decode_oplog_v2(oplog, decoded, signatures),
         EncodeMode::CompressedRleUpdates => miniz_oxide::inflate::decompress_to_vec(decoded)
             .map_err(|_| LoroError::DecodeError("Invalid compressed data".into()))
-            .and_then(|bytes| decode_oplog_v2(oplog, &bytes)),
+            .and_then(|bytes| decode_oplog_v2(oplog, &bytes, signatures)),
+        EncodeMode::Encrypted => Err(LoroError::DecryptError(
+            "Encrypted data can only be imported by LoroDoc with a key provider".into(),
+        )),
         EncodeMode::Auto => unreachable!(),
     }
 }
+
+/// Decode the snapshot whose header is already parsed by [parse_header]
+fn decode_snapshot_payload(
+    doc: &LoroDoc,
+    decoded: &[u8],
+    with_state: bool,
+    signatures: SignatureStore,
+) -> Result<(), LoroError> {
+    decode_app_snapshot(doc, decoded, with_state)?;
+    doc.oplog().lock().unwrap().signatures.merge(signatures);
+    Ok(())
+}
+
+/// Wrap the exported `data` in an encrypted envelope.
+///
+/// `peer_spans` are the spans of the changes inside `data`. They're kept in
+/// plaintext so that they can be read by [read_envelope_metadata] without the key.
+pub(crate) fn encode_envelope(
+    data: &[u8],
+    key_id: KeyId,
+    aead: &dyn Aead,
+    is_snapshot: bool,
+    peer_spans: &[loro_common::IdSpan],
+) -> Result<Vec<u8>, LoroError> {
+    let payload = encrypt_envelope(data, key_id, aead, is_snapshot, peer_spans)?;
+    encode_with_header(
+        EncodeMode::Encrypted,
+        &payload,
+        ENCODE_SCHEMA_VERSION,
+        None,
+        &[],
+    )
+}
+
+/// Read the plaintext metadata of the encrypted data exported by
+/// [crate::LoroDoc::export_from_encrypted] or [crate::LoroDoc::export_snapshot_encrypted].
+pub fn read_envelope_metadata(bytes: &[u8]) -> Result<EnvelopeMetadata, LoroError> {
+    let (_, mode, payload, _) = parse_header(bytes)?;
+    if mode != EncodeMode::Encrypted {
+        return Err(LoroError::DecodeError("The data is not encrypted".into()));
+    }
+
+    decode_envelope_metadata(&payload)
+}
@@ -0,0 +1,181 @@
+use crate::LoroError;
+
+/// The id of a codec, it's recorded in the header of the encoded data
+/// so that the importer knows how to decompress the payload.
+pub type CodecId = u8;
+
+/// The max length of a decompressed payload, so that corrupted or malicious data
+/// cannot make the importer allocate unbounded memory
+pub(crate) const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A compression codec that can be used when exporting updates or snapshots.
+///
+/// Only the built-in codecs can be decoded, so the trait is sealed and the ids are fixed:
+///
+/// - 0: [NoCompression]
+/// - 1: [Deflate]
+/// - 2: [Lz4] (feature `lz4`)
+/// - 3: [Zstd] (feature `zstd`)
+pub trait CompressionCodec: private::Sealed + Send + Sync {
+    fn id(&self) -> CodecId;
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError>;
+    /// Return error if the data is invalid or larger than [MAX_DECOMPRESSED_LEN] after decompression
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError>;
+}
+
+pub struct NoCompression;
+
+impl private::Sealed for NoCompression {}
+
+impl CompressionCodec for NoCompression {
+    fn id(&self) -> CodecId {
+        0
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Deflate powered by `miniz_oxide`. `level` ranges from 0 to 10.
+pub struct Deflate {
+    pub level: u8,
+}
+
+impl Default for Deflate {
+    fn default() -> Self {
+        Self { level: 7 }
+    }
+}
+
+impl private::Sealed for Deflate {}
+
+impl CompressionCodec for Deflate {
+    fn id(&self) -> CodecId {
+        1
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        Ok(miniz_oxide::deflate::compress_to_vec(data, self.level))
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_DECOMPRESSED_LEN)
+            .map_err(|_| invalid_compressed_data())
+    }
+}
+
+#[cfg(feature = "lz4")]
+pub struct Lz4;
+
+#[cfg(feature = "lz4")]
+impl private::Sealed for Lz4 {}
+
+#[cfg(feature = "lz4")]
+impl CompressionCodec for Lz4 {
+    fn id(&self) -> CodecId {
+        2
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        // the buffer is allocated by the prepended size, so it's checked first
+        let (len, _) =
+            lz4_flex::block::uncompressed_size(data).map_err(|_| invalid_compressed_data())?;
+        if len > MAX_DECOMPRESSED_LEN {
+            return Err(invalid_compressed_data());
+        }
+
+        lz4_flex::decompress_size_prepended(data).map_err(|_| invalid_compressed_data())
+    }
+}
+
+/// Zstandard. `level` ranges from 1 to 22, 0 means the default level of zstd.
+#[cfg(feature = "zstd")]
+pub struct Zstd {
+    pub level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl Default for Zstd {
+    fn default() -> Self {
+        Self { level: 3 }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl private::Sealed for Zstd {}
+
+#[cfg(feature = "zstd")]
+impl CompressionCodec for Zstd {
+    fn id(&self) -> CodecId {
+        3
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        zstd::bulk::compress(data, self.level).map_err(|e| {
+            LoroError::ArgErr(format!("Failed to compress with zstd: {}", e).into_boxed_str())
+        })
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, LoroError> {
+        use std::io::Read;
+
+        let decoder = zstd::stream::Decoder::new(data).map_err(|_| invalid_compressed_data())?;
+        let mut ans = Vec::new();
+        decoder
+            .take(MAX_DECOMPRESSED_LEN as u64 + 1)
+            .read_to_end(&mut ans)
+            .map_err(|_| invalid_compressed_data())?;
+        if ans.len() > MAX_DECOMPRESSED_LEN {
+            return Err(invalid_compressed_data());
+        }
+
+        Ok(ans)
+    }
+}
+
+/// Get the codec to decompress the data encoded with the codec of the given id.
+pub(crate) fn get_codec(id: CodecId) -> Result<&'static dyn CompressionCodec, LoroError> {
+    match id {
+        0 => Ok(&NoCompression),
+        1 => Ok(&Deflate { level: 7 }),
+        #[cfg(feature = "lz4")]
+        2 => Ok(&Lz4),
+        #[cfg(feature = "zstd")]
+        3 => Ok(&Zstd { level: 3 }),
+        #[cfg(not(feature = "lz4"))]
+        2 => Err(feature_not_enabled("lz4")),
+        #[cfg(not(feature = "zstd"))]
+        3 => Err(feature_not_enabled("zstd")),
+        _ => Err(LoroError::DecodeError(
+            format!("Unknown compression codec {}", id).into_boxed_str(),
+        )),
+    }
+}
+
+fn invalid_compressed_data() -> LoroError {
+    LoroError::DecodeError("Invalid compressed data".into())
+}
+
+#[allow(unused)]
+fn feature_not_enabled(name: &str) -> LoroError {
+    LoroError::DecodeError(
+        format!(
+            "The data is compressed with {}, but the feature `{}` is not enabled",
+            name, name
+        )
+        .into_boxed_str(),
+    )
+}
@@ -18,7 +18,7 @@ use crate::{
     },
     id::{Counter, PeerID, ID},
     op::{ListSlice, RawOpContent, RemoteOp},
-    oplog::OpLog,
+    oplog::{signature::SignatureStore, OpLog},
     span::HasId,
     version::Frontiers,
     InternalString, LoroError, LoroValue, VersionVector,
@@ -467,7 +467,22 @@ fn extract_containers(
     (root_containers, container_idx2index, normal_containers)
 }
 
-pub fn decode_oplog_v2(oplog: &mut OpLog, input: &[u8]) -> Result<(), LoroError> {
+pub fn decode_oplog_v2(
+    oplog: &mut OpLog,
+    input: &[u8],
+    signatures: SignatureStore,
+) -> Result<(), LoroError> {
+    decode_changes_v2(input, |changes| import_changes(oplog, changes, signatures))
+}
+
+/// Decode the changes in `input` and pass them to `f`.
+///
+/// The lamports of the changes are not calculated, they are all 0.
+/// The changes borrow the text from the decoded data, so they are only available inside `f`.
+pub(super) fn decode_changes_v2<R>(
+    input: &[u8],
+    f: impl FnOnce(Vec<Change<RemoteOp>>) -> Result<R, LoroError>,
+) -> Result<R, LoroError> {
     let encoded = iter_from_bytes::<DocEncoding>(input)
         .map_err(|e| LoroError::DecodeError(e.to_string().into()))?;
 
@@ -583,7 +598,11 @@ pub fn decode_oplog_v2(oplog: &mut OpLog, input: &[u8]) -> Result<(), LoroError>
                             Kind::Insert => match container_type {
                                 ContainerType::Text => {
                                     let insert_len = insert_del_len as usize;
-                                    let s = &str[str_index..str_index + insert_len];
+                                    let Some(s) = str.get(str_index..str_index + insert_len) else {
+                                        return Err(LoroError::DecodeError(
+                                            "The text of the ops is out of bounds".into(),
+                                        ));
+                                    };
                                     str_index += insert_len;
                                     RawOpContent::List(ListOp::Insert {
                                         slice: ListSlice::from_borrowed_str(s),
@@ -656,11 +675,23 @@ pub fn decode_oplog_v2(oplog: &mut OpLog, input: &[u8]) -> Result<(), LoroError>
             *counter += delta;
             Ok(change)
         })
-        .collect::<Result<Vec<_>, LoroError>>();
-    let changes = match changes {
-        Ok(changes) => changes,
-        Err(err) => return Err(err),
-    };
+        .collect::<Result<Vec<_>, LoroError>>()?;
+    // check the data before the changes are handed to `f`, which may mutate the oplog
+    if str_index != str.len() {
+        return Err(LoroError::DecodeError(
+            "The text of the ops doesn't match the encoded text".into(),
+        ));
+    }
+
+    f(changes)
+}
+
+fn import_changes(
+    oplog: &mut OpLog,
+    changes: Vec<Change<RemoteOp>>,
+    signatures: SignatureStore,
+) -> Result<(), LoroError> {
+    let changes = oplog.verify_remote_changes(changes, signatures)?;
     let mut pending_remote_changes = Vec::new();
     debug_log::debug_dbg!(&changes);
     let mut latest_ids = Vec::new();
@@ -731,6 +762,5 @@ pub fn decode_oplog_v2(oplog: &mut OpLog, input: &[u8]) -> Result<(), LoroError>
     }
 
     oplog.import_unknown_lamport_remote_changes(pending_remote_changes)?;
-    assert_eq!(str_index, str.len());
     Ok(())
 }
@@ -0,0 +1,588 @@
+//! A human-readable JSON encoding of the changes.
+//!
+//! It's lossless, but much larger and slower than the binary encodings. It's meant
+//! for debugging, hand-written test fixtures and diffing histories with ordinary tools.
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "changes": [
+//!     {
+//!       "id": "0@1",
+//!       "lamport": 0,
+//!       "timestamp": 0,
+//!       "deps": [],
+//!       "ops": [
+//!         { "container": "cid:root-text:Text", "counter": 0, "type": "insert_text", "pos": 0, "text": "hi" },
+//!         { "container": "cid:root-map:Map", "counter": 2, "type": "map_set", "key": "a", "value": 1 }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The values are written as plain JSON, except for the ones JSON cannot represent:
+//!
+//! - binary: `{"$binary": "<hex>"}`
+//! - container: `{"$container": "<container id>"}`
+//! - NaN and infinite double: `{"$double": "NaN" | "Infinity" | "-Infinity"}`
+//! - a map with a single key that starts with `$`: `{"$map": {...}}`
+//!
+//! Integers are i32 values and numbers with a fraction or exponent are doubles.
+
+use std::{borrow::Cow, sync::Arc};
+
+use fxhash::FxHashMap;
+use loro_common::{ContainerID, Counter, Lamport, LoroValue, TreeID, ID};
+use rle::{HasLength, RleVec};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    change::{Change, Timestamp},
+    container::{
+        list::list_op::{DeleteSpan, ListOp},
+        map::MapSet,
+        richtext::TextStyleInfoFlag,
+        tree::tree_op::TreeOp,
+    },
+    encoding::RemoteClientChanges,
+    op::{ListSlice, RawOpContent, RemoteOp},
+    oplog::OpLog,
+    version::Frontiers,
+    InternalString, LoroError, VersionVector,
+};
+
+const JSON_SCHEMA_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct JsonChanges {
+    schema_version: u8,
+    changes: Vec<JsonChange>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonChange {
+    id: String,
+    lamport: Lamport,
+    timestamp: Timestamp,
+    deps: Vec<String>,
+    ops: Vec<JsonOp>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonOp {
+    container: String,
+    counter: Counter,
+    #[serde(flatten)]
+    content: JsonOpContent,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonOpContent {
+    Insert {
+        pos: usize,
+        values: Vec<Value>,
+    },
+    InsertText {
+        pos: usize,
+        text: String,
+    },
+    Delete {
+        pos: isize,
+        signed_len: isize,
+    },
+    Mark {
+        start: u32,
+        end: u32,
+        key: String,
+        info: u8,
+        value: Value,
+    },
+    MarkEnd,
+    MapSet {
+        key: String,
+        value: Value,
+    },
+    MapDelete {
+        key: String,
+    },
+    TreeMove {
+        target: String,
+        parent: Option<String>,
+    },
+}
+
+pub(crate) fn encode_oplog_json(oplog: &OpLog, from: &VersionVector) -> String {
+    let mut changes: Vec<_> = oplog
+        .export_changes_from(from)
+        .into_values()
+        .flatten()
+        .collect();
+    // the order of the changes should be deterministic, so that the outputs can be diffed
+    changes.sort_by_key(|c| (c.lamport, c.id.peer));
+    let json = JsonChanges {
+        schema_version: JSON_SCHEMA_VERSION,
+        changes: changes.iter().map(change_to_json).collect(),
+    };
+    serde_json::to_string_pretty(&json).unwrap()
+}
+
+pub(crate) fn decode_oplog_json(oplog: &mut OpLog, json: &str) -> Result<(), LoroError> {
+    let changes = decode_json(json)?;
+    oplog.import_remote_changes(changes, Default::default())
+}
+
+fn decode_json(json: &str) -> Result<RemoteClientChanges<'static>, LoroError> {
+    let json: JsonChanges = serde_json::from_str(json).map_err(decode_err)?;
+    if json.schema_version != JSON_SCHEMA_VERSION {
+        return Err(LoroError::DecodeError(
+            format!(
+                "Unsupported JSON schema version {}, expected {}",
+                json.schema_version, JSON_SCHEMA_VERSION
+            )
+            .into_boxed_str(),
+        ));
+    }
+
+    let mut changes: RemoteClientChanges = FxHashMap::default();
+    for change in json.changes {
+        let change = json_to_change(change)?;
+        changes.entry(change.id.peer).or_default().push(change);
+    }
+    for peer_changes in changes.values_mut() {
+        peer_changes.sort_by_key(|c| c.id.counter);
+    }
+    Ok(changes)
+}
+
+fn change_to_json(change: &Change<RemoteOp>) -> JsonChange {
+    JsonChange {
+        id: change.id.to_string(),
+        lamport: change.lamport,
+        timestamp: change.timestamp,
+        deps: change.deps.iter().map(|x| x.to_string()).collect(),
+        ops: change
+            .ops
+            .iter()
+            .map(|op| JsonOp {
+                container: op.container.to_string(),
+                counter: op.counter,
+                content: op_content_to_json(&op.content),
+            })
+            .collect(),
+    }
+}
+
+fn op_content_to_json(content: &RawOpContent) -> JsonOpContent {
+    match content {
+        RawOpContent::Map(set) => match &set.value {
+            Some(value) => JsonOpContent::MapSet {
+                key: set.key.to_string(),
+                value: value_to_json(value),
+            },
+            None => JsonOpContent::MapDelete {
+                key: set.key.to_string(),
+            },
+        },
+        RawOpContent::List(ListOp::Insert { slice, pos }) => match slice {
+            ListSlice::RawData(values) => JsonOpContent::Insert {
+                pos: *pos,
+                values: values.iter().map(value_to_json).collect(),
+            },
+            ListSlice::RawStr { str, .. } => JsonOpContent::InsertText {
+                pos: *pos,
+                text: str.to_string(),
+            },
+        },
+        RawOpContent::List(ListOp::Delete(span)) => JsonOpContent::Delete {
+            pos: span.pos,
+            signed_len: span.signed_len,
+        },
+        RawOpContent::List(ListOp::StyleStart {
+            start,
+            end,
+            key,
+            info,
+            value,
+        }) => JsonOpContent::Mark {
+            start: *start,
+            end: *end,
+            key: key.to_string(),
+            info: info.to_byte(),
+            value: value_to_json(value),
+        },
+        RawOpContent::List(ListOp::StyleEnd) => JsonOpContent::MarkEnd,
+        RawOpContent::Tree(op) => JsonOpContent::TreeMove {
+            target: op.target.to_string(),
+            parent: op.parent.map(|x| x.to_string()),
+        },
+    }
+}
+
+fn json_to_change(change: JsonChange) -> Result<Change<RemoteOp<'static>>, LoroError> {
+    let id = ID::try_from(change.id.as_str())?;
+    let mut deps = Frontiers::default();
+    for dep in change.deps {
+        deps.push(ID::try_from(dep.as_str())?);
+    }
+
+    let mut ops = RleVec::new();
+    let mut counter = id.counter;
+    for op in change.ops {
+        if op.counter != counter {
+            return Err(LoroError::DecodeError(
+                format!(
+                    "The counter of the op should be {}, but it's {}",
+                    counter, op.counter
+                )
+                .into_boxed_str(),
+            ));
+        }
+
+        let container = ContainerID::try_from(op.container.as_str()).map_err(|_| {
+            LoroError::DecodeError(
+                format!("Invalid container id {}", op.container).into_boxed_str(),
+            )
+        })?;
+        let content = json_to_op_content(op.content)?;
+        counter += content.atom_len() as Counter;
+        ops.push(RemoteOp {
+            counter: op.counter,
+            container,
+            content,
+        });
+    }
+
+    Ok(Change {
+        ops,
+        deps,
+        id,
+        lamport: change.lamport,
+        timestamp: change.timestamp,
+        has_dependents: false,
+    })
+}
+
+fn json_to_op_content(content: JsonOpContent) -> Result<RawOpContent<'static>, LoroError> {
+    Ok(match content {
+        JsonOpContent::Insert { pos, values } => RawOpContent::List(ListOp::Insert {
+            slice: ListSlice::RawData(Cow::Owned(
+                values
+                    .into_iter()
+                    .map(json_to_value)
+                    .collect::<Result<_, _>>()?,
+            )),
+            pos,
+        }),
+        JsonOpContent::InsertText { pos, text } => RawOpContent::List(ListOp::Insert {
+            slice: ListSlice::RawStr {
+                unicode_len: text.chars().count(),
+                str: Cow::Owned(text),
+            },
+            pos,
+        }),
+        JsonOpContent::Delete { pos, signed_len } => {
+            if signed_len == 0 {
+                return Err(LoroError::DecodeError(
+                    "The length of a deletion cannot be 0".into(),
+                ));
+            }
+            RawOpContent::List(ListOp::Delete(DeleteSpan::new(pos, signed_len)))
+        }
+        JsonOpContent::Mark {
+            start,
+            end,
+            key,
+            info,
+            value,
+        } => RawOpContent::List(ListOp::StyleStart {
+            start,
+            end,
+            key: key.into(),
+            info: TextStyleInfoFlag::from_byte(info),
+            value: json_to_value(value)?,
+        }),
+        JsonOpContent::MarkEnd => RawOpContent::List(ListOp::StyleEnd),
+        JsonOpContent::MapSet { key, value } => RawOpContent::Map(MapSet {
+            key: InternalString::from(key.as_str()),
+            value: Some(json_to_value(value)?),
+        }),
+        JsonOpContent::MapDelete { key } => RawOpContent::Map(MapSet {
+            key: InternalString::from(key.as_str()),
+            value: None,
+        }),
+        JsonOpContent::TreeMove { target, parent } => RawOpContent::Tree(TreeOp {
+            target: TreeID::try_from(target.as_str())?,
+            parent: parent.map(|x| TreeID::try_from(x.as_str())).transpose()?,
+        }),
+    })
+}
+
+fn value_to_json(value: &LoroValue) -> Value {
+    match value {
+        LoroValue::Null => Value::Null,
+        LoroValue::Bool(b) => Value::Bool(*b),
+        LoroValue::Double(d) => match serde_json::Number::from_f64(*d) {
+            Some(n) => Value::Number(n),
+            None => tagged(
+                "$double",
+                Value::String(
+                    if d.is_nan() {
+                        "NaN"
+                    } else if *d > 0. {
+                        "Infinity"
+                    } else {
+                        "-Infinity"
+                    }
+                    .into(),
+                ),
+            ),
+        },
+        LoroValue::I32(i) => Value::Number((*i).into()),
+        LoroValue::String(s) => Value::String(s.to_string()),
+        LoroValue::Binary(b) => tagged(
+            "$binary",
+            Value::String(b.iter().map(|x| format!("{:02x}", x)).collect()),
+        ),
+        LoroValue::List(list) => Value::Array(list.iter().map(value_to_json).collect()),
+        LoroValue::Map(map) => {
+            let obj: Map<String, Value> = map
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_json(v)))
+                .collect();
+            if obj.len() == 1 && obj.keys().next().unwrap().starts_with('$') {
+                tagged("$map", Value::Object(obj))
+            } else {
+                Value::Object(obj)
+            }
+        }
+        LoroValue::Container(id) => tagged("$container", Value::String(id.to_string())),
+    }
+}
+
+fn tagged(tag: &str, value: Value) -> Value {
+    let mut obj = Map::new();
+    obj.insert(tag.to_string(), value);
+    Value::Object(obj)
+}
+
+fn json_to_value(value: Value) -> Result<LoroValue, LoroError> {
+    Ok(match value {
+        Value::Null => LoroValue::Null,
+        Value::Bool(b) => LoroValue::Bool(b),
+        Value::Number(n) => {
+            if n.is_f64() {
+                LoroValue::Double(n.as_f64().unwrap())
+            } else {
+                let i = n
+                    .as_i64()
+                    .and_then(|x| i32::try_from(x).ok())
+                    .ok_or_else(|| {
+                        LoroError::DecodeError(
+                            format!("Integer {} is out of the range of i32", n).into_boxed_str(),
+                        )
+                    })?;
+                LoroValue::I32(i)
+            }
+        }
+        Value::String(s) => LoroValue::String(Arc::new(s)),
+        Value::Array(arr) => LoroValue::List(Arc::new(
+            arr.into_iter()
+                .map(json_to_value)
+                .collect::<Result<_, _>>()?,
+        )),
+        Value::Object(obj) => {
+            if obj.len() == 1 && obj.keys().next().unwrap().starts_with('$') {
+                let (tag, value) = obj.into_iter().next().unwrap();
+                return json_tagged_to_value(&tag, value);
+            }
+
+            let mut map = FxHashMap::default();
+            for (k, v) in obj {
+                map.insert(k, json_to_value(v)?);
+            }
+            LoroValue::Map(Arc::new(map))
+        }
+    })
+}
+
+fn json_tagged_to_value(tag: &str, value: Value) -> Result<LoroValue, LoroError> {
+    let invalid = || LoroError::DecodeError(format!("Invalid value of {}", tag).into_boxed_str());
+    Ok(match (tag, value) {
+        ("$double", Value::String(s)) => LoroValue::Double(match s.as_str() {
+            "NaN" => f64::NAN,
+            "Infinity" => f64::INFINITY,
+            "-Infinity" => f64::NEG_INFINITY,
+            _ => return Err(invalid()),
+        }),
+        ("$binary", Value::String(s)) => {
+            if !s.is_ascii() || s.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let bytes = (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| invalid())?;
+            LoroValue::Binary(Arc::new(bytes))
+        }
+        ("$container", Value::String(s)) => {
+            LoroValue::Container(ContainerID::try_from(s.as_str()).map_err(|_| invalid())?)
+        }
+        ("$map", Value::Object(obj)) => {
+            let mut map = FxHashMap::default();
+            for (k, v) in obj {
+                map.insert(k, json_to_value(v)?);
+            }
+            LoroValue::Map(Arc::new(map))
+        }
+        _ => return Err(invalid()),
+    })
+}
+
+fn decode_err(e: serde_json::Error) -> LoroError {
+    LoroError::DecodeError(e.to_string().into_boxed_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{container::richtext::TextStyleInfoFlag, LoroDoc, ToJson};
+    use loro_common::{ContainerType, LoroError};
+
+    #[test]
+    fn value_round_trip() {
+        let mut map = FxHashMap::default();
+        map.insert("$not_a_tag".to_string(), LoroValue::I32(1));
+        let mut map2 = FxHashMap::default();
+        map2.insert("a".to_string(), LoroValue::Double(1.0));
+        map2.insert("$b".to_string(), LoroValue::Null);
+        let values = vec![
+            LoroValue::Null,
+            LoroValue::Bool(true),
+            LoroValue::I32(-3),
+            LoroValue::Double(1.0),
+            LoroValue::Double(-0.5e30),
+            LoroValue::Double(f64::INFINITY),
+            LoroValue::Double(f64::NEG_INFINITY),
+            LoroValue::String(Arc::new("$binary".into())),
+            LoroValue::Binary(Arc::new(vec![0, 1, 255])),
+            LoroValue::List(Arc::new(vec![LoroValue::I32(1), LoroValue::Double(2.)])),
+            LoroValue::Map(Arc::new(map)),
+            LoroValue::Map(Arc::new(map2)),
+            LoroValue::Container(ContainerID::new_root("a", loro_common::ContainerType::Text)),
+        ];
+        for value in values {
+            let json = serde_json::to_string(&value_to_json(&value)).unwrap();
+            let decoded = json_to_value(serde_json::from_str(&json).unwrap()).unwrap();
+            assert_eq!(value, decoded, "{}", json);
+        }
+
+        let json = serde_json::to_string(&value_to_json(&LoroValue::Double(f64::NAN))).unwrap();
+        let decoded = json_to_value(serde_json::from_str(&json).unwrap()).unwrap();
+        assert!(decoded.as_double().unwrap().is_nan());
+    }
+
+    fn edit(doc: &LoroDoc) {
+        let text = doc.get_text("text");
+        text.insert_(0, "hello 你好").unwrap();
+        text.delete_(1, 2).unwrap();
+        text.mark_(0, 3, "bold", true.into(), TextStyleInfoFlag::BOLD)
+            .unwrap();
+        let list = doc.get_list("list");
+        list.push_(LoroValue::Double(1.0)).unwrap();
+        list.push_(LoroValue::I32(1)).unwrap();
+        list.push_(LoroValue::Binary(Arc::new(vec![1, 2, 3])))
+            .unwrap();
+        list.push_(LoroValue::Double(f64::INFINITY)).unwrap();
+        let map = list
+            .insert_container_(0, ContainerType::Map)
+            .unwrap()
+            .into_map()
+            .unwrap();
+        map.insert_("$key", "value".into()).unwrap();
+        map.insert_("null", LoroValue::Null).unwrap();
+        map.delete_("null").unwrap();
+        let tree = doc.get_tree("tree");
+        let root = tree.create_().unwrap();
+        let child = tree.create_and_mov_(root).unwrap();
+        tree.delete_(child).unwrap();
+        doc.commit_then_renew();
+    }
+
+    #[test]
+    fn json_updates_round_trip() {
+        let a = LoroDoc::new_auto_commit();
+        a.set_peer_id(1).unwrap();
+        let b = LoroDoc::new_auto_commit();
+        b.set_peer_id(2).unwrap();
+        edit(&a);
+        b.import(&a.export_from(&Default::default())).unwrap();
+        edit(&b);
+        a.import(&b.export_from(&Default::default())).unwrap();
+
+        let json = a.export_json_updates(&Default::default());
+        let c = LoroDoc::new();
+        c.import_json_updates(&json).unwrap();
+        assert_eq!(a.get_deep_value(), c.get_deep_value());
+        assert_eq!(a.oplog_frontiers(), c.oplog_frontiers());
+        // the json and the binary encoding are lossless
+        assert_eq!(json, c.export_json_updates(&Default::default()));
+        let d = LoroDoc::new();
+        d.import(&c.export_from(&Default::default())).unwrap();
+        assert_eq!(json, d.export_json_updates(&Default::default()));
+
+        // incremental updates
+        let c = LoroDoc::new();
+        c.import(&b.export_from(&Default::default())).unwrap();
+        c.import_json_updates(&a.export_json_updates(&c.oplog_vv()))
+            .unwrap();
+        assert_eq!(a.get_deep_value(), c.get_deep_value());
+    }
+
+    #[test]
+    fn import_handwritten_json() {
+        let json = r#"{
+        "schema_version": 1,
+        "changes": [
+            {
+                "id": "0@A",
+                "lamport": 0,
+                "timestamp": 0,
+                "deps": [],
+                "ops": [
+                    { "container": "cid:root-text:Text", "counter": 0, "type": "insert_text", "pos": 0, "text": "hi" },
+                    { "container": "cid:root-map:Map", "counter": 2, "type": "map_set", "key": "a", "value": {"$binary": "ff00"} },
+                    { "container": "cid:root-list:List", "counter": 3, "type": "insert", "pos": 0, "values": [1, 1.5, null] }
+                ]
+            }
+        ]
+    }"#;
+        let doc = LoroDoc::new();
+        doc.import_json_updates(json).unwrap();
+        assert_eq!(
+            doc.get_deep_value().to_json_value(),
+            serde_json::json!({
+                "text": "hi",
+                "map": {"a": [255, 0]},
+                "list": [1, 1.5, null],
+            })
+        );
+        assert_eq!(
+            doc.get_map("map").get_("a").unwrap().into_value().unwrap(),
+            LoroValue::Binary(Arc::new(vec![255, 0]))
+        );
+
+        // wrong counter
+        let err = LoroDoc::new()
+            .import_json_updates(&json.replace("\"counter\": 2", "\"counter\": 3"))
+            .unwrap_err();
+        assert!(matches!(err, LoroError::DecodeError(_)));
+        let err = LoroDoc::new()
+            .import_json_updates(&json.replace("\"schema_version\": 1", "\"schema_version\": 9"))
+            .unwrap_err();
+        assert!(matches!(err, LoroError::DecodeError(_)));
+    }
+}
@@ -1,18 +1,19 @@
-use std::{borrow::Cow, ops::Deref};
+use std::{borrow::Cow, ops::Deref, sync::Arc};
 
 use fxhash::FxHashMap;
 use itertools::Itertools;
-use loro_common::{ContainerType, HasLamport, TreeID, ID};
+use loro_common::{ContainerID, ContainerType, HasCounterSpan, HasLamport, TreeID, ID};
 use loro_preload::{
-    CommonArena, EncodedAppState, EncodedContainerState, FinalPhase, MapEntry, TempArena,
+    CommonArena, EncodedAppState, EncodedCheckpoint, EncodedCheckpoints, EncodedContainerState,
+    EncodedRichtextState, FinalPhase, MapEntry, TempArena,
 };
-use rle::{HasLength, RleVec};
+use rle::{HasLength, RleVec, Sliceable};
 use serde::{Deserialize, Serialize};
 use serde_columnar::{columnar, to_vec};
 use smallvec::smallvec;
 
 use crate::{
-    change::{Change, Timestamp},
+    change::{Change, Lamport, Timestamp},
     container::{
         idx::ContainerIdx, list::list_op::InnerListOp, map::InnerMapSet,
         richtext::TextStyleInfoFlag, tree::tree_op::TreeOp,
@@ -23,7 +24,7 @@ use crate::{
     state::RichtextState,
     state::TreeState,
     version::Frontiers,
-    InternalString, LoroError, LoroValue,
+    InternalString, LoroError, LoroValue, VersionVector,
 };
 
 use crate::{
@@ -31,16 +32,32 @@ use crate::{
     loro::LoroDoc,
     oplog::OpLog,
     state::{DocState, ListState, MapState, State},
+    utils::lazy::LazyLoad,
 };
 
-pub fn encode_app_snapshot(app: &LoroDoc) -> Vec<u8> {
-    let state = app.app_state().lock().unwrap();
-    let pre_encoded_state = encode_app_state(&state);
-    let f = encode_oplog(&app.oplog().lock().unwrap(), Some(pre_encoded_state));
+/// Encode the snapshot of `app`. The states of its checkpoints are included if `with_checkpoints`,
+/// see [crate::oplog::checkpoint]
+pub fn encode_app_snapshot(app: &LoroDoc, with_checkpoints: bool) -> Vec<u8> {
+    let mut state = app.app_state().lock().unwrap();
+    let pre_encoded_state = encode_app_state(&mut state);
+    let mut oplog = app.oplog().lock().unwrap();
+    let mut f = encode_oplog(&oplog, Some(pre_encoded_state), None);
+    if with_checkpoints {
+        f.checkpoints = Cow::Owned(encode_checkpoints(&mut oplog));
+    }
     // f.diagnose_size();
     f.encode()
 }
 
+/// Encode the given `state` together with the part of the `oplog` that is included in `vv`.
+///
+/// `state` should be the state at version `vv`.
+pub fn encode_snapshot_at(oplog: &OpLog, state: &mut DocState, vv: &VersionVector) -> Vec<u8> {
+    let pre_encoded_state = encode_app_state(state);
+    let f = encode_oplog(oplog, Some(pre_encoded_state), Some(vv));
+    f.encode()
+}
+
 pub fn decode_app_snapshot(app: &LoroDoc, bytes: &[u8], with_state: bool) -> Result<(), LoroError> {
     assert!(app.can_reset_with_snapshot());
     let data = FinalPhase::decode(bytes)?;
@@ -48,11 +65,21 @@ pub fn decode_app_snapshot(app: &LoroDoc, bytes: &[u8], with_state: bool) -> Res
         let mut app_state = app.app_state().lock().unwrap();
         let (state_arena, common) = decode_state(&mut app_state, &data)?;
         let arena = app_state.arena.clone();
+        let mut oplog = app.oplog().lock().unwrap();
+        let container_ids = if oplog.checkpoint_interval().is_some() && !data.checkpoints.is_empty()
+        {
+            common.container_ids.clone()
+        } else {
+            Vec::new()
+        };
         decode_oplog(
-            &mut app.oplog().lock().unwrap(),
+            &mut oplog,
             &data,
-            Some((arena, state_arena, common)),
+            Some((arena.clone(), state_arena, common)),
         )?;
+        if !container_ids.is_empty() {
+            decode_checkpoints(&mut oplog, &arena, &data, &container_ids)?;
+        }
     } else {
         decode_oplog(&mut app.oplog().lock().unwrap(), &data, None)?;
     }
@@ -263,6 +290,13 @@ pub fn decode_state<'b>(
     debug_assert!(arena.can_import_snapshot());
     arena.alloc_str_fast(&state_arena.text);
     let encoded_app_state = EncodedAppState::decode(data)?;
+    let snapshot_arena = Arc::new(SnapshotStateArena {
+        keywords: state_arena.keywords.clone(),
+        values: state_arena.values.clone(),
+        tree_ids: state_arena.tree_ids.clone(),
+        peer_ids: common.peer_ids.to_vec(),
+        arena: arena.clone(),
+    });
     let mut container_states =
         FxHashMap::with_capacity_and_hasher(common.container_ids.len(), Default::default());
     // this part should be moved to encode.rs in preload
@@ -277,81 +311,207 @@ pub fn decode_state<'b>(
         let parent_idx =
             (*parent).map(|x| arena.register_container(&common.container_ids[x as usize]));
         arena.set_parent(idx, parent_idx);
+        container_states.insert(
+            idx,
+            LazyLoad::new(ContainerStateLoader {
+                idx,
+                state: into_owned_state(state),
+                arena: snapshot_arena.clone(),
+            }),
+        );
+    }
+
+    let frontiers = Frontiers::from(&encoded_app_state.frontiers);
+    app_state.init_with_states_and_version(container_states, frontiers);
+    Ok((state_arena, common))
+}
+
+/// Decode the checkpoints of the snapshot into `oplog`, after its state and oplog are decoded.
+///
+/// The text of the checkpoints is already allocated on `arena` by [decode_state].
+fn decode_checkpoints(
+    oplog: &mut OpLog,
+    arena: &SharedArena,
+    data: &FinalPhase,
+    container_ids: &[ContainerID],
+) -> Result<(), LoroError> {
+    for EncodedCheckpoint {
+        app_state,
+        arena: state_arena,
+        peer_ids,
+    } in EncodedCheckpoints::decode(data)?.checkpoints
+    {
+        let snapshot_arena = Arc::new(SnapshotStateArena {
+            keywords: state_arena.keywords,
+            values: state_arena.values,
+            tree_ids: state_arena.tree_ids,
+            peer_ids,
+            arena: arena.clone(),
+        });
+        let mut container_states =
+            FxHashMap::with_capacity_and_hasher(container_ids.len(), Default::default());
+        for (id, state) in container_ids.iter().zip(app_state.states) {
+            let idx = arena.register_container(id);
+            container_states.insert(
+                idx,
+                LazyLoad::new(ContainerStateLoader {
+                    idx,
+                    state: into_owned_state(state),
+                    arena: snapshot_arena.clone(),
+                }),
+            );
+        }
+
+        let frontiers = Frontiers::from(&app_state.frontiers);
+        let Some(vv) = oplog.dag.frontiers_to_vv(&frontiers) else {
+            return Err(LoroError::DecodeError(
+                "Cannot find the version of the checkpoint".into(),
+            ));
+        };
+        let mut state = DocState::new(arena.clone());
+        state.init_with_states_and_version(container_states, frontiers);
+        oplog.checkpoints.insert(vv, state);
+    }
+
+    Ok(())
+}
+
+/// Decode only the state of the snapshot into the empty `app_state`.
+///
+/// The oplog is decoded into a temporary [OpLog] to get the version and the next lamport
+/// of the snapshot, and it's dropped after that.
+pub(crate) fn decode_state_only(
+    app_state: &mut DocState,
+    bytes: &[u8],
+) -> Result<(VersionVector, Lamport), LoroError> {
+    let data = FinalPhase::decode(bytes)?;
+    decode_state(app_state, &data)?;
+    let mut oplog = OpLog::new();
+    decode_oplog(&mut oplog, &data, None)?;
+    let next_lamport = oplog.dag.frontiers_to_next_lamport(&app_state.frontiers);
+    Ok((oplog.vv().clone(), next_lamport))
+}
+
+/// The arenas of a snapshot that are needed to decode the container states
+#[derive(Debug, Default)]
+struct SnapshotStateArena {
+    keywords: Vec<InternalString>,
+    values: Vec<LoroValue>,
+    tree_ids: Vec<(u32, i32)>,
+    peer_ids: Vec<PeerID>,
+    arena: SharedArena,
+}
+
+/// The encoded state of a container in a snapshot.
+/// It's decoded into [State] when the container is accessed for the first time.
+#[derive(Debug, Clone)]
+pub(crate) struct ContainerStateLoader {
+    idx: ContainerIdx,
+    state: EncodedContainerState<'static>,
+    arena: Arc<SnapshotStateArena>,
+}
+
+impl Default for ContainerStateLoader {
+    // it's only used by [LazyLoad] to take the loader out
+    fn default() -> Self {
+        Self {
+            idx: ContainerIdx::from_index_and_type(0, ContainerType::List),
+            state: EncodedContainerState::List(Vec::new()),
+            arena: Default::default(),
+        }
+    }
+}
+
+fn into_owned_state(state: EncodedContainerState) -> EncodedContainerState<'static> {
+    match state {
+        EncodedContainerState::Map(x) => EncodedContainerState::Map(x),
+        EncodedContainerState::List(x) => EncodedContainerState::List(x),
+        EncodedContainerState::Richtext(x) => {
+            let EncodedRichtextState {
+                len,
+                text_bytes,
+                styles,
+                is_style_start,
+            } = *x;
+            EncodedContainerState::Richtext(Box::new(EncodedRichtextState {
+                len,
+                text_bytes: Cow::Owned(text_bytes.into_owned()),
+                styles,
+                is_style_start,
+            }))
+        }
+        EncodedContainerState::Tree(x) => EncodedContainerState::Tree(x),
+    }
+}
+
+impl From<ContainerStateLoader> for State {
+    fn from(ContainerStateLoader { idx, state, arena }: ContainerStateLoader) -> Self {
         match state {
-            loro_preload::EncodedContainerState::Map(map_data) => {
+            EncodedContainerState::Map(map_data) => {
                 let mut map = MapState::new(idx);
                 for entry in map_data.iter() {
                     map.insert(
-                        InternalString::from(&*state_arena.keywords[entry.key]),
+                        InternalString::from(&*arena.keywords[entry.key]),
                         MapValue {
                             counter: entry.counter as Counter,
                             value: if entry.value == 0 {
                                 None
                             } else {
-                                Some(state_arena.values[entry.value - 1].clone())
+                                Some(arena.values[entry.value - 1].clone())
                             },
-                            lamport: (entry.lamport, common.peer_ids[entry.peer as usize]),
+                            lamport: (entry.lamport, arena.peer_ids[entry.peer as usize]),
                         },
                     )
                 }
-                container_states.insert(idx, State::MapState(map));
+                State::MapState(map)
             }
-            loro_preload::EncodedContainerState::List(list_data) => {
+            EncodedContainerState::List(list_data) => {
                 let mut list = ListState::new(idx);
                 list.insert_batch(
                     0,
                     list_data
                         .iter()
-                        .map(|&x| state_arena.values[x].clone())
+                        .map(|&x| arena.values[x].clone())
                         .collect_vec(),
                 );
-                container_states.insert(idx, State::ListState(list));
+                State::ListState(list)
             }
-            loro_preload::EncodedContainerState::Richtext(richtext_data) => {
+            EncodedContainerState::Richtext(richtext_data) => {
                 let mut richtext = RichtextState::new(idx);
-                richtext.decode_snapshot(*richtext_data, &state_arena, &common, &arena);
-                container_states.insert(idx, State::RichtextState(richtext));
+                richtext.decode_snapshot(
+                    *richtext_data,
+                    &arena.keywords,
+                    &arena.peer_ids,
+                    &arena.arena,
+                );
+                State::RichtextState(richtext)
             }
-            loro_preload::EncodedContainerState::Tree((tree_data, deleted)) => {
+            EncodedContainerState::Tree((tree_data, deleted)) => {
                 let mut tree = TreeState::new();
-                for (target, parent) in tree_data {
-                    let (peer, counter) = state_arena.tree_ids[target - 1];
-                    let target_peer = common.peer_ids[peer as usize];
-                    let target = TreeID {
-                        peer: target_peer,
+                let tree_id = |i: usize| {
+                    let (peer, counter) = arena.tree_ids[i - 1];
+                    TreeID {
+                        peer: arena.peer_ids[peer as usize],
                         counter,
-                    };
-
+                    }
+                };
+                for (target, parent) in tree_data {
                     let parent = if parent == Some(0) {
                         TreeID::delete_root()
                     } else {
-                        parent.map(|p| {
-                            let (peer, counter) = state_arena.tree_ids[p - 1];
-                            let peer = common.peer_ids[peer as usize];
-                            TreeID { peer, counter }
-                        })
+                        parent.map(tree_id)
                     };
-                    tree.trees.insert(target, parent);
+                    tree.trees.insert(tree_id(target), parent);
                 }
 
                 for target in deleted {
-                    let (peer, counter) = state_arena.tree_ids[target - 1];
-                    let target_peer = common.peer_ids[peer as usize];
-                    let target = TreeID {
-                        peer: target_peer,
-                        counter,
-                    };
-                    tree.deleted.insert(target);
+                    tree.deleted.insert(tree_id(target));
                 }
 
-                container_states.insert(idx, State::TreeState(tree));
+                State::TreeState(tree)
             }
         }
     }
-
-    let frontiers = Frontiers::from(&encoded_app_state.frontiers);
-    app_state.init_with_states_and_version(container_states, frontiers);
-    Ok((state_arena, common))
 }
 
 type ClientIdx = u32;
@@ -620,8 +780,10 @@ struct PreEncodedState<'a> {
     tree_id_lookup: FxHashMap<(u32, i32), usize>,
 }
 
-fn encode_app_state(app_state: &DocState) -> PreEncodedState {
+fn encode_app_state(app_state: &mut DocState) -> PreEncodedState {
     assert!(!app_state.is_in_txn());
+    app_state.load_all_states();
+    let app_state = &*app_state;
     let mut peers = Vec::new();
     let mut peer_lookup = FxHashMap::default();
     let mut tree_ids = Vec::new();
@@ -691,7 +853,7 @@ fn encode_app_state(app_state: &DocState) -> PreEncodedState {
     let container_ids = app_state.arena.export_containers();
     for (i, id) in container_ids.iter().enumerate() {
         let idx = ContainerIdx::from_index_and_type(i as u32, id.container_type());
-        let Some(state) = app_state.states.get(&idx) else {
+        let Some(state) = app_state.states.get(&idx).map(|x| x.get().unwrap()) else {
             match id.container_type() {
                 loro_common::ContainerType::List => {
                     encoded.states.push(EncodedContainerState::List(Vec::new()))
@@ -792,7 +954,38 @@ fn encode_app_state(app_state: &DocState) -> PreEncodedState {
     }
 }
 
-fn encode_oplog(oplog: &OpLog, state_ref: Option<PreEncodedState>) -> FinalPhase<'static> {
+/// Encode the states of the checkpoints of `oplog`.
+///
+/// Their text is not encoded, it's in the arena text of the snapshot.
+fn encode_checkpoints(oplog: &mut OpLog) -> Vec<u8> {
+    let checkpoints = oplog
+        .checkpoints
+        .states_mut()
+        .map(|state| {
+            let PreEncodedState {
+                common,
+                arena,
+                app_state,
+                ..
+            } = encode_app_state(state);
+            EncodedCheckpoint {
+                app_state,
+                arena: TempArena {
+                    text: Cow::Borrowed(&[]),
+                    ..arena
+                },
+                peer_ids: common.peer_ids.into_owned(),
+            }
+        })
+        .collect();
+    EncodedCheckpoints { checkpoints }.encode()
+}
+
+fn encode_oplog(
+    oplog: &OpLog,
+    state_ref: Option<PreEncodedState>,
+    vv: Option<&VersionVector>,
+) -> FinalPhase<'static> {
     let state_ref = state_ref.unwrap_or_default();
     let PreEncodedState {
         mut common,
@@ -860,11 +1053,20 @@ fn encode_oplog(oplog: &OpLog, state_ref: Option<PreEncodedState>) -> FinalPhase
     };
 
     let mut styles = Vec::new();
-    // Add all changes
-    let mut changes: Vec<&Change> = Vec::with_capacity(oplog.len_changes());
-    for (_, peer_changes) in oplog.changes().iter() {
+    // Add all changes, or only the ones included in `vv` if it's given
+    let mut changes: Vec<Cow<Change>> = Vec::with_capacity(oplog.len_changes());
+    for (peer, peer_changes) in oplog.changes().iter() {
+        let end = vv.map(|vv| vv.get(peer).copied().unwrap_or(0));
         for change in peer_changes.iter() {
-            changes.push(change);
+            match end {
+                Some(end) if change.id.counter >= end => break,
+                Some(end) if change.ctr_end() > end => {
+                    changes.push(Cow::Owned(
+                        change.slice(0, (end - change.id.counter) as usize),
+                    ));
+                }
+                _ => changes.push(Cow::Borrowed(change)),
+            }
         }
     }
 
@@ -1052,6 +1254,7 @@ fn encode_oplog(oplog: &OpLog, state_ref: Option<PreEncodedState>) -> FinalPhase
             .encode(),
         ),
         oplog: Cow::Owned(oplog_encoded.encode()),
+        checkpoints: Cow::Borrowed(&[]),
     };
 
     ans
@@ -1073,6 +1276,7 @@ mod test {
             state_arena: Cow::Owned(vec![255]),
             oplog_extra_arena: Cow::Owned(vec![255]),
             oplog: Cow::Owned(vec![255]),
+            checkpoints: Cow::Owned(vec![255]),
         }
         .encode();
     }
@@ -1136,4 +1340,40 @@ mod test {
         a.import(&bytes).unwrap();
         assert_eq!(a.get_deep_value(), b.get_deep_value());
     }
+
+    #[test]
+    fn container_states_are_decoded_lazily() {
+        let a = LoroDoc::new();
+        a.with_txn(|txn| {
+            a.get_text("text").insert(txn, 0, "hello")?;
+            a.get_map("map").insert(txn, "key", 1.into())?;
+            a.get_list("list").push(txn, "item".into())?;
+            a.get_tree("tree").create(txn)?;
+            Ok(())
+        })
+        .unwrap();
+        let snapshot = a.export_snapshot();
+
+        let b = LoroDoc::from_snapshot(&snapshot).unwrap();
+        let loaded = |doc: &LoroDoc| {
+            doc.app_state()
+                .lock()
+                .unwrap()
+                .states
+                .values()
+                .filter(|x| x.get().is_some())
+                .count()
+        };
+        assert_eq!(loaded(&b), 0);
+        assert_eq!(b.get_map("map").get("key"), Some(1.into()));
+        assert_eq!(loaded(&b), 1);
+        assert_eq!(b.get_deep_value(), a.get_deep_value());
+        // including the meta map of the tree node
+        assert_eq!(loaded(&b), 5);
+
+        // the states are decoded before they are encoded
+        let c = LoroDoc::from_snapshot(&snapshot).unwrap();
+        let d = LoroDoc::from_snapshot(&c.export_snapshot()).unwrap();
+        assert_eq!(d.get_deep_value(), a.get_deep_value());
+    }
 }
@@ -9,7 +9,7 @@ use crate::{
     encoding::RemoteClientChanges,
     id::{Counter, PeerID, ID},
     op::{RawOpContent, RemoteOp},
-    oplog::OpLog,
+    oplog::{signature::SignatureStore, OpLog},
     version::Frontiers,
     LoroError, VersionVector,
 };
@@ -48,8 +48,12 @@ struct EncodedChange {
     pub(crate) timestamp_delta: i64,
 }
 
-pub(crate) fn encode_oplog_updates(oplog: &OpLog, from: &VersionVector) -> Vec<u8> {
-    let changes = oplog.export_changes_from(from);
+pub(crate) fn encode_oplog_updates(
+    oplog: &OpLog,
+    from: &VersionVector,
+    to: &VersionVector,
+) -> Vec<u8> {
+    let changes = oplog.export_changes_between(from, to);
     let mut updates = Updates {
         changes: Vec::with_capacity(changes.len()),
     };
@@ -61,9 +65,13 @@ pub(crate) fn encode_oplog_updates(oplog: &OpLog, from: &VersionVector) -> Vec<u
     postcard::to_allocvec(&updates).unwrap()
 }
 
-pub(crate) fn decode_oplog_updates(oplog: &mut OpLog, updates: &[u8]) -> Result<(), LoroError> {
+pub(crate) fn decode_oplog_updates(
+    oplog: &mut OpLog,
+    updates: &[u8],
+    signatures: SignatureStore,
+) -> Result<(), LoroError> {
     let changes = decode_updates(updates)?;
-    oplog.import_remote_changes(changes)?;
+    oplog.import_remote_changes(changes, signatures)?;
     Ok(())
 }
 
@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use loro_common::{Counter, CounterSpan, IdSpan, PeerID};
+use serde::{Deserialize, Serialize};
+
+use crate::{LoroError, VersionVector};
+
+/// The id of an encryption key. It's stored in plaintext in the envelope,
+/// so the importer can find the key to decrypt the data.
+pub type KeyId = u32;
+
+/// An authenticated encryption algorithm.
+///
+/// The data exported by Loro doesn't depend on any specific cipher,
+/// users should wrap the implementation they trust (AES-GCM, ChaCha20-Poly1305, ...).
+pub trait Aead: Send + Sync {
+    fn nonce_len(&self) -> usize;
+    fn encrypt(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8>;
+    /// Return error if the ciphertext or `aad` is tampered with
+    fn decrypt(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, LoroError>;
+}
+
+/// Used by [crate::LoroDoc::import] to find the key of encrypted data.
+pub trait KeyProvider: Send + Sync {
+    fn get_key(&self, key_id: KeyId) -> Option<Arc<dyn Aead>>;
+}
+
+/// The plaintext metadata of an encrypted envelope.
+///
+/// It can be read without the key, e.g. by relays that route the updates.
+/// It's authenticated together with the ciphertext, so it cannot be tampered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeMetadata {
+    pub key_id: KeyId,
+    pub is_snapshot: bool,
+    /// The spans of the changes inside the envelope
+    pub peer_spans: Vec<IdSpan>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedEnvelopeHeader {
+    key_id: KeyId,
+    nonce: Vec<u8>,
+    is_snapshot: bool,
+    peer_spans: Vec<(PeerID, Counter, Counter)>,
+}
+
+/// Get the spans of the changes that are in `to` but not in `from`.
+pub(crate) fn peer_spans_between(from: &VersionVector, to: &VersionVector) -> Vec<IdSpan> {
+    let mut spans: Vec<IdSpan> = to
+        .diff(from)
+        .left
+        .into_iter()
+        .map(|(peer, span)| IdSpan {
+            client_id: peer,
+            counter: span,
+        })
+        .collect();
+    spans.sort_by_key(|x| x.client_id);
+    spans
+}
+
+/// Encrypt the exported `data` (with its header) into the payload of an envelope.
+///
+/// The payload is `postcard(header) | ciphertext`, the encoded header is used as
+/// the associated data.
+pub(crate) fn encrypt_envelope(
+    data: &[u8],
+    key_id: KeyId,
+    aead: &dyn Aead,
+    is_snapshot: bool,
+    peer_spans: &[IdSpan],
+) -> Result<Vec<u8>, LoroError> {
+    // the nonce must never repeat for a key, so it always comes from the OS
+    // instead of the deterministic generator used in tests
+    let mut nonce = vec![0; aead.nonce_len()];
+    getrandom::getrandom(&mut nonce).map_err(|e| {
+        LoroError::EncryptError(format!("Cannot generate the nonce: {}", e).into_boxed_str())
+    })?;
+    let header = EncodedEnvelopeHeader {
+        key_id,
+        nonce,
+        is_snapshot,
+        peer_spans: peer_spans
+            .iter()
+            .map(|x| (x.client_id, x.counter.start, x.counter.end))
+            .collect(),
+    };
+    let mut ans = postcard::to_allocvec(&header).unwrap();
+    let ciphertext = aead.encrypt(&header.nonce, &ans, data);
+    ans.extend_from_slice(&ciphertext);
+    Ok(ans)
+}
+
+fn decode_envelope_header(payload: &[u8]) -> Result<(EncodedEnvelopeHeader, &[u8]), LoroError> {
+    postcard::take_from_bytes(payload)
+        .map_err(|e| LoroError::DecodeError(e.to_string().into_boxed_str()))
+}
+
+pub(crate) fn decode_envelope_metadata(payload: &[u8]) -> Result<EnvelopeMetadata, LoroError> {
+    let (header, _) = decode_envelope_header(payload)?;
+    Ok(EnvelopeMetadata {
+        key_id: header.key_id,
+        is_snapshot: header.is_snapshot,
+        peer_spans: header
+            .peer_spans
+            .into_iter()
+            .map(|(peer, start, end)| IdSpan {
+                client_id: peer,
+                counter: CounterSpan::new(start, end),
+            })
+            .collect(),
+    })
+}
+
+/// Decrypt the payload of an envelope. Return the exported data inside it.
+pub(crate) fn decrypt_envelope(
+    payload: &[u8],
+    key_provider: Option<&dyn KeyProvider>,
+) -> Result<Vec<u8>, LoroError> {
+    let (header, ciphertext) = decode_envelope_header(payload)?;
+    let Some(key_provider) = key_provider else {
+        return Err(LoroError::DecryptError(
+            "The data is encrypted, but there is no key provider".into(),
+        ));
+    };
+    let Some(aead) = key_provider.get_key(header.key_id) else {
+        return Err(LoroError::DecryptError(
+            format!("Cannot find the key {}", header.key_id).into_boxed_str(),
+        ));
+    };
+
+    let aad = &payload[..payload.len() - ciphertext.len()];
+    aead.decrypt(&header.nonce, aad, ciphertext)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        sync::Arc,
+    };
+
+    use crate::{read_envelope_metadata, Aead, KeyId, KeyProvider, LoroDoc};
+    use loro_common::{CounterSpan, IdSpan, LoroError};
+
+    /// A toy cipher for testing. It must never be used for real data.
+    struct XorAead {
+        key: u8,
+    }
+
+    impl XorAead {
+        fn tag(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> [u8; 8] {
+            let mut hasher = DefaultHasher::new();
+            (self.key, nonce, aad, ciphertext).hash(&mut hasher);
+            hasher.finish().to_le_bytes()
+        }
+    }
+
+    impl Aead for XorAead {
+        fn nonce_len(&self) -> usize {
+            12
+        }
+
+        fn encrypt(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+            let mut ans: Vec<u8> = plaintext
+                .iter()
+                .enumerate()
+                .map(|(i, x)| x ^ self.key ^ nonce[i % nonce.len()])
+                .collect();
+            let tag = self.tag(nonce, aad, &ans);
+            ans.extend_from_slice(&tag);
+            ans
+        }
+
+        fn decrypt(
+            &self,
+            nonce: &[u8],
+            aad: &[u8],
+            ciphertext: &[u8],
+        ) -> Result<Vec<u8>, LoroError> {
+            let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - 8);
+            if self.tag(nonce, aad, ciphertext) != tag {
+                return Err(LoroError::DecryptError("Authentication failed".into()));
+            }
+
+            Ok(ciphertext
+                .iter()
+                .enumerate()
+                .map(|(i, x)| x ^ self.key ^ nonce[i % nonce.len()])
+                .collect())
+        }
+    }
+
+    struct Keys(Vec<(KeyId, u8)>);
+
+    impl KeyProvider for Keys {
+        fn get_key(&self, key_id: KeyId) -> Option<Arc<dyn Aead>> {
+            self.0
+                .iter()
+                .find(|(id, _)| *id == key_id)
+                .map(|(_, key)| Arc::new(XorAead { key: *key }) as Arc<dyn Aead>)
+        }
+    }
+
+    #[test]
+    fn encrypted_updates() {
+        let a = LoroDoc::new_auto_commit();
+        a.set_peer_id(1).unwrap();
+        a.get_text("text").insert_(0, "hello world").unwrap();
+        a.commit_then_renew();
+        let v = a.oplog_vv();
+        a.get_text("text").insert_(0, "hi ").unwrap();
+        a.commit_then_renew();
+
+        let aead = XorAead { key: 42 };
+        let all = a
+            .export_from_encrypted(&Default::default(), 7, &aead)
+            .unwrap();
+        assert!(!all.windows(5).any(|x| x == b"hello"));
+        // every export uses a new nonce
+        assert_ne!(
+            all,
+            a.export_from_encrypted(&Default::default(), 7, &aead)
+                .unwrap()
+        );
+        let meta = read_envelope_metadata(&all).unwrap();
+        assert_eq!(meta.key_id, 7);
+        assert!(!meta.is_snapshot);
+        assert_eq!(
+            meta.peer_spans,
+            vec![IdSpan {
+                client_id: 1,
+                counter: CounterSpan::new(0, 14)
+            }]
+        );
+        let partial = a.export_from_encrypted(&v, 7, &aead).unwrap();
+        assert_eq!(
+            read_envelope_metadata(&partial).unwrap().peer_spans,
+            vec![IdSpan {
+                client_id: 1,
+                counter: CounterSpan::new(11, 14)
+            }]
+        );
+        let err = read_envelope_metadata(&a.export_from(&Default::default())).unwrap_err();
+        assert!(matches!(err, LoroError::DecodeError(_)));
+
+        // no key provider
+        let err = LoroDoc::new().import(&all).unwrap_err();
+        assert!(matches!(err, LoroError::DecryptError(_)));
+        // unknown key
+        let b = LoroDoc::new();
+        b.set_key_provider(Arc::new(Keys(vec![(8, 42)])));
+        let err = b.import(&all).unwrap_err();
+        assert!(matches!(err, LoroError::DecryptError(_)));
+        // wrong key
+        let b = LoroDoc::new();
+        b.set_key_provider(Arc::new(Keys(vec![(7, 41)])));
+        let err = b.import(&all).unwrap_err();
+        assert!(matches!(err, LoroError::DecryptError(_)));
+
+        let b = LoroDoc::new();
+        b.set_key_provider(Arc::new(Keys(vec![(7, 42)])));
+        b.import(&all).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+    }
+
+    #[test]
+    fn encrypted_snapshot() {
+        let a = LoroDoc::new_auto_commit();
+        a.set_peer_id(1).unwrap();
+        a.get_text("text").insert_(0, "hello world").unwrap();
+        a.get_map("map").insert_("key", "value".into()).unwrap();
+        a.commit_then_renew();
+
+        let snapshot = a.export_snapshot_encrypted(1, &XorAead { key: 1 }).unwrap();
+        let meta = read_envelope_metadata(&snapshot).unwrap();
+        assert!(meta.is_snapshot);
+        assert_eq!(
+            meta.peer_spans,
+            vec![IdSpan {
+                client_id: 1,
+                counter: CounterSpan::new(0, 12)
+            }]
+        );
+
+        let b = LoroDoc::new();
+        b.set_key_provider(Arc::new(Keys(vec![(1, 1)])));
+        b.import(&snapshot).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+        assert_eq!(a.oplog_frontiers(), b.oplog_frontiers());
+    }
+}
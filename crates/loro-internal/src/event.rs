@@ -254,6 +254,9 @@ impl Diff {
                 for (k, v) in b.updated {
                     a = a.with_entry(k, v);
                 }
+                for (k, v) in b.old_values {
+                    a.old_values.entry(k).or_insert(v);
+                }
                 Diff::NewMap(a)
             }
 
@@ -3,14 +3,18 @@ use crate::{
     container::{
         idx::ContainerIdx,
         list::list_op::{DeleteSpan, ListOp},
-        richtext::TextStyleInfoFlag,
+        richtext::{TextChange, TextStyleInfoFlag},
         tree::tree_op::TreeOp,
     },
-    delta::{MapValue, TreeDiffItem, TreeExternalDiff},
+    delta::{DeltaItem, MapValue, StyleMeta, TreeDiffItem, TreeExternalDiff},
+    event::Diff,
     op::ListSlice,
+    oplog::TextBlame,
     state::RichtextState,
     txn::EventHint,
     utils::utf16::count_utf16_len,
+    version::Frontiers,
+    OpLog,
 };
 use enum_as_inner::EnumAsInner;
 use fxhash::FxHashMap;
@@ -122,7 +126,7 @@ impl Handler {
 }
 
 impl Handler {
-    fn new(
+    pub(crate) fn new(
         txn: Weak<Mutex<Option<Transaction>>>,
         idx: ContainerIdx,
         state: Weak<Mutex<DocState>>,
@@ -134,6 +138,139 @@ impl Handler {
             ContainerType::Text => Self::Text(TextHandler::new(txn, idx, state)),
         }
     }
+
+    pub fn id(&self) -> ContainerID {
+        match self {
+            Self::Map(x) => x.id(),
+            Self::List(x) => x.id(),
+            Self::Text(x) => x.id(),
+            Self::Tree(x) => x.id(),
+        }
+    }
+
+    /// Apply the external `diff` of a container as local edits.
+    ///
+    /// `container_remap` maps the ids of the containers in the diffs to the ids of the
+    /// containers in this doc. The containers created here are added to it, so that
+    /// the diffs of their children can be applied to them later.
+    pub(crate) fn apply_diff(
+        &self,
+        txn: &mut Transaction,
+        diff: &Diff,
+        container_remap: &mut FxHashMap<ContainerID, ContainerID>,
+    ) -> LoroResult<()> {
+        match (self, diff) {
+            (Self::Text(x), Diff::Text(delta)) => {
+                let delta: Vec<TextDelta> = delta
+                    .iter()
+                    .map(|item| {
+                        let attributes = |meta: &StyleMeta| {
+                            let attr: FxHashMap<String, LoroValue> = meta
+                                .iter()
+                                .map(|(_, style)| (style.key.to_string(), style.data))
+                                .collect();
+                            (!attr.is_empty()).then_some(attr)
+                        };
+                        match item {
+                            DeltaItem::Retain {
+                                retain,
+                                attributes: meta,
+                            } => TextDelta::Retain {
+                                retain: *retain,
+                                attributes: attributes(meta),
+                            },
+                            DeltaItem::Insert {
+                                insert,
+                                attributes: meta,
+                            } => TextDelta::Insert {
+                                insert: insert.as_str().to_string(),
+                                attributes: attributes(meta),
+                            },
+                            DeltaItem::Delete { delete, .. } => {
+                                TextDelta::Delete { delete: *delete }
+                            }
+                        }
+                    })
+                    .collect();
+                x.apply_delta(txn, &delta)
+            }
+            (Self::List(x), Diff::List(delta)) => {
+                let mut index = 0;
+                for item in delta.iter() {
+                    match item {
+                        DeltaItem::Retain { retain, .. } => {
+                            index += retain;
+                        }
+                        DeltaItem::Insert { insert, .. } => {
+                            for value in insert.iter() {
+                                if let LoroValue::Container(id) = value {
+                                    let child =
+                                        x.insert_container(txn, index, id.container_type())?;
+                                    container_remap.insert(id.clone(), child.id());
+                                } else {
+                                    x.insert(txn, index, value.clone())?;
+                                }
+                                index += 1;
+                            }
+                        }
+                        DeltaItem::Delete { delete, .. } => {
+                            x.delete(txn, index, *delete)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            (Self::Map(x), Diff::NewMap(delta)) => {
+                for (key, value) in delta.updated.iter() {
+                    match &value.value {
+                        Some(LoroValue::Container(id)) => {
+                            let child = x.insert_container(txn, key, id.container_type())?;
+                            container_remap.insert(id.clone(), child.id());
+                        }
+                        Some(value) => x.insert(txn, key, value.clone())?,
+                        None => x.delete(txn, key)?,
+                    }
+                }
+                Ok(())
+            }
+            (Self::Tree(x), Diff::Tree(tree)) => {
+                let remap = |id: TreeID, container_remap: &FxHashMap<_, _>| match container_remap
+                    .get(&id.associated_meta_container())
+                {
+                    Some(ContainerID::Normal { peer, counter, .. }) => TreeID {
+                        peer: *peer,
+                        counter: *counter,
+                    },
+                    _ => id,
+                };
+                for item in tree.diff.iter() {
+                    let target = remap(item.target, container_remap);
+                    match item.action {
+                        TreeExternalDiff::Create => {
+                            let new_target = x.create(txn)?;
+                            container_remap.insert(
+                                item.target.associated_meta_container(),
+                                new_target.associated_meta_container(),
+                            );
+                        }
+                        TreeExternalDiff::Move(Some(parent)) => {
+                            x.mov(txn, target, remap(parent, container_remap))?
+                        }
+                        TreeExternalDiff::Move(None) => x.as_root(txn, target)?,
+                        TreeExternalDiff::Delete => x.delete(txn, target)?,
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(LoroError::ArgErr(
+                format!(
+                    "The diff doesn't match the type of the container {}",
+                    self.id()
+                )
+                .into_boxed_str(),
+            )),
+        }
+    }
 }
 
 #[derive(Clone, EnumAsInner, Debug)]
@@ -271,6 +408,55 @@ impl TextHandler {
         })
     }
 
+    /// Get the author of each run of text in the current version of the state.
+    ///
+    /// The handler doesn't own the history, so the [OpLog] of the doc is required.
+    /// Ranges are in event index, see [TextHandler::insert].
+    ///
+    /// The text is replayed from the empty version on every call, so the cost grows with
+    /// the whole history of the doc. Keep the result if it's needed more than once.
+    pub fn blame(&self, oplog: &OpLog) -> LoroResult<Vec<TextBlame>> {
+        let frontiers = self
+            .state
+            .upgrade()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .frontiers
+            .clone();
+        self.blame_at(oplog, &frontiers)
+    }
+
+    /// Get the author of each run of text at the given version
+    pub fn blame_at(&self, oplog: &OpLog, frontiers: &Frontiers) -> LoroResult<Vec<TextBlame>> {
+        let Some(vv) = oplog.dag.frontiers_to_vv(frontiers) else {
+            return Err(LoroError::NotFoundError(
+                format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
+            ));
+        };
+
+        Ok(oplog.blame_text(self.container_idx, &vv))
+    }
+
+    /// Get the inserted, deleted and unchanged text between two versions in the document order,
+    /// with the ops that produced them.
+    pub fn changes_between(
+        &self,
+        oplog: &OpLog,
+        from: &Frontiers,
+        to: &Frontiers,
+    ) -> LoroResult<Vec<TextChange>> {
+        let vv = |frontiers: &Frontiers| {
+            oplog.dag.frontiers_to_vv(frontiers).ok_or_else(|| {
+                LoroError::NotFoundError(
+                    format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
+                )
+            })
+        };
+
+        Ok(oplog.text_changes_between(self.container_idx, &vv(from)?, &vv(to)?))
+    }
+
     /// `pos` is a Event Index:
     ///
     /// - if feature="wasm", pos is a UTF-16 index
@@ -533,9 +719,16 @@ impl TextHandler {
             }
         }
 
+        let config = self
+            .state
+            .upgrade()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .text_style_config
+            .clone();
         for (start, end, key, value) in marks {
-            // FIXME: allow users to set a config table to store the flag, so that we can use it directly
-            self.mark(txn, start, end, key, value, TextStyleInfoFlag::BOLD)?;
+            self.mark(txn, start, end, key, value, config.get(key))?;
         }
 
         Ok(())
@@ -683,7 +876,7 @@ impl ListHandler {
 
     pub fn get_child_handler(&self, index: usize) -> Handler {
         let mutex = &self.state.upgrade().unwrap();
-        let state = mutex.lock().unwrap();
+        let mut state = mutex.lock().unwrap();
         let container_id = state.with_state(self.container_idx, |state| {
             state
                 .as_list_state()
@@ -767,13 +960,14 @@ impl ListHandler {
     /// Get value at given index, if it's a container, return a handler to the container
     pub fn get_(&self, index: usize) -> Option<ValueOrContainer> {
         let mutex = &self.state.upgrade().unwrap();
-        let doc_state = &mutex.lock().unwrap();
+        let mut doc_state = mutex.lock().unwrap();
+        let arena = doc_state.arena.clone();
         doc_state.with_state(self.container_idx, |state| {
             let a = state.as_list_state().unwrap();
             match a.get(index) {
                 Some(v) => {
                     if let LoroValue::Container(id) = v {
-                        let idx = doc_state.arena.register_container(id);
+                        let idx = arena.register_container(id);
                         Some(ValueOrContainer::Container(Handler::new(
                             self.txn.clone(),
                             idx,
@@ -830,7 +1024,8 @@ impl MapHandler {
             return Ok(());
         }
 
-        if self.get(key).map(|x| x == value).unwrap_or(false) {
+        let old_value = self.get(key);
+        if old_value.as_ref().map(|x| x == &value).unwrap_or(false) {
             // skip if the value is already set
             return Ok(());
         }
@@ -844,6 +1039,7 @@ impl MapHandler {
             EventHint::Map {
                 key: key.into(),
                 value: Some(value.clone()),
+                old_value,
             },
             &self.state,
         )
@@ -859,6 +1055,7 @@ impl MapHandler {
         key: &str,
         c_type: ContainerType,
     ) -> LoroResult<Handler> {
+        let old_value = self.get(key);
         let id = txn.next_id();
         let container_id = ContainerID::new_normal(id, c_type);
         let child_idx = txn.arena.register_container(&container_id);
@@ -872,6 +1069,7 @@ impl MapHandler {
             EventHint::Map {
                 key: key.into(),
                 value: Some(LoroValue::Container(container_id)),
+                old_value,
             },
             &self.state,
         )?;
@@ -888,6 +1086,7 @@ impl MapHandler {
     }
 
     pub fn delete(&self, txn: &mut Transaction, key: &str) -> LoroResult<()> {
+        let old_value = self.get(key);
         txn.apply_local_op(
             self.container_idx,
             crate::op::RawOpContent::Map(crate::container::map::MapSet {
@@ -897,6 +1096,7 @@ impl MapHandler {
             EventHint::Map {
                 key: key.into(),
                 value: None,
+                old_value,
             },
             &self.state,
         )
@@ -930,7 +1130,7 @@ impl MapHandler {
 
     pub fn get_child_handler(&self, key: &str) -> Handler {
         let mutex = &self.state.upgrade().unwrap();
-        let state = mutex.lock().unwrap();
+        let mut state = mutex.lock().unwrap();
         let container_id = state.with_state(self.container_idx, |state| {
             state
                 .as_map_state()
@@ -979,13 +1179,14 @@ impl MapHandler {
     /// Get the value at given key, if value is a container, return a handler to the container
     pub fn get_(&self, key: &str) -> Option<ValueOrContainer> {
         let mutex = &self.state.upgrade().unwrap();
-        let doc_state = mutex.lock().unwrap();
+        let mut doc_state = mutex.lock().unwrap();
+        let arena = doc_state.arena.clone();
         doc_state.with_state(self.container_idx, |state| {
             let a = state.as_map_state().unwrap();
             let value = a.get(key);
             match value {
                 Some(LoroValue::Container(container_id)) => {
-                    let idx = doc_state.arena.register_container(container_id);
+                    let idx = arena.register_container(container_id);
                     Some(ValueOrContainer::Container(Handler::new(
                         self.txn.clone(),
                         idx,
@@ -7,17 +7,27 @@
 #![warn(rustdoc::broken_intra_doc_links)]
 
 pub mod arena;
+pub mod awareness;
 pub mod diff_calc;
+pub mod doc_view;
 pub mod handler;
+pub use awareness::Awareness;
+pub use doc_view::DocView;
 pub use event::{ContainerDiff, DiffEvent, DocDiff};
 pub use handler::{ListHandler, MapHandler, TextHandler, TreeHandler};
 pub use loro::LoroDoc;
+pub use oplog::signature::{ChangeSigner, PublicKey, TrustStore, UntrustedChangePolicy};
 pub use oplog::OpLog;
 pub use state::DocState;
+pub use state_only::StateOnlyDoc;
+pub use suggestion::{Suggestion, SuggestionBranch};
+pub use version_tag::VersionTag;
 pub mod loro;
 pub mod obs;
 pub mod oplog;
 mod state;
+pub mod state_only;
+pub mod suggestion;
 pub mod txn;
 
 pub mod change;
@@ -25,9 +35,20 @@ pub mod configure;
 pub mod container;
 pub mod dag;
 mod encoding;
+#[cfg(feature = "lz4")]
+pub use encoding::codec::Lz4;
+#[cfg(feature = "zstd")]
+pub use encoding::codec::Zstd;
+pub use encoding::codec::{CodecId, CompressionCodec, Deflate, NoCompression};
+pub use encoding::encrypt::{Aead, EnvelopeMetadata, KeyId, KeyProvider};
+pub use encoding::{
+    decode_header_info, decode_snapshot_state, negotiate_schema_version, read_envelope_metadata,
+    HeaderInfo, PayloadMode, ENCODE_SCHEMA_VERSION, MIN_ENCODE_SCHEMA_VERSION,
+};
 pub mod id;
 pub mod op;
 pub mod version;
+pub mod version_tag;
 
 mod error;
 #[cfg(feature = "test_utils")]
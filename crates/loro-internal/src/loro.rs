@@ -4,26 +4,38 @@ use std::{
     sync::{Arc, Mutex, Weak},
 };
 
-use loro_common::{ContainerID, ContainerType, LoroResult, LoroValue};
+use fxhash::FxHashMap;
+use loro_common::{ContainerID, ContainerType, IdSpan, LoroResult, LoroValue, ID};
 
 use crate::{
     arena::SharedArena,
     change::Timestamp,
-    container::{idx::ContainerIdx, IntoContainerId},
-    encoding::{EncodeMode, ENCODE_SCHEMA_VERSION, MAGIC_BYTES},
-    handler::TextHandler,
+    container::{idx::ContainerIdx, richtext::StyleConfigMap, IntoContainerId},
+    encoding::{
+        codec::CompressionCodec,
+        decode_oplog_json, encode_envelope, encode_oplog_json, encode_with_header,
+        encrypt::{decrypt_envelope, peer_spans_between, Aead, KeyId, KeyProvider},
+        parse_header, EncodeMode, ENCODE_SCHEMA_VERSION,
+    },
     handler::TreeHandler,
+    handler::{Handler, TextHandler},
     id::PeerID,
+    suggestion::SuggestionBranch,
     version::Frontiers,
+    version_tag::{frontiers_to_value, VersionTag, VERSION_TAGS_CONTAINER},
     InternalString, LoroError, VersionVector,
 };
 
 use super::{
     diff_calc::DiffCalculator,
-    encoding::encode_snapshot::{decode_app_snapshot, encode_app_snapshot},
-    event::InternalDocDiff,
-    obs::{Observer, SubID, Subscriber},
-    oplog::OpLog,
+    doc_view::DocView,
+    encoding::encode_snapshot::{encode_app_snapshot, encode_snapshot_at},
+    event::{ContainerDiff, InternalDocDiff},
+    obs::{LocalUpdateCallback, Observer, SubID, Subscriber},
+    oplog::{
+        signature::{ChangeSigner, TrustStore},
+        OpLog,
+    },
     state::DocState,
     txn::Transaction,
     ListHandler, MapHandler,
@@ -56,6 +68,9 @@ pub struct LoroDoc {
     txn: Arc<Mutex<Option<Transaction>>>,
     auto_commit: bool,
     detached: bool,
+    /// Whether the detached state can be edited, see [LoroDoc::set_detached_editing]
+    detached_editing: bool,
+    key_provider: Mutex<Option<Arc<dyn KeyProvider>>>,
 }
 
 impl Default for LoroDoc {
@@ -74,11 +89,13 @@ impl LoroDoc {
             oplog: Arc::new(Mutex::new(oplog)),
             state,
             detached: false,
+            detached_editing: false,
             auto_commit: false,
             observer: Arc::new(Observer::new(arena.clone())),
             diff_calculator: Arc::new(Mutex::new(DiffCalculator::new())),
             txn: Arc::new(Mutex::new(None)),
             arena,
+            key_provider: Mutex::new(None),
         }
     }
 
@@ -92,10 +109,11 @@ impl LoroDoc {
 
     pub fn from_snapshot(bytes: &[u8]) -> LoroResult<Self> {
         let doc = Self::new();
-        let (input, mode) = parse_encode_header(bytes)?;
+        let (schema, mode, input, _) = parse_header(bytes)?;
         match mode {
             EncodeMode::Snapshot => {
-                decode_app_snapshot(&doc, input, true)?;
+                // the signatures can't be verified without a trust store, so they're dropped
+                (schema.decode_snapshot)(&doc, &input, true, Default::default())?;
                 Ok(doc)
             }
             _ => Err(LoroError::DecodeError(
@@ -104,6 +122,31 @@ impl LoroDoc {
         }
     }
 
+    /// Set the provider of the keys to decrypt the imported data.
+    pub fn set_key_provider(&self, key_provider: Arc<dyn KeyProvider>) {
+        *self.key_provider.lock().unwrap() = Some(key_provider);
+    }
+
+    /// Sign the local changes with `signer`. See [crate::oplog::signature]
+    pub fn set_change_signer(&self, signer: Arc<dyn ChangeSigner>) {
+        self.oplog.lock().unwrap().set_change_signer(Some(signer));
+    }
+
+    /// Only import the remote changes that are signed by the keys in the trust store.
+    /// See [crate::oplog::signature]
+    pub fn set_trust_store(&self, trust_store: TrustStore) {
+        self.oplog
+            .lock()
+            .unwrap()
+            .set_trust_store(Some(trust_store));
+    }
+
+    /// The spans of the remote changes that are quarantined by the trust store.
+    /// See [crate::UntrustedChangePolicy::Quarantine]
+    pub fn quarantined_changes(&self) -> Vec<IdSpan> {
+        self.oplog.lock().unwrap().quarantined_changes()
+    }
+
     /// Is the document empty? (no ops)
     #[inline(always)]
     pub fn can_reset_with_snapshot(&self) -> bool {
@@ -128,6 +171,8 @@ impl LoroDoc {
             diff_calculator: Arc::new(Mutex::new(DiffCalculator::new())),
             txn: Arc::new(Mutex::new(None)),
             detached: false,
+            detached_editing: false,
+            key_provider: Mutex::new(None),
         }
     }
 
@@ -136,8 +181,15 @@ impl LoroDoc {
         self.state.lock().unwrap().peer
     }
 
+    /// Set the peer id of the doc. [PeerID::MAX] is reserved, see [loro_common::VERSION_TAGS_CONTAINER]
     #[inline(always)]
     pub fn set_peer_id(&self, peer: PeerID) -> LoroResult<()> {
+        if peer == PeerID::MAX {
+            return Err(LoroError::ArgErr(
+                "PeerID::MAX is reserved".to_string().into_boxed_str(),
+            ));
+        }
+
         if self.auto_commit {
             let mut doc_state = self.state.lock().unwrap();
             doc_state.peer = peer;
@@ -170,6 +222,51 @@ impl LoroDoc {
         Ok(())
     }
 
+    /// Enable or disable editing the detached state.
+    ///
+    /// When it's enabled, the changes committed on the detached state depend on the
+    /// checked out version. They form a concurrent branch in the history, which is merged
+    /// with the other changes when the doc is attached again.
+    ///
+    /// The changes of a peer must be sequential, so the peer id is replaced by a random
+    /// one when the checked out version doesn't include all the changes of the current peer,
+    /// even if it was set by [LoroDoc::set_peer_id]. Use [LoroDoc::peer_id] to get the new one.
+    pub fn set_detached_editing(&mut self, enable: bool) {
+        self.detached_editing = enable;
+        if !self.detached {
+            return;
+        }
+
+        if enable {
+            self.refresh_peer_id_if_needed();
+            self.renew_txn_if_auto_commit();
+        } else {
+            self.commit_then_stop();
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_detached_editing_enabled(&self) -> bool {
+        self.detached_editing
+    }
+
+    /// Replace the peer id if the state doesn't include all the changes of the current peer,
+    /// so that the new changes of the peer are still sequential
+    fn refresh_peer_id_if_needed(&self) {
+        let oplog = self.oplog.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        let end = oplog.vv().get(&state.peer).copied().unwrap_or(0);
+        if end > 0
+            && !oplog
+                .dag
+                .frontiers_to_vv(&state.frontiers)
+                .unwrap()
+                .includes_id(ID::new(state.peer, end - 1))
+        {
+            state.refresh_peer_id();
+        }
+    }
+
     #[inline(always)]
     pub fn detach(&mut self) {
         self.detached = true;
@@ -202,7 +299,7 @@ impl LoroDoc {
     where
         F: FnOnce(&mut Transaction) -> LoroResult<R>,
     {
-        let mut txn = self.txn().unwrap();
+        let mut txn = self.txn()?;
         let v = f(&mut txn)?;
         txn.commit()?;
         Ok(v)
@@ -211,7 +308,7 @@ impl LoroDoc {
     pub fn start_auto_commit(&mut self) {
         self.auto_commit = true;
         let mut self_txn = self.txn.try_lock().unwrap();
-        if self_txn.is_some() || self.detached {
+        if self_txn.is_some() || (self.detached && !self.detached_editing) {
             return;
         }
 
@@ -267,7 +364,7 @@ impl LoroDoc {
         txn.commit().unwrap();
         if immediate_renew {
             let mut txn_guard = self.txn.try_lock().unwrap();
-            assert!(!self.detached);
+            assert!(!self.detached || self.detached_editing);
             *txn_guard = Some(self.txn().unwrap());
         }
 
@@ -288,7 +385,7 @@ impl LoroDoc {
     }
 
     pub fn renew_txn_if_auto_commit(&self) {
-        if self.auto_commit && !self.detached {
+        if self.auto_commit && (!self.detached || self.detached_editing) {
             let mut self_txn = self.txn.try_lock().unwrap();
             if self_txn.is_some() {
                 return;
@@ -308,7 +405,7 @@ impl LoroDoc {
     /// The origin will be propagated to the events.
     /// There can only be one active transaction at a time for a [LoroDoc].
     pub fn txn_with_origin(&self, origin: &str) -> Result<Transaction, LoroError> {
-        if self.is_detached() {
+        if self.is_detached() && !self.detached_editing {
             return Err(LoroError::TransactionError(
                 String::from("LoroDoc is in detached mode. OpLog and AppState are using different version. So it's readonly.").into_boxed_str(),
             ));
@@ -322,6 +419,8 @@ impl LoroDoc {
         );
 
         let obs = self.observer.clone();
+        let oplog = self.oplog.clone();
+        let start = txn.next_id();
         txn.set_on_commit(Box::new(move |state| {
             let mut state = state.try_lock().unwrap();
             let events = state.take_events();
@@ -329,6 +428,18 @@ impl LoroDoc {
             for event in events {
                 obs.emit(event);
             }
+
+            if obs.has_local_update_subscribers() {
+                let oplog = oplog.lock().unwrap();
+                if oplog.vv().get(&start.peer).copied().unwrap_or(0) > start.counter {
+                    // only export the change of this txn, excluding the imported ones
+                    let mut vv = oplog.vv().clone();
+                    vv.insert(start.peer, start.counter);
+                    let bytes = oplog.export_from(&vv);
+                    drop(oplog);
+                    obs.emit_local_update(&bytes);
+                }
+            }
         }));
 
         Ok(txn)
@@ -356,6 +467,59 @@ impl LoroDoc {
         ans
     }
 
+    /// Same as [LoroDoc::export_from], but encode the data with the given schema version,
+    /// so that it can be imported by peers that only support older versions.
+    ///
+    /// See [crate::negotiate_schema_version]
+    pub fn export_from_with_schema(
+        &self,
+        vv: &VersionVector,
+        schema_version: u8,
+    ) -> LoroResult<Vec<u8>> {
+        self.commit_then_stop();
+        let ans = self
+            .oplog
+            .lock()
+            .unwrap()
+            .export_from_with_schema(vv, schema_version);
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
+    /// Same as [LoroDoc::export_from], but the data is encrypted by `aead`.
+    ///
+    /// The spans of the exported changes are kept in plaintext,
+    /// they can be read by [crate::read_envelope_metadata].
+    /// The importer should register the key by [LoroDoc::set_key_provider].
+    ///
+    /// It fails if the nonce cannot be drawn from the system's random source.
+    pub fn export_from_encrypted(
+        &self,
+        vv: &VersionVector,
+        key_id: KeyId,
+        aead: &dyn Aead,
+    ) -> LoroResult<Vec<u8>> {
+        self.commit_then_stop();
+        let oplog = self.oplog.lock().unwrap();
+        let data = oplog.export_from(vv);
+        let spans = peer_spans_between(vv, oplog.vv());
+        drop(oplog);
+        self.renew_txn_if_auto_commit();
+        encode_envelope(&data, key_id, aead, false, &spans)
+    }
+
+    /// Same as [LoroDoc::export_from], but compress the data with the given codec.
+    pub fn export_from_with_codec(
+        &self,
+        vv: &VersionVector,
+        codec: &dyn CompressionCodec,
+    ) -> LoroResult<Vec<u8>> {
+        self.commit_then_stop();
+        let ans = self.oplog.lock().unwrap().export_from_with_codec(vv, codec);
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
     #[inline(always)]
     pub fn import(&self, bytes: &[u8]) -> Result<(), LoroError> {
         self.import_with(bytes, Default::default())
@@ -381,42 +545,22 @@ impl LoroDoc {
         bytes: &[u8],
         origin: string_cache::Atom<string_cache::EmptyStaticAtomSet>,
     ) -> Result<(), LoroError> {
-        let (input, mode) = parse_encode_header(bytes)?;
+        let (schema, mode, input, signatures) = parse_header(bytes)?;
         match mode {
             EncodeMode::Updates | EncodeMode::RleUpdates | EncodeMode::CompressedRleUpdates => {
-                // TODO: need to throw error if state is in transaction
-                debug_log::group!("import to {}", self.peer_id());
-                let mut oplog = self.oplog.lock().unwrap();
-                let old_vv = oplog.vv().clone();
-                let old_frontiers = oplog.frontiers().clone();
-                oplog.decode(bytes)?;
-                if !self.detached {
-                    let mut diff = DiffCalculator::default();
-                    let diff = diff.calc_diff_internal(
-                        &oplog,
-                        &old_vv,
-                        Some(&old_frontiers),
-                        oplog.vv(),
-                        Some(oplog.dag.get_frontiers()),
-                    );
-                    let mut state = self.state.lock().unwrap();
-                    state.apply_diff(InternalDocDiff {
-                        origin,
-                        local: false,
-                        diff: (diff).into(),
-                        from_checkout: false,
-                        new_version: Cow::Owned(oplog.frontiers().clone()),
-                    });
-                }
-
-                debug_log::group_end!();
+                self.import_to_oplog(origin, |oplog| {
+                    (schema.decode_oplog)(oplog, mode, &input, signatures)
+                })?;
             }
             EncodeMode::Snapshot => {
-                if self.can_reset_with_snapshot() {
-                    decode_app_snapshot(self, input, !self.detached)?;
+                // the changes from the snapshot need to be verified by `import_remote_changes`
+                // if there is a trust store. Otherwise the signatures can't be verified,
+                // so they're dropped
+                if self.can_reset_with_snapshot() && !self.oplog.lock().unwrap().has_trust_store() {
+                    (schema.decode_snapshot)(self, &input, !self.detached, Default::default())?;
                 } else {
                     let app = LoroDoc::new();
-                    decode_app_snapshot(&app, input, false)?;
+                    (schema.decode_snapshot)(&app, &input, false, signatures)?;
                     let oplog = self.oplog.lock().unwrap();
                     // TODO: PERF: the ser and de can be optimized out
                     let updates = app.export_from(oplog.vv());
@@ -424,12 +568,73 @@ impl LoroDoc {
                     return self.import_with(&updates, origin);
                 }
             }
+            EncodeMode::Encrypted => {
+                let key_provider = self.key_provider.lock().unwrap().clone();
+                let data = decrypt_envelope(&input, key_provider.as_deref())?;
+                return self._import_with(&data, origin);
+            }
             EncodeMode::Auto => unreachable!(),
         };
         self.emit_events();
         Ok(())
     }
 
+    /// Import the changes with `f`, then apply the diff to the state
+    fn import_to_oplog(
+        &self,
+        origin: InternalString,
+        f: impl FnOnce(&mut OpLog) -> Result<(), LoroError>,
+    ) -> Result<(), LoroError> {
+        // TODO: need to throw error if state is in transaction
+        debug_log::group!("import to {}", self.peer_id());
+        let mut oplog = self.oplog.lock().unwrap();
+        let old_vv = oplog.vv().clone();
+        let old_frontiers = oplog.frontiers().clone();
+        f(&mut oplog)?;
+        if !self.detached {
+            let mut diff = DiffCalculator::default();
+            let diff = diff.calc_diff_internal(
+                &oplog,
+                &old_vv,
+                Some(&old_frontiers),
+                oplog.vv(),
+                Some(oplog.dag.get_frontiers()),
+            );
+            let mut state = self.state.lock().unwrap();
+            state.apply_diff(InternalDocDiff {
+                origin,
+                local: false,
+                diff: (diff).into(),
+                from_checkout: false,
+                new_version: Cow::Owned(oplog.frontiers().clone()),
+            });
+        }
+
+        debug_log::group_end!();
+        Ok(())
+    }
+
+    /// Export the changes since `vv` as human-readable JSON.
+    ///
+    /// It's lossless but much larger than [LoroDoc::export_from]. It's useful for
+    /// debugging and writing test fixtures. The signatures of the changes are not included.
+    pub fn export_json_updates(&self, vv: &VersionVector) -> String {
+        self.commit_then_stop();
+        let ans = encode_oplog_json(&self.oplog.lock().unwrap(), vv);
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
+    /// Import the changes exported by [LoroDoc::export_json_updates].
+    pub fn import_json_updates(&self, json: &str) -> Result<(), LoroError> {
+        self.commit_then_stop();
+        let ans = self
+            .import_to_oplog(Default::default(), |oplog| decode_oplog_json(oplog, json))
+            .map(|_| self.emit_events());
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
     fn emit_events(&self) {
         let events = self.state.lock().unwrap().take_events();
         for event in events {
@@ -438,19 +643,116 @@ impl LoroDoc {
     }
 
     pub fn export_snapshot(&self) -> Vec<u8> {
+        self.export_snapshot_with_schema(ENCODE_SCHEMA_VERSION)
+            .unwrap()
+    }
+
+    /// Same as [LoroDoc::export_snapshot], but encode the data with the given schema version.
+    ///
+    /// See [crate::negotiate_schema_version]
+    pub fn export_snapshot_with_schema(&self, schema_version: u8) -> LoroResult<Vec<u8>> {
+        self._export_snapshot(schema_version, None, false)
+    }
+
+    /// Same as [LoroDoc::export_snapshot], but compress the data with the given codec.
+    ///
+    /// It's useful when the snapshot is sent over the network to cold-starting clients.
+    pub fn export_snapshot_with_codec(&self, codec: &dyn CompressionCodec) -> LoroResult<Vec<u8>> {
+        self._export_snapshot(ENCODE_SCHEMA_VERSION, Some(codec), false)
+    }
+
+    /// Same as [LoroDoc::export_snapshot], but the cached checkpoints are included,
+    /// so that the doc importing it doesn't need to rebuild them.
+    ///
+    /// They're only loaded when the snapshot is imported into an empty doc whose checkpoints
+    /// are enabled by [LoroDoc::set_checkpoint_interval]. Other docs ignore them.
+    pub fn export_snapshot_with_checkpoints(&self) -> LoroResult<Vec<u8>> {
+        self._export_snapshot(ENCODE_SCHEMA_VERSION, None, true)
+    }
+
+    /// Same as [LoroDoc::export_snapshot], but the data is encrypted by `aead`.
+    ///
+    /// See [LoroDoc::export_from_encrypted]
+    pub fn export_snapshot_encrypted(&self, key_id: KeyId, aead: &dyn Aead) -> LoroResult<Vec<u8>> {
+        let data = self.export_snapshot();
+        let spans = peer_spans_between(&Default::default(), &self.oplog_vv());
+        encode_envelope(&data, key_id, aead, true, &spans)
+    }
+
+    fn _export_snapshot(
+        &self,
+        schema_version: u8,
+        codec: Option<&dyn CompressionCodec>,
+        with_checkpoints: bool,
+    ) -> LoroResult<Vec<u8>> {
         self.commit_then_stop();
         debug_log::group!("export snapshot");
-        let version = ENCODE_SCHEMA_VERSION;
-        let mut ans = Vec::from(MAGIC_BYTES);
-        // maybe u8 is enough
-        ans.push(version);
-        ans.push((EncodeMode::Snapshot).to_byte());
-        ans.extend(encode_app_snapshot(self));
+        let signatures = {
+            let oplog = self.oplog.lock().unwrap();
+            oplog
+                .signatures
+                .spans_between(&Default::default(), oplog.vv())
+        };
+        let ans = encode_with_header(
+            EncodeMode::Snapshot,
+            &encode_app_snapshot(self, with_checkpoints),
+            schema_version,
+            codec,
+            &signatures,
+        );
         debug_log::group_end!();
         self.renew_txn_if_auto_commit();
         ans
     }
 
+    /// Export a snapshot whose state and history stop at the given version.
+    ///
+    /// The state at `frontiers` is calculated on a scratch [DocState] with its own
+    /// [DiffCalculator], so neither the state nor the cached diffs of this doc are affected.
+    pub fn export_snapshot_at(&self, frontiers: &Frontiers) -> LoroResult<Vec<u8>> {
+        self.commit_then_stop();
+        let ans = self._export_snapshot_at(frontiers);
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
+    fn _export_snapshot_at(&self, frontiers: &Frontiers) -> LoroResult<Vec<u8>> {
+        debug_log::group!("export snapshot at {:?}", frontiers);
+        let oplog = self.oplog.lock().unwrap();
+        let Some(vv) = oplog.dag.frontiers_to_vv(frontiers) else {
+            debug_log::group_end!();
+            return Err(LoroError::NotFoundError(
+                format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
+            ));
+        };
+
+        let mut state = DocState::new(self.arena.clone());
+        let diff = DiffCalculator::new().calc_diff_internal(
+            &oplog,
+            &Default::default(),
+            Some(&Default::default()),
+            &vv,
+            Some(frontiers),
+        );
+        state.apply_diff(InternalDocDiff {
+            origin: "checkout".into(),
+            local: true,
+            diff: Cow::Owned(diff),
+            from_checkout: true,
+            new_version: Cow::Owned(frontiers.clone()),
+        });
+
+        let ans = encode_with_header(
+            EncodeMode::Snapshot,
+            &encode_snapshot_at(&oplog, &mut state, &vv),
+            ENCODE_SCHEMA_VERSION,
+            None,
+            &oplog.signatures.spans_between(&Default::default(), &vv),
+        );
+        debug_log::group_end!();
+        ans
+    }
+
     /// Get the version vector of the current OpLog
     #[inline]
     pub fn oplog_vv(&self) -> VersionVector {
@@ -518,6 +820,74 @@ impl LoroDoc {
         self.state.lock().unwrap().frontiers.clone()
     }
 
+    /// Tag the latest version with `name`. The tag with the same name is replaced.
+    ///
+    /// The tags are replicated with the doc, see [crate::version_tag]
+    pub fn tag(&self, name: &str) -> LoroResult<()> {
+        self.commit_then_stop();
+        let frontiers = self.oplog_frontiers();
+        self.set_tag(name, Some(frontiers_to_value(&frontiers)))
+    }
+
+    /// Tag the given version with `name`. The tag with the same name is replaced.
+    pub fn tag_at(&self, name: &str, frontiers: &Frontiers) -> LoroResult<()> {
+        if self.frontiers_to_vv(frontiers).is_none() {
+            return Err(LoroError::NotFoundError(
+                format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
+            ));
+        }
+
+        self.set_tag(name, Some(frontiers_to_value(frontiers)))
+    }
+
+    pub fn delete_tag(&self, name: &str) -> LoroResult<()> {
+        self.set_tag(name, None)
+    }
+
+    fn set_tag(&self, name: &str, value: Option<LoroValue>) -> LoroResult<()> {
+        self.commit_then_stop();
+        let ans = self.txn().and_then(|mut txn| {
+            txn.write_version_tags = true;
+            let map = self.get_map(VERSION_TAGS_CONTAINER);
+            match value {
+                Some(value) => map.insert(&mut txn, name, value)?,
+                None => map.delete(&mut txn, name)?,
+            }
+            txn.commit()
+        });
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
+    /// All the version tags of the latest version, sorted by their timestamps
+    pub fn tags(&self) -> Vec<VersionTag> {
+        self.oplog.lock().unwrap().version_tags().tags()
+    }
+
+    pub fn get_tag(&self, name: &str) -> Option<VersionTag> {
+        self.oplog.lock().unwrap().version_tags().get(name)
+    }
+
+    /// Checkout the state to the version of the tag.
+    ///
+    /// The doc is detached afterwards, see [LoroDoc::checkout]
+    pub fn checkout_tag(&mut self, name: &str) -> LoroResult<()> {
+        let Some(tag) = self.get_tag(name) else {
+            return Err(LoroError::NotFoundError(
+                format!("Cannot find the tag {}", name).into_boxed_str(),
+            ));
+        };
+
+        self.checkout(&tag.frontiers)
+    }
+
+    /// Create a suggestion branch from the current version of the state.
+    ///
+    /// See [SuggestionBranch]
+    pub fn create_suggestion(&self, name: impl Into<String>) -> LoroResult<SuggestionBranch> {
+        SuggestionBranch::new(self, name.into())
+    }
+
     /// - Ordering::Less means self is less than target or parallel
     /// - Ordering::Equal means versions equal
     /// - Ordering::Greater means self's version is greater than target
@@ -535,6 +905,14 @@ impl LoroDoc {
         self.observer.subscribe_root(callback)
     }
 
+    /// Subscribe the updates of the local changes. The callback is called after each
+    /// commit with the update of exactly that change, encoded the same as [LoroDoc::export_from].
+    ///
+    /// It can be removed by [LoroDoc::unsubscribe].
+    pub fn subscribe_local_update(&self, callback: LocalUpdateCallback) -> SubID {
+        self.observer.subscribe_local_update(callback)
+    }
+
     pub fn subscribe(&self, container_id: &ContainerID, callback: Subscriber) -> SubID {
         let mut state = self.state.lock().unwrap();
         if !state.is_recording() {
@@ -596,19 +974,60 @@ impl LoroDoc {
 
     pub fn checkout_to_latest(&mut self) {
         let f = self.oplog_frontiers();
-        self.checkout(&f).unwrap();
+        self.checkout_without_renew(&f).unwrap();
         self.detached = false;
         self.renew_txn_if_auto_commit();
     }
 
+    /// Cache the states of the checked out versions every `interval` ops at least, so that
+    /// checking out a distant version only replays the ops after the nearest checkpoint.
+    ///
+    /// The checkpoints are not used while the doc has subscribers. They can be persisted by
+    /// [LoroDoc::export_snapshot_with_checkpoints]. See [crate::oplog::checkpoint]
+    #[inline]
+    pub fn set_checkpoint_interval(&self, interval: Option<usize>) {
+        self.oplog.lock().unwrap().set_checkpoint_interval(interval);
+    }
+
+    /// Keep at most `max` checkpoints, the least recently used ones are evicted.
+    /// See [LoroDoc::set_checkpoint_interval]
+    #[inline]
+    pub fn set_max_checkpoints(&self, max: usize) {
+        self.oplog.lock().unwrap().set_max_checkpoints(max);
+    }
+
+    /// Get a read-only view of the doc at a specific version.
+    ///
+    /// Unlike [LoroDoc::checkout], the view has its own [DocState], so the doc stays attached
+    /// and editable. The view doesn't follow the later changes of the doc.
+    pub fn view_at(&self, frontiers: &Frontiers) -> LoroResult<DocView> {
+        DocView::new(self.oplog.clone(), self.arena.clone(), frontiers)
+    }
+
     /// Checkout [DocState] to a specific version.
     ///
     /// This will make the current [DocState] detached from the latest version of [OpLog].
     /// Any further import will not be reflected on the [DocState], until user call [LoroDoc::attach()]
+    ///
+    /// The detached state is read-only unless [LoroDoc::set_detached_editing] is enabled.
+    /// If it's enabled and the version doesn't include all the changes of the current peer,
+    /// the peer id is replaced by a random one, even if it was set by [LoroDoc::set_peer_id].
+    /// Use [LoroDoc::peer_id] to get the new one.
     pub fn checkout(&mut self, frontiers: &Frontiers) -> LoroResult<()> {
+        self.checkout_without_renew(frontiers)?;
+        if self.detached_editing {
+            self.refresh_peer_id_if_needed();
+            self.renew_txn_if_auto_commit();
+        }
+
+        Ok(())
+    }
+
+    fn checkout_without_renew(&mut self, frontiers: &Frontiers) -> LoroResult<()> {
         self.commit_then_stop();
-        let oplog = self.oplog.lock().unwrap();
+        let mut oplog = self.oplog.lock().unwrap();
         let mut state = self.state.lock().unwrap();
+        let was_attached = !self.detached;
         self.detached = true;
         let mut calc = self.diff_calculator.lock().unwrap();
         let before = &oplog.dag.frontiers_to_vv(&state.frontiers).unwrap();
@@ -617,13 +1036,31 @@ impl LoroDoc {
                 format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
             ));
         };
-        let diff = calc.calc_diff_internal(
-            &oplog,
-            before,
-            Some(&state.frontiers),
-            after,
-            Some(frontiers),
-        );
+        if was_attached {
+            // the latest state was built by the imported and local changes,
+            // which are counted from the nearest checkpoint
+            let replayed = match oplog.checkpoints.nearest(before) {
+                Some((_, distance)) => distance,
+                None => before.distance_to(&Default::default()),
+            };
+            oplog.checkpoints.record(before, &state, replayed);
+        }
+        // Starting from a checkpoint skips the events of the checkout,
+        // so it's only used when there is no subscriber
+        let replay_len = calc.replay_len(before, after);
+        let checkpoint = oplog
+            .checkpoints
+            .nearest(after)
+            .filter(|(_, len)| !state.is_recording() && *len < replay_len);
+        let (from, from_frontiers, replayed) = if let Some((index, len)) = checkpoint {
+            let checkpoint = oplog.checkpoints.use_checkpoint(index);
+            state.replace_with(&checkpoint.state);
+            (checkpoint.vv.clone(), checkpoint.frontiers.clone(), len)
+        } else {
+            (before.clone(), state.frontiers.clone(), replay_len)
+        };
+        let diff =
+            calc.calc_diff_internal(&oplog, &from, Some(&from_frontiers), after, Some(frontiers));
         state.apply_diff(InternalDocDiff {
             origin: "checkout".into(),
             local: true,
@@ -631,6 +1068,7 @@ impl LoroDoc {
             from_checkout: true,
             new_version: Cow::Owned(frontiers.clone()),
         });
+        oplog.checkpoints.record(after, &state, replayed);
         let events = state.take_events();
         for event in events {
             self.observer.emit(event);
@@ -638,6 +1076,138 @@ impl LoroDoc {
         Ok(())
     }
 
+    /// Discard the changes that are not included in `vv`, and bring the state to the new
+    /// latest version. The doc is attached afterwards.
+    ///
+    /// The history is rebuilt on the same arena, so the handlers, the subscriptions and
+    /// the config of the doc are kept. The changes of the state are emitted like a checkout.
+    /// If some changes of the current peer are discarded, the peer id is replaced by a random one.
+    pub(crate) fn truncate(&mut self, vv: &VersionVector) -> LoroResult<()> {
+        let frontiers = self.oplog.lock().unwrap().dag.vv_to_frontiers(vv);
+        self.checkout_without_renew(&frontiers)?;
+        self.refresh_peer_id_if_needed();
+        let mut oplog = self.oplog.lock().unwrap();
+        let truncated = oplog.truncated(vv);
+        let ans = truncated.map(|truncated| {
+            *oplog = truncated;
+            *self.diff_calculator.lock().unwrap() = DiffCalculator::new();
+            self.detached = false;
+        });
+        drop(oplog);
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
+    /// Calculate the diff that brings the doc from version `from` to version `to`.
+    ///
+    /// The diff is calculated on a scratch state at `from`, so the state of the doc is
+    /// untouched and no event is emitted. The paths of the returned diffs are the paths
+    /// of the containers at version `to`.
+    pub fn diff(&self, from: &Frontiers, to: &Frontiers) -> LoroResult<Vec<ContainerDiff>> {
+        self.commit_then_stop();
+        let ans = self.diff_inner(from, to);
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
+    fn diff_inner(&self, from: &Frontiers, to: &Frontiers) -> LoroResult<Vec<ContainerDiff>> {
+        let oplog = self.oplog.lock().unwrap();
+        let to_vv = |frontiers: &Frontiers| {
+            oplog.dag.frontiers_to_vv(frontiers).ok_or_else(|| {
+                LoroError::NotFoundError(
+                    format!("Cannot find the specified version {:?}", frontiers).into_boxed_str(),
+                )
+            })
+        };
+        let from_vv = to_vv(from)?;
+        let to_vv = to_vv(to)?;
+        let mut calc = DiffCalculator::new();
+        let mut state = DocState::new(self.arena.clone());
+        let diff = calc.calc_diff_internal(
+            &oplog,
+            &Default::default(),
+            Some(&Default::default()),
+            &from_vv,
+            Some(from),
+        );
+        state.apply_diff(InternalDocDiff {
+            origin: "diff".into(),
+            local: true,
+            diff: Cow::Owned(diff),
+            from_checkout: true,
+            new_version: Cow::Owned(from.clone()),
+        });
+
+        state.start_recording();
+        let diff = calc.calc_diff_internal(&oplog, &from_vv, Some(from), &to_vv, Some(to));
+        state.apply_diff(InternalDocDiff {
+            origin: "diff".into(),
+            local: true,
+            diff: Cow::Owned(diff),
+            from_checkout: true,
+            new_version: Cow::Owned(to.clone()),
+        });
+        Ok(state
+            .take_events()
+            .into_iter()
+            .flat_map(|event| event.diff)
+            .collect())
+    }
+
+    /// Set the flags of the text styles that are applied by their keys,
+    /// e.g. by [LoroDoc::apply_diff] and [TextHandler::apply_delta].
+    #[inline]
+    pub fn config_text_style(&self, config: StyleConfigMap) {
+        self.state.lock().unwrap().text_style_config = config;
+    }
+
+    #[inline]
+    pub fn text_style_config(&self) -> StyleConfigMap {
+        self.state.lock().unwrap().text_style_config.clone()
+    }
+
+    /// Apply the diffs, e.g. the diffs from [LoroDoc::diff] or the events of another doc,
+    /// to this doc as local edits in a new transaction.
+    ///
+    /// The containers created by the diffs get new ids in this doc, and the diffs of
+    /// their children are applied to the new containers. The other containers are
+    /// looked up by their ids. The text styles are marked with the flags configured by
+    /// [LoroDoc::config_text_style].
+    ///
+    /// It returns an error if the doc is detached and not editable.
+    #[inline]
+    pub fn apply_diff(&self, diff: &[ContainerDiff]) -> LoroResult<()> {
+        self.apply_diff_with_remap(diff, &mut Default::default())
+    }
+
+    /// The same as [LoroDoc::apply_diff], but the ids of the created containers are
+    /// recorded in `container_remap`.
+    ///
+    /// To mirror a doc by its events incrementally, `container_remap` should be kept
+    /// across the calls, so that the later diffs of the created containers can be applied.
+    pub fn apply_diff_with_remap(
+        &self,
+        diff: &[ContainerDiff],
+        container_remap: &mut FxHashMap<ContainerID, ContainerID>,
+    ) -> LoroResult<()> {
+        let mut diff: Vec<&ContainerDiff> = diff.iter().collect();
+        // the parents should be applied before their children
+        diff.sort_by_key(|x| x.path.len());
+        self.commit_then_stop();
+        let ans =
+            self.with_txn(|txn| {
+                for x in diff {
+                    let id = container_remap.get(&x.id).unwrap_or(&x.id).clone();
+                    let idx = self.get_container_idx(id, x.id.container_type());
+                    Handler::new(self.get_global_txn(), idx, Arc::downgrade(&self.state))
+                        .apply_diff(txn, &x.diff, container_remap)?;
+                }
+                Ok(())
+            });
+        self.renew_txn_if_auto_commit();
+        ans
+    }
+
     #[inline]
     pub fn vv_to_frontiers(&self, vv: &VersionVector) -> Frontiers {
         self.oplog.lock().unwrap().dag.vv_to_frontiers(vv)
@@ -656,23 +1226,6 @@ impl LoroDoc {
     }
 }
 
-fn parse_encode_header(bytes: &[u8]) -> Result<(&[u8], EncodeMode), LoroError> {
-    if bytes.len() <= 6 {
-        return Err(LoroError::DecodeError("Invalid import data".into()));
-    }
-    let (magic_bytes, input) = bytes.split_at(4);
-    let magic_bytes: [u8; 4] = magic_bytes.try_into().unwrap();
-    if magic_bytes != MAGIC_BYTES {
-        return Err(LoroError::DecodeError("Invalid header bytes".into()));
-    }
-    let (version, input) = input.split_at(1);
-    if version != [ENCODE_SCHEMA_VERSION] {
-        return Err(LoroError::DecodeError("Invalid version".into()));
-    }
-    let mode: EncodeMode = input[0].try_into()?;
-    Ok((&input[1..], mode))
-}
-
 #[cfg(test)]
 mod test {
     use loro_common::ID;
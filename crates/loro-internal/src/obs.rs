@@ -14,6 +14,8 @@ use super::{
 };
 
 pub type Subscriber = Arc<dyn (for<'a> Fn(DiffEvent<'a>)) + Send + Sync>;
+/// Receives the encoded update of each local change
+pub type LocalUpdateCallback = Arc<dyn Fn(&[u8]) + Send + Sync>;
 
 #[derive(Default)]
 struct ObserverInner {
@@ -42,6 +44,7 @@ pub struct Observer {
     arena: SharedArena,
     next_sub_id: AtomicU32,
     taken_times: AtomicUsize,
+    local_update_subscribers: Mutex<FxHashMap<SubID, LocalUpdateCallback>>,
 }
 
 impl Observer {
@@ -50,6 +53,7 @@ impl Observer {
             arena,
             next_sub_id: AtomicU32::new(0),
             taken_times: AtomicUsize::new(0),
+            local_update_subscribers: Default::default(),
             inner: Mutex::new(ObserverInner {
                 subscribers: Default::default(),
                 containers: Default::default(),
@@ -77,6 +81,33 @@ impl Observer {
         sub_id
     }
 
+    pub fn subscribe_local_update(&self, callback: LocalUpdateCallback) -> SubID {
+        let sub_id = self.fetch_add_next_id();
+        self.local_update_subscribers
+            .lock()
+            .unwrap()
+            .insert(sub_id, callback);
+        sub_id
+    }
+
+    pub(crate) fn has_local_update_subscribers(&self) -> bool {
+        !self.local_update_subscribers.lock().unwrap().is_empty()
+    }
+
+    pub(crate) fn emit_local_update(&self, bytes: &[u8]) {
+        // the callbacks may subscribe or unsubscribe
+        let subscribers: Vec<_> = self
+            .local_update_subscribers
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        for subscriber in subscribers {
+            subscriber(bytes);
+        }
+    }
+
     fn fetch_add_next_id(&self) -> SubID {
         SubID(
             self.next_sub_id
@@ -201,6 +232,16 @@ impl Observer {
     }
 
     pub fn unsubscribe(&self, sub_id: SubID) {
+        if self
+            .local_update_subscribers
+            .lock()
+            .unwrap()
+            .remove(&sub_id)
+            .is_some()
+        {
+            return;
+        }
+
         let mut inner = self.inner.try_lock().unwrap();
         inner.subscribers.remove(&sub_id);
         if self.is_taken() {
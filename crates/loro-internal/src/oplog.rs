@@ -1,33 +1,48 @@
+pub(crate) mod checkpoint;
 pub(crate) mod dag;
 mod pending_changes;
+pub(crate) mod signature;
 
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
 use std::mem::take;
+use std::ops::Range;
 use std::rc::Rc;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use fxhash::FxHashMap;
+use loro_common::IdSpan;
 use rle::{HasLength, RleCollection, RlePush, RleVec, Sliceable};
 use smallvec::SmallVec;
 // use tabled::measurment::Percent;
 
 use crate::change::{Change, Lamport, Timestamp};
+use crate::container::idx::ContainerIdx;
 use crate::container::list::list_op;
+use crate::container::richtext::{RichtextTracker, TextChange, TextChangeKind};
 use crate::dag::DagUtils;
 use crate::diff_calc::tree::MoveLamportAndID;
 use crate::diff_calc::TreeDiffCache;
 use crate::encoding::RemoteClientChanges;
-use crate::encoding::{decode_oplog, encode_oplog, EncodeMode};
+use crate::encoding::{
+    codec::CompressionCodec, decode_oplog, encode_oplog, encode_updates_between, EncodeMode,
+    ENCODE_SCHEMA_VERSION,
+};
 use crate::id::{Counter, PeerID, ID};
-use crate::op::{ListSlice, RawOpContent, RemoteOp};
+use crate::op::{InnerContent, ListSlice, RawOpContent, RemoteOp};
 use crate::span::{HasCounterSpan, HasIdSpan, HasLamportSpan};
 use crate::version::{Frontiers, ImVersionVector, VersionVector};
+use crate::version_tag::VersionTagStore;
 use crate::LoroError;
 
 type ClientChanges = FxHashMap<PeerID, Vec<Change>>;
+/// The event index range of a run of text, with its peer, the lamport of its first char
+/// and its timestamp
+pub type TextBlame = (Range<usize>, PeerID, Lamport, Timestamp);
+use self::checkpoint::StateCheckpoints;
 use self::pending_changes::PendingChanges;
+use self::signature::{ChangeSigner, SignatureStore, TrustStore};
 
 use super::arena::SharedArena;
 
@@ -54,6 +69,17 @@ pub struct OpLog {
     pub(crate) batch_importing: bool,
 
     pub(crate) tree_parent_cache: Mutex<TreeDiffCache>,
+    /// The signatures of the changes, see [signature]
+    pub(crate) signatures: SignatureStore,
+    /// Sign the local changes if it's set
+    signer: Option<Arc<dyn ChangeSigner>>,
+    /// Verify the remote changes if it's set
+    trust_store: Option<Arc<TrustStore>>,
+    /// The untrusted remote changes
+    quarantine: Vec<Change<RemoteOp<'static>>>,
+    /// The cached states for checkout, see [checkpoint]
+    pub(crate) checkpoints: StateCheckpoints,
+    version_tags: VersionTagStore,
 }
 
 /// [AppDag] maintains the causal graph of the app.
@@ -87,6 +113,12 @@ impl Clone for OpLog {
             pending_changes: Default::default(),
             batch_importing: false,
             tree_parent_cache: Default::default(),
+            signatures: self.signatures.clone(),
+            signer: self.signer.clone(),
+            trust_store: self.trust_store.clone(),
+            quarantine: Default::default(),
+            checkpoints: Default::default(),
+            version_tags: self.version_tags.clone(),
         }
     }
 }
@@ -161,6 +193,12 @@ impl OpLog {
             pending_changes: Default::default(),
             batch_importing: false,
             tree_parent_cache: Default::default(),
+            signatures: Default::default(),
+            signer: None,
+            trust_store: None,
+            quarantine: Vec::new(),
+            checkpoints: Default::default(),
+            version_tags: Default::default(),
         }
     }
 
@@ -200,6 +238,12 @@ impl OpLog {
         &self.changes
     }
 
+    /// The latest version tags, see [crate::version_tag]
+    pub(crate) fn version_tags(&mut self) -> &VersionTagStore {
+        self.version_tags.update(&self.changes, &self.arena);
+        &self.version_tags
+    }
+
     /// This is the only place to update the `OpLog.changes`
     pub(crate) fn insert_new_change(&mut self, mut change: Change, _: EnsureChangeDepsAreAtTheEnd) {
         let entry = self.changes.entry(change.id.peer).or_default();
@@ -284,6 +328,7 @@ impl OpLog {
         }
 
         drop(tree_cache);
+        self.sign_local_change(&change);
         self.insert_new_change(change, mark);
         Ok(())
     }
@@ -428,9 +473,20 @@ impl OpLog {
     }
 
     pub(crate) fn export_changes_from(&self, from: &VersionVector) -> RemoteClientChanges {
+        self.export_changes_between(from, self.vv())
+    }
+
+    /// Export the changes that are included in `to` but not in `from`.
+    /// The changes crossing the boundaries are sliced.
+    pub(crate) fn export_changes_between(
+        &self,
+        from: &VersionVector,
+        to: &VersionVector,
+    ) -> RemoteClientChanges<'_> {
         let mut changes = RemoteClientChanges::default();
-        for (&peer, &cnt) in self.vv().iter() {
+        for (&peer, &cnt) in to.iter() {
             let start_cnt = from.get(&peer).copied().unwrap_or(0);
+            let cnt = cnt.min(self.vv().get(&peer).copied().unwrap_or(0));
             if cnt <= start_cnt {
                 continue;
             }
@@ -439,13 +495,19 @@ impl OpLog {
             if let Some(peer_changes) = self.changes.get(&peer) {
                 if let Some(result) = peer_changes.get_by_atom_index(start_cnt) {
                     for change in &peer_changes[result.merged_index..] {
-                        if change.id.counter < start_cnt {
-                            if change.id.counter + change.atom_len() as Counter <= start_cnt {
+                        if change.id.counter >= cnt {
+                            break;
+                        }
+
+                        if change.id.counter < start_cnt || change.ctr_end() > cnt {
+                            if change.ctr_end() <= start_cnt {
                                 continue;
                             }
 
-                            let sliced = change
-                                .slice((start_cnt - change.id.counter) as usize, change.atom_len());
+                            let sliced = change.slice(
+                                (start_cnt - change.id.counter).max(0) as usize,
+                                (cnt.min(change.ctr_end()) - change.id.counter) as usize,
+                            );
                             temp.push(self.convert_change_to_remote(&sliced));
                         } else {
                             temp.push(self.convert_change_to_remote(change));
@@ -602,12 +664,29 @@ impl OpLog {
 
     // Changes are expected to be sorted by counter in each value in the hashmap
     // They should also be continuous  (TODO: check this)
+    /// Import the remote changes.
+    ///
+    /// If there is a trust store, the changes are verified against the given `signatures` first.
     pub(crate) fn import_remote_changes(
         &mut self,
         remote_changes: RemoteClientChanges,
+        signatures: SignatureStore,
     ) -> Result<(), LoroError> {
         // check whether we can append the new changes
         self.check_changes(&remote_changes)?;
+        let remote_changes = if self.trust_store.is_some() || !signatures.is_empty() {
+            let changes = self.verify_remote_changes(
+                remote_changes.into_values().flatten().collect(),
+                signatures,
+            )?;
+            let mut grouped = RemoteClientChanges::default();
+            for change in changes {
+                grouped.entry(change.id.peer).or_default().push(change);
+            }
+            grouped
+        } else {
+            remote_changes
+        };
         let latest_vv = self.dag.vv.clone();
         // op_converter is faster than using arena directly
         let ids = self.arena.clone().with_op_converter(|converter| {
@@ -654,9 +733,240 @@ impl OpLog {
             .and_then(|change| change.ops.get_by_atom_index(id.counter).map(|x| x.element))
     }
 
+    /// Get the author of each run of text in the text container at the given version.
+    ///
+    /// It returns the event index range of each run, with the peer, the lamport of its first char
+    /// and the timestamp of the change. The adjacent runs with the same peer and timestamp
+    /// are merged.
+    pub(crate) fn blame_text(&self, idx: ContainerIdx, vv: &VersionVector) -> Vec<TextBlame> {
+        let mut tracker = self.replay_text(idx, vv);
+        tracker.checkout(vv);
+        let mut ans: Vec<TextBlame> = Vec::new();
+        let mut index = 0;
+        for (mut id, mut range) in tracker.iter_visible_text() {
+            while !range.is_empty() {
+                let change = self.lookup_change(id).unwrap();
+                let len = (change.ctr_end() - id.counter).min(range.len() as Counter) as u32;
+                let event_len = if cfg!(feature = "wasm") {
+                    self.arena
+                        .slice_str_by_unicode_range(
+                            range.start as usize..(range.start + len) as usize,
+                        )
+                        .encode_utf16()
+                        .count()
+                } else {
+                    len as usize
+                };
+                match ans.last_mut() {
+                    Some(last) if last.1 == id.peer && last.3 == change.timestamp => {
+                        last.0.end += event_len;
+                    }
+                    _ => ans.push((
+                        index..index + event_len,
+                        id.peer,
+                        change.lamport + (id.counter - change.id.counter) as Lamport,
+                        change.timestamp,
+                    )),
+                }
+
+                index += event_len;
+                id = id.inc(len as Counter);
+                range.start += len;
+            }
+        }
+
+        ans
+    }
+
+    /// Get the inserted, deleted and unchanged text of the text container between two versions,
+    /// in the document order.
+    ///
+    /// The adjacent spans of the same kind produced by the same peer with the same timestamp
+    /// are merged.
+    pub(crate) fn text_changes_between(
+        &self,
+        idx: ContainerIdx,
+        from: &VersionVector,
+        to: &VersionVector,
+    ) -> Vec<TextChange> {
+        let mut all = from.clone();
+        all.merge(to);
+        let mut tracker = self.replay_text(idx, &all);
+        // the deletions made by the ops that are in `to` but not in `from`, sorted by the
+        // deleted spans. The spans covered by another one are dropped, so both their starts
+        // and ends are increasing, and the span deleting a char can be found by binary search.
+        let mut deletions: Vec<(ID, IdSpan)> = from
+            .diff_iter(to)
+            .1
+            .flat_map(|span| tracker.iter_deletions(span))
+            .collect();
+        deletions.sort_unstable_by_key(|(_, deleted)| {
+            (
+                deleted.client_id,
+                deleted.counter.min(),
+                Reverse(deleted.counter.max()),
+            )
+        });
+        deletions.dedup_by(|(_, deleted), (_, kept)| {
+            deleted.client_id == kept.client_id && deleted.counter.max() <= kept.counter.max()
+        });
+        let spans: Vec<_> = tracker.iter_text_changes(from, to).collect();
+        let mut ans: Vec<TextChange> = Vec::new();
+        for (kind, mut id, mut range) in spans {
+            while !range.is_empty() {
+                let deletion = if kind == TextChangeKind::Deleted {
+                    let i = deletions.partition_point(|(_, deleted)| {
+                        (deleted.client_id, deleted.counter.min()) <= (id.peer, id.counter)
+                    });
+                    i.checked_sub(1)
+                        .map(|i| &deletions[i])
+                        .filter(|(_, deleted)| deleted.contains(id))
+                } else {
+                    None
+                };
+
+                // the op that produced the first char, and the number of chars produced
+                // by the same change
+                let (op_id, change, len) = match deletion {
+                    Some((del_id, deleted)) => {
+                        let op_id = del_id.inc((id.counter - deleted.counter.start).abs());
+                        let change = self.lookup_change(op_id).unwrap();
+                        let len = if deleted.is_reversed() {
+                            (deleted.counter.start - id.counter + 1)
+                                .min(op_id.counter - change.id.counter + 1)
+                        } else {
+                            (deleted.counter.end - id.counter).min(change.ctr_end() - op_id.counter)
+                        };
+                        (op_id, change, len)
+                    }
+                    None => {
+                        let change = self.lookup_change(id).unwrap();
+                        (id, change, change.ctr_end() - id.counter)
+                    }
+                };
+
+                let len = len.min(range.len() as Counter) as u32;
+                let text = self
+                    .arena
+                    .slice_str_by_unicode_range(range.start as usize..(range.start + len) as usize);
+                match ans.last_mut() {
+                    Some(last)
+                        if last.kind == kind
+                            && last.id.peer == op_id.peer
+                            && last.timestamp == change.timestamp =>
+                    {
+                        last.text.push_str(&text);
+                    }
+                    _ => ans.push(TextChange {
+                        kind,
+                        text,
+                        id: op_id,
+                        lamport: change.lamport + (op_id.counter - change.id.counter) as Lamport,
+                        timestamp: change.timestamp,
+                    }),
+                }
+
+                id = id.inc(len as Counter);
+                range.start += len;
+            }
+        }
+
+        ans
+    }
+
+    /// Replay the ops of the text container in the given version on a new tracker.
+    ///
+    /// It iterates over all the changes in `vv` from the empty version. The trackers cached
+    /// by the [crate::diff_calc::DiffCalculator] are not reused, because they may not start
+    /// from the empty version.
+    fn replay_text(&self, idx: ContainerIdx, vv: &VersionVector) -> RichtextTracker {
+        let mut tracker = RichtextTracker::new();
+        for (change, before) in self.iter_causally(Default::default(), vv.clone()) {
+            let end = vv.get(&change.id.peer).copied().unwrap_or(0);
+            let mut checked_out = false;
+            for op in change.ops.iter() {
+                if op.counter >= end {
+                    break;
+                }
+
+                if op.container != idx {
+                    continue;
+                }
+
+                if !checked_out {
+                    tracker.checkout(&before.borrow());
+                    checked_out = true;
+                }
+
+                let sliced_op;
+                let op = if op.ctr_end() > end {
+                    sliced_op = op.slice(0, (end - op.counter) as usize);
+                    &sliced_op
+                } else {
+                    op
+                };
+                let InnerContent::List(list_op) = &op.content else {
+                    unreachable!()
+                };
+                // The anchors only take up the positions. Their styles are not needed here
+                tracker.apply_list_op(ID::new(change.id.peer, op.counter), list_op, 0);
+            }
+        }
+
+        tracker
+    }
+
     #[inline(always)]
     pub fn export_from(&self, vv: &VersionVector) -> Vec<u8> {
-        encode_oplog(self, vv, EncodeMode::Auto)
+        encode_oplog(self, vv, EncodeMode::Auto, ENCODE_SCHEMA_VERSION, None).unwrap()
+    }
+
+    /// Export the changes that are included in `to` but not in `from`.
+    ///
+    /// The signatures of the changes are not exported, because the sliced changes
+    /// cannot be verified.
+    pub(crate) fn export_between(&self, from: &VersionVector, to: &VersionVector) -> Vec<u8> {
+        encode_updates_between(self, from, to)
+    }
+
+    /// A new oplog on the same arena with the changes that are included in `vv`.
+    ///
+    /// The signer, the trust store and the checkpoint settings are kept. The signatures
+    /// of the changes that are not entirely included in `vv` are dropped.
+    pub(crate) fn truncated(&self, vv: &VersionVector) -> Result<Self, LoroError> {
+        let mut oplog = Self::new_with_arena(self.arena.clone());
+        decode_oplog(&mut oplog, &self.export_between(&Default::default(), vv))?;
+        for span in self.signatures.spans_between(&Default::default(), vv) {
+            oplog.signatures.insert(span);
+        }
+        oplog.signer = self.signer.clone();
+        oplog.trust_store = self.trust_store.clone();
+        oplog.checkpoints = self.checkpoints.cleared();
+        Ok(oplog)
+    }
+
+    #[inline(always)]
+    pub fn export_from_with_schema(
+        &self,
+        vv: &VersionVector,
+        schema_version: u8,
+    ) -> Result<Vec<u8>, LoroError> {
+        encode_oplog(self, vv, EncodeMode::Auto, schema_version, None)
+    }
+
+    #[inline(always)]
+    pub fn export_from_with_codec(
+        &self,
+        vv: &VersionVector,
+        codec: &dyn CompressionCodec,
+    ) -> Result<Vec<u8>, LoroError> {
+        encode_oplog(
+            self,
+            vv,
+            EncodeMode::Auto,
+            ENCODE_SCHEMA_VERSION,
+            Some(codec),
+        )
     }
 
     #[inline(always)]
@@ -774,7 +1084,8 @@ impl OpLog {
                         cur_cnt = 0;
                     }
 
-                    inner_vv.extend_to_include_end_id(change.id);
+                    // the change may be sliced at `cnt`, its ops before `cnt` are already included
+                    inner_vv.extend_to_include_end_id(ID::new(peer, cnt));
                     // debug_log::debug_dbg!(&change, &inner_vv);
                     Some((change, cnt, vv.clone()))
                 } else {
@@ -0,0 +1,187 @@
+//! State checkpoints for fast time travel.
+//!
+//! Checking out a distant version needs to replay all the ops between the two versions.
+//! When the checkpoints are enabled by [OpLog::set_checkpoint_interval], the state that
+//! the doc has checked out is cached once the checkouts have replayed `interval` ops
+//! since the last checkpoint, as long as it's at least `interval` ops away from the other
+//! checkpoints. A checkout can then start from the nearest checkpoint before the target
+//! version and only replay the remaining ops.
+//!
+//! The state of an attached doc is also recorded before it checks out another version, so
+//! the latest state, e.g. the one decoded from a snapshot, becomes a checkpoint without extra work.
+//! At most [OpLog::set_max_checkpoints] checkpoints are kept, the least recently used one
+//! is evicted first.
+//!
+//! The checkpoints can be persisted by [crate::LoroDoc::export_snapshot_with_checkpoints].
+//! They're loaded when the snapshot is imported into an empty doc whose checkpoints are enabled.
+//!
+//! A checkout doesn't start from a checkpoint when the doc has subscribers, because
+//! its events are calculated from the ops between the two versions anyway.
+
+use crate::{state::DocState, version::Frontiers, VersionVector};
+
+use super::OpLog;
+
+const DEFAULT_MAX_CHECKPOINTS: usize = 16;
+
+pub(crate) struct StateCheckpoints {
+    /// The min number of ops between two checkpoints. Checkpoints are disabled if it's `None`
+    interval: Option<usize>,
+    max: usize,
+    checkpoints: Vec<StateCheckpoint>,
+    /// Increased every time a checkpoint is recorded or used
+    clock: u64,
+    /// The number of ops replayed since the last checkpoint was recorded
+    replayed: usize,
+}
+
+impl Default for StateCheckpoints {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            max: DEFAULT_MAX_CHECKPOINTS,
+            checkpoints: Vec::new(),
+            clock: 0,
+            replayed: 0,
+        }
+    }
+}
+
+pub(crate) struct StateCheckpoint {
+    pub(crate) vv: VersionVector,
+    pub(crate) frontiers: Frontiers,
+    pub(crate) state: DocState,
+    last_used: u64,
+}
+
+impl StateCheckpoints {
+    /// Get the index of the checkpoint before `vv` with the fewest ops to replay,
+    /// and the number of them
+    pub(crate) fn nearest(&self, vv: &VersionVector) -> Option<(usize, usize)> {
+        self.checkpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, checkpoint)| vv.includes_vv(&checkpoint.vv))
+            .map(|(i, checkpoint)| (i, vv.distance_to(&checkpoint.vv)))
+            .min_by_key(|(_, distance)| *distance)
+    }
+
+    /// Get the checkpoint at `index` to start a checkout from
+    pub(crate) fn use_checkpoint(&mut self, index: usize) -> &StateCheckpoint {
+        self.clock += 1;
+        let checkpoint = &mut self.checkpoints[index];
+        checkpoint.last_used = self.clock;
+        checkpoint
+    }
+
+    /// Cache `state` as a checkpoint if `replayed` ops were applied to reach it, and it's due:
+    /// the ops replayed since the last checkpoint reach the interval and it's far enough
+    /// from all the other checkpoints and the empty version.
+    ///
+    /// The state is only forked when it's recorded.
+    pub(crate) fn record(&mut self, vv: &VersionVector, state: &DocState, replayed: usize) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        if self.max == 0 {
+            return;
+        }
+
+        self.replayed += replayed;
+        if self.replayed < interval {
+            return;
+        }
+
+        let is_far = vv.distance_to(&Default::default()) >= interval
+            && self.checkpoints.iter().all(|checkpoint| {
+                vv.distance_to(&checkpoint.vv) + checkpoint.vv.distance_to(vv) >= interval
+            });
+        if !is_far {
+            return;
+        }
+
+        self.replayed = 0;
+        self.insert(vv.clone(), state.fork());
+    }
+
+    /// Add `state` at version `vv` as the most recently used checkpoint
+    pub(crate) fn insert(&mut self, vv: VersionVector, state: DocState) {
+        if self.max == 0 {
+            return;
+        }
+
+        self.evict(self.max - 1);
+        self.clock += 1;
+        self.checkpoints.push(StateCheckpoint {
+            vv,
+            frontiers: state.frontiers.clone(),
+            state,
+            last_used: self.clock,
+        });
+    }
+
+    /// An empty cache with the same settings
+    pub(crate) fn cleared(&self) -> Self {
+        Self {
+            interval: self.interval,
+            max: self.max,
+            ..Default::default()
+        }
+    }
+
+    /// The states of the checkpoints, from the least recently used one
+    pub(crate) fn states_mut(&mut self) -> impl Iterator<Item = &mut DocState> {
+        self.checkpoints
+            .sort_by_key(|checkpoint| checkpoint.last_used);
+        self.checkpoints
+            .iter_mut()
+            .map(|checkpoint| &mut checkpoint.state)
+    }
+
+    /// Drop the least recently used checkpoints until there are at most `len` of them
+    fn evict(&mut self, len: usize) {
+        while self.checkpoints.len() > len {
+            let (lru, _) = self
+                .checkpoints
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, checkpoint)| checkpoint.last_used)
+                .unwrap();
+            self.checkpoints.swap_remove(lru);
+        }
+    }
+}
+
+impl OpLog {
+    /// Cache the states of the checked out versions every `interval` ops at least.
+    /// The cached checkpoints are dropped if it's `None`. See [crate::oplog::checkpoint]
+    pub fn set_checkpoint_interval(&mut self, interval: Option<usize>) {
+        if interval.is_none() {
+            self.checkpoints.checkpoints.clear();
+            self.checkpoints.replayed = 0;
+        }
+
+        self.checkpoints.interval = interval;
+    }
+
+    #[inline]
+    pub fn checkpoint_interval(&self) -> Option<usize> {
+        self.checkpoints.interval
+    }
+
+    /// Keep at most `max` checkpoints, the least recently used ones are evicted.
+    /// It's 16 by default.
+    pub fn set_max_checkpoints(&mut self, max: usize) {
+        self.checkpoints.max = max;
+        self.checkpoints.evict(max);
+    }
+
+    /// The versions of the cached checkpoints
+    pub fn checkpoint_versions(&self) -> Vec<Frontiers> {
+        self.checkpoints
+            .checkpoints
+            .iter()
+            .map(|checkpoint| checkpoint.frontiers.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,629 @@
+//! Optional signing of [Change]s.
+//!
+//! A signature covers the id, the deps and the ops of a change when it's created.
+//! Because the changes may be merged or sliced afterwards, the signatures are stored
+//! separately by the spans they cover. The change of a span can always be restored
+//! by slicing the stored changes, since only the changes that depend on the previous
+//! change of the same peer are merged.
+//!
+//! The signatures of the remote changes are only kept if they're verified by the
+//! [TrustStore], so a doc only relays the signatures of the peers it trusts.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use fxhash::FxHashMap;
+use itertools::Itertools;
+use loro_common::{ContainerID, Counter, HasCounterSpan, IdSpan, PeerID, ID};
+use rle::{HasLength, RleVec, Sliceable};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change::Change,
+    op::{RawOpContent, RemoteOp},
+    LoroError, VersionVector,
+};
+
+use super::OpLog;
+
+/// Signs the changes created by the local peer.
+///
+/// The key pair should be bound to the peer, i.e. the other peers should
+/// register the public key for this [PeerID] in their [TrustStore].
+pub trait ChangeSigner: Send + Sync {
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// The public key of a peer.
+pub trait PublicKey: Send + Sync {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// What to do with the imported changes that are unsigned or whose signatures are invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UntrustedChangePolicy {
+    /// Reject the whole import
+    #[default]
+    Reject,
+    /// Import the trusted changes and keep the untrusted ones aside.
+    /// They can be inspected by [OpLog::quarantined_changes].
+    Quarantine,
+}
+
+/// The public keys of the trusted peers.
+///
+/// When it's set, the remote changes are only imported if they are signed
+/// by the key of their peer.
+#[derive(Clone, Default)]
+pub struct TrustStore {
+    keys: FxHashMap<PeerID, Arc<dyn PublicKey>>,
+    policy: UntrustedChangePolicy,
+}
+
+impl TrustStore {
+    pub fn new(policy: UntrustedChangePolicy) -> Self {
+        Self {
+            keys: Default::default(),
+            policy,
+        }
+    }
+
+    pub fn trust(&mut self, peer: PeerID, key: Arc<dyn PublicKey>) {
+        self.keys.insert(peer, key);
+    }
+
+    pub fn distrust(&mut self, peer: PeerID) {
+        self.keys.remove(&peer);
+    }
+
+    pub fn policy(&self) -> UntrustedChangePolicy {
+        self.policy
+    }
+}
+
+impl std::fmt::Debug for TrustStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrustStore")
+            .field("peers", &self.keys.keys().collect::<Vec<_>>())
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/// The signature of the change `peer:start..end`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SignedSpan {
+    pub(crate) peer: PeerID,
+    pub(crate) start: Counter,
+    pub(crate) end: Counter,
+    pub(crate) signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SignatureStore {
+    spans: FxHashMap<PeerID, BTreeMap<Counter, SignedSpan>>,
+}
+
+impl SignatureStore {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// The spans of the signed changes, sorted by peer and counter
+    pub(crate) fn id_spans(&self) -> Vec<IdSpan> {
+        self.spans
+            .iter()
+            .sorted_by_key(|(peer, _)| **peer)
+            .flat_map(|(_, spans)| spans.values())
+            .map(|span| IdSpan::new(span.peer, span.start, span.end))
+            .collect()
+    }
+
+    pub(crate) fn merge(&mut self, other: SignatureStore) {
+        for span in other.spans.into_values().flat_map(|x| x.into_values()) {
+            self.insert(span);
+        }
+    }
+
+    /// The existing signature will not be overwritten
+    pub(crate) fn insert(&mut self, span: SignedSpan) {
+        self.spans
+            .entry(span.peer)
+            .or_default()
+            .entry(span.start)
+            .or_insert(span);
+    }
+
+    pub(crate) fn get_covering(&self, peer: PeerID, counter: Counter) -> Option<&SignedSpan> {
+        self.spans
+            .get(&peer)?
+            .range(..=counter)
+            .next_back()
+            .map(|(_, span)| span)
+            .filter(|span| span.end > counter)
+    }
+
+    /// Lower the version to the start of the signed spans it's inside, so that the
+    /// changes exported from it can be verified as a whole.
+    pub(crate) fn align_to_span_start(&self, vv: &VersionVector) -> VersionVector {
+        let mut ans = vv.clone();
+        for (peer, counter) in ans.iter_mut() {
+            if let Some(span) = self.get_covering(*peer, *counter) {
+                *counter = span.start;
+            }
+        }
+        ans
+    }
+
+    /// Get the signatures of the spans between `from` and `to`.
+    ///
+    /// The spans that are not entirely included in `to` are omitted.
+    pub(crate) fn spans_between(
+        &self,
+        from: &VersionVector,
+        to: &VersionVector,
+    ) -> Vec<SignedSpan> {
+        let mut ans = Vec::new();
+        for (peer, spans) in self.spans.iter().sorted_by_key(|(peer, _)| **peer) {
+            let start = from.get(peer).copied().unwrap_or(0);
+            let end = to.get(peer).copied().unwrap_or(0);
+            for span in spans.values() {
+                if span.end > start && span.end <= end {
+                    ans.push(span.clone());
+                }
+            }
+        }
+        ans
+    }
+}
+
+/// Get the bytes to sign for the change.
+///
+/// The ops are merged before encoding, so that the message
+/// doesn't depend on how the ops are split by different encodings.
+pub(crate) fn signing_message<'a>(
+    id: ID,
+    deps: &[ID],
+    ops: impl Iterator<Item = &'a RemoteOp<'a>>,
+) -> Vec<u8> {
+    let mut merged: RleVec<[RemoteOp; 1]> = RleVec::new();
+    for op in ops {
+        merged.push(op.clone());
+    }
+    let mut deps = deps.to_vec();
+    deps.sort();
+    let ops: Vec<(&ContainerID, &RawOpContent)> = merged
+        .iter()
+        .map(|op| (&op.container, &op.content))
+        .collect();
+    postcard::to_allocvec(&(id, deps, ops)).unwrap()
+}
+
+impl OpLog {
+    pub(super) fn sign_local_change(&mut self, change: &Change) {
+        let Some(signer) = self.signer.clone() else {
+            return;
+        };
+
+        let remote = self.convert_change_to_remote(change);
+        let message = signing_message(remote.id, &remote.deps, remote.ops.iter());
+        drop(remote);
+        self.signatures.insert(SignedSpan {
+            peer: change.id.peer,
+            start: change.id.counter,
+            end: change.ctr_end(),
+            signature: signer.sign(&message),
+        });
+    }
+
+    /// Verify the signatures of the remote changes of one peer. The changes should be sorted
+    /// by counter. The verified signatures are pushed to `verified`.
+    /// Return the counter from which the changes are untrusted.
+    fn find_untrusted_counter(
+        &self,
+        trust_store: &TrustStore,
+        changes: &[&Change<RemoteOp>],
+        signatures: &SignatureStore,
+        verified: &mut Vec<SignedSpan>,
+    ) -> Option<Counter> {
+        let first = changes.first()?;
+        let peer = first.id.peer;
+        let end = changes.last().unwrap().ctr_end();
+        let mut pos = self
+            .vv()
+            .get(&peer)
+            .copied()
+            .unwrap_or(0)
+            .max(first.id.counter);
+        if pos >= end {
+            return None;
+        }
+
+        let Some(key) = trust_store.keys.get(&peer) else {
+            return Some(pos);
+        };
+
+        while pos < end {
+            let Some(span) = signatures.get_covering(peer, pos) else {
+                return Some(pos);
+            };
+            if span.start < first.id.counter || span.end > end {
+                return Some(pos);
+            }
+
+            let mut ops = Vec::new();
+            let mut deps = None;
+            for change in changes.iter() {
+                let change_span = change.ctr_span();
+                if change_span.end <= span.start || change_span.start >= span.end {
+                    continue;
+                }
+
+                let from = (span.start - change_span.start).max(0) as usize;
+                let to = ((span.end - change_span.start) as usize).min(change.atom_len());
+                let slice = change.slice(from, to);
+                if deps.is_none() {
+                    deps = Some(slice.deps.clone());
+                }
+                ops.extend(slice.ops.iter().cloned());
+            }
+
+            let message = signing_message(
+                ID::new(peer, span.start),
+                &deps.unwrap_or_default(),
+                ops.iter(),
+            );
+            if !key.verify(&message, &span.signature) {
+                return Some(pos);
+            }
+
+            verified.push(span.clone());
+            pos = span.end;
+        }
+
+        None
+    }
+
+    /// Verify the remote changes against the trust store. Only the verified signatures
+    /// are saved, so the signatures are dropped if there is no trust store.
+    /// Return the trusted changes.
+    ///
+    /// # Err
+    ///
+    /// Return [LoroError::SignatureError] if there are untrusted changes and
+    /// the policy is [UntrustedChangePolicy::Reject].
+    pub(crate) fn verify_remote_changes<'a>(
+        &mut self,
+        changes: Vec<Change<RemoteOp<'a>>>,
+        signatures: SignatureStore,
+    ) -> Result<Vec<Change<RemoteOp<'a>>>, LoroError> {
+        let Some(trust_store) = self.trust_store.clone() else {
+            return Ok(changes);
+        };
+
+        let mut untrusted: FxHashMap<PeerID, Counter> = FxHashMap::default();
+        let mut verified = Vec::new();
+        for (peer, peer_changes) in changes.iter().into_group_map_by(|c| c.id.peer).into_iter() {
+            let peer_changes: Vec<_> = peer_changes
+                .into_iter()
+                .sorted_by_key(|c| c.id.counter)
+                .collect();
+            if let Some(counter) =
+                self.find_untrusted_counter(&trust_store, &peer_changes, &signatures, &mut verified)
+            {
+                untrusted.insert(peer, counter);
+            }
+        }
+
+        if !untrusted.is_empty() && trust_store.policy == UntrustedChangePolicy::Reject {
+            let (peer, counter) = untrusted.iter().min().unwrap();
+            return Err(LoroError::SignatureError(
+                format!(
+                    "The change {:?} is unsigned or its signature is invalid",
+                    ID::new(*peer, *counter)
+                )
+                .into_boxed_str(),
+            ));
+        }
+
+        let mut trusted = Vec::with_capacity(changes.len());
+        for change in changes {
+            let Some(&counter) = untrusted.get(&change.id.peer) else {
+                trusted.push(change);
+                continue;
+            };
+
+            let span = change.ctr_span();
+            if span.end <= counter {
+                trusted.push(change);
+            } else if span.start >= counter {
+                self.quarantine.push(to_static_change(&change));
+            } else {
+                let at = (counter - span.start) as usize;
+                self.quarantine
+                    .push(to_static_change(&change.slice(at, change.atom_len())));
+                trusted.push(change.slice(0, at));
+            }
+        }
+
+        for span in verified {
+            self.signatures.insert(span);
+        }
+
+        Ok(trusted)
+    }
+
+    /// The spans of the changes whose signatures are stored, sorted by peer and counter
+    pub fn signed_changes(&self) -> Vec<IdSpan> {
+        self.signatures.id_spans()
+    }
+
+    /// The spans of the changes that are not imported because they're untrusted.
+    pub fn quarantined_changes(&self) -> Vec<IdSpan> {
+        self.quarantine
+            .iter()
+            .map(|c| IdSpan::new(c.id.peer, c.id.counter, c.ctr_end()))
+            .collect()
+    }
+
+    #[inline]
+    pub(crate) fn has_trust_store(&self) -> bool {
+        self.trust_store.is_some()
+    }
+
+    #[inline]
+    pub fn set_change_signer(&mut self, signer: Option<Arc<dyn ChangeSigner>>) {
+        self.signer = signer;
+    }
+
+    #[inline]
+    pub fn set_trust_store(&mut self, trust_store: Option<TrustStore>) {
+        self.trust_store = trust_store.map(Arc::new);
+    }
+}
+
+pub(crate) fn to_static_change(change: &Change<RemoteOp>) -> Change<RemoteOp<'static>> {
+    let mut ops = RleVec::new();
+    for op in change.ops.iter() {
+        ops.push(RemoteOp {
+            container: op.container.clone(),
+            counter: op.counter,
+            content: op.content.to_static(),
+        });
+    }
+    Change {
+        ops,
+        deps: change.deps.clone(),
+        id: change.id,
+        lamport: change.lamport,
+        timestamp: change.timestamp,
+        has_dependents: false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        sync::Arc,
+    };
+
+    use crate::{
+        ChangeSigner, CompressionCodec, LoroDoc, NoCompression, PublicKey, TrustStore,
+        UntrustedChangePolicy, VersionVector,
+    };
+    use loro_common::{IdSpan, LoroError};
+
+    /// A toy signature scheme for testing. It must never be used for real data.
+    struct ToyKey(u64);
+
+    impl ToyKey {
+        fn signature(&self, message: &[u8]) -> Vec<u8> {
+            let mut hasher = DefaultHasher::new();
+            (self.0, message).hash(&mut hasher);
+            hasher.finish().to_le_bytes().to_vec()
+        }
+    }
+
+    impl ChangeSigner for ToyKey {
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            self.signature(message)
+        }
+    }
+
+    impl PublicKey for ToyKey {
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            self.signature(message) == signature
+        }
+    }
+
+    fn signed_doc(peer: u64) -> LoroDoc {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_peer_id(peer).unwrap();
+        doc.set_change_signer(Arc::new(ToyKey(peer)));
+        doc
+    }
+
+    fn trust_store(peers: &[u64], policy: UntrustedChangePolicy) -> TrustStore {
+        let mut store = TrustStore::new(policy);
+        for &peer in peers {
+            store.trust(peer, Arc::new(ToyKey(peer)));
+        }
+        store
+    }
+
+    fn trusting_doc(peers: &[u64], policy: UntrustedChangePolicy) -> LoroDoc {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_trust_store(trust_store(peers, policy));
+        doc
+    }
+
+    fn edit(doc: &LoroDoc, n: usize) {
+        let text = doc.get_text("text");
+        let list = doc.get_list("list");
+        let map = doc.get_map("map");
+        for i in 0..n {
+            text.insert_(0, "abc").unwrap();
+            text.delete_(1, 1).unwrap();
+            list.push_((i as i32).into()).unwrap();
+            map.insert_("key", (i as i32).into()).unwrap();
+            doc.commit_then_renew();
+        }
+    }
+
+    #[test]
+    fn signed_changes_can_be_imported() {
+        let a = signed_doc(1);
+        edit(&a, 2);
+
+        // small updates
+        let b = trusting_doc(&[1], UntrustedChangePolicy::Reject);
+        b.import(&a.export_from(&Default::default())).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+        assert_eq!(
+            b.oplog().lock().unwrap().signed_changes(),
+            vec![IdSpan::new(1, 0, 6), IdSpan::new(1, 6, 12)]
+        );
+
+        // large updates use the enhanced encoding
+        edit(&a, 20);
+        b.import(&a.export_from(&b.oplog_vv())).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+
+        // snapshot
+        let c = trusting_doc(&[1], UntrustedChangePolicy::Reject);
+        c.import(&a.export_snapshot()).unwrap();
+        assert_eq!(a.get_deep_value(), c.get_deep_value());
+        let c = trusting_doc(&[1], UntrustedChangePolicy::Reject);
+        c.import(&a.export_snapshot_at(&b.oplog_frontiers()).unwrap())
+            .unwrap();
+        assert_eq!(b.get_deep_value(), c.get_deep_value());
+    }
+
+    #[test]
+    fn signatures_survive_reencoding() {
+        let a = signed_doc(1);
+        let b = signed_doc(2);
+        // only the verified signatures of `a` are kept by `b`
+        b.set_trust_store(trust_store(&[1], UntrustedChangePolicy::Reject));
+        edit(&a, 5);
+        b.import(&a.export_from(&Default::default())).unwrap();
+        edit(&b, 5);
+
+        // relay through docs with different encodings
+        let relay_snapshot = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+        relay_snapshot.import(&b.export_snapshot()).unwrap();
+        let relay_updates = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+        relay_updates
+            .import(&relay_snapshot.export_from(&Default::default()))
+            .unwrap();
+        let relay_small = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+        for _ in 0..2 {
+            relay_small
+                .import(&relay_updates.export_from(&relay_small.oplog_vv()))
+                .unwrap();
+        }
+
+        for relay in [&relay_snapshot, &relay_updates, &relay_small] {
+            let c = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+            c.import(&relay.export_from(&Default::default())).unwrap();
+            assert_eq!(b.get_deep_value(), c.get_deep_value());
+            let c = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+            c.import(&relay.export_snapshot()).unwrap();
+            assert_eq!(b.get_deep_value(), c.get_deep_value());
+        }
+
+        // exporting from a version in the middle of a signed change
+        // includes the whole change
+        let c = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+        let mut vv = VersionVector::new();
+        vv.insert(1, 3);
+        c.import(&a.export_from(&vv)).unwrap();
+        assert_eq!(a.get_deep_value(), c.get_deep_value());
+        let mut vv = c.oplog_vv();
+        vv.insert(2, 1);
+        c.import(&b.export_from(&vv)).unwrap();
+        assert_eq!(b.get_deep_value(), c.get_deep_value());
+    }
+
+    #[test]
+    fn unverified_signatures_are_dropped() {
+        let a = signed_doc(1);
+        edit(&a, 2);
+
+        let docs = [
+            LoroDoc::from_snapshot(&a.export_snapshot()).unwrap(),
+            LoroDoc::new(),
+            LoroDoc::new(),
+        ];
+        docs[1].import(&a.export_snapshot()).unwrap();
+        docs[2].import(&a.export_from(&Default::default())).unwrap();
+        for doc in docs.iter() {
+            assert_eq!(a.get_deep_value(), doc.get_deep_value());
+            assert!(doc.oplog().lock().unwrap().signed_changes().is_empty());
+            let err = trusting_doc(&[1], UntrustedChangePolicy::Reject)
+                .import(&doc.export_from(&Default::default()))
+                .unwrap_err();
+            assert!(matches!(err, LoroError::SignatureError(_)));
+        }
+
+        // the verified signatures are kept
+        let b = trusting_doc(&[1], UntrustedChangePolicy::Reject);
+        b.import(&a.export_snapshot()).unwrap();
+        assert_eq!(
+            b.oplog().lock().unwrap().signed_changes(),
+            vec![IdSpan::new(1, 0, 6), IdSpan::new(1, 6, 12)]
+        );
+    }
+
+    #[test]
+    fn untrusted_changes_are_rejected() {
+        let a = signed_doc(1);
+        edit(&a, 2);
+        let unsigned = LoroDoc::new_auto_commit();
+        unsigned.set_peer_id(1).unwrap();
+        edit(&unsigned, 2);
+        let forged = LoroDoc::new_auto_commit();
+        forged.set_peer_id(1).unwrap();
+        forged.set_change_signer(Arc::new(ToyKey(2)));
+        edit(&forged, 2);
+        let unknown = signed_doc(3);
+        edit(&unknown, 2);
+
+        for doc in [&unsigned, &forged, &unknown] {
+            let b = trusting_doc(&[1, 2], UntrustedChangePolicy::Reject);
+            for bytes in [doc.export_from(&Default::default()), doc.export_snapshot()] {
+                let err = b.import(&bytes).unwrap_err();
+                assert!(matches!(err, LoroError::SignatureError(_)));
+                assert!(b.oplog_vv().is_empty());
+            }
+        }
+
+        // signatures can't be reused for other ops
+        let b = trusting_doc(&[1], UntrustedChangePolicy::Reject);
+        let mut tampered = a.export_from(&Default::default());
+        // `magic | version | mode | codec | crc32(payload) | payload`
+        assert_eq!(tampered[6], NoCompression.id());
+        let pos = tampered.windows(3).position(|x| x == b"abc").unwrap();
+        tampered[pos] = b'x';
+        let checksum = crc32fast::hash(&tampered[11..]);
+        tampered[7..11].copy_from_slice(&checksum.to_le_bytes());
+        let err = b.import(&tampered).unwrap_err();
+        assert!(matches!(err, LoroError::SignatureError(_)));
+    }
+
+    #[test]
+    fn untrusted_changes_are_quarantined() {
+        let a = signed_doc(1);
+        edit(&a, 2);
+        let unknown = signed_doc(3);
+        unknown.set_trust_store(trust_store(&[1], UntrustedChangePolicy::Reject));
+        unknown.import(&a.export_from(&Default::default())).unwrap();
+        edit(&unknown, 2);
+
+        let b = trusting_doc(&[1], UntrustedChangePolicy::Quarantine);
+        b.import(&unknown.export_from(&Default::default())).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+        assert_eq!(b.quarantined_changes(), vec![IdSpan::new(3, 0, 12)]);
+    }
+}
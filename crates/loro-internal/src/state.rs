@@ -7,13 +7,16 @@ use loro_common::{ContainerID, LoroResult};
 
 use crate::{
     configure::{DefaultRandom, SecureRandomGenerator},
-    container::{idx::ContainerIdx, ContainerIdRaw},
+    container::{idx::ContainerIdx, richtext::StyleConfigMap, ContainerIdRaw},
+    encoding::ContainerStateLoader,
     event::{Diff, Index},
     event::{InternalContainerDiff, InternalDiff},
     fx_map,
     id::PeerID,
     op::{Op, RawOp},
+    utils::lazy::LazyLoad,
     version::Frontiers,
+    version_tag::is_version_tags,
     ContainerType, InternalString, LoroValue,
 };
 
@@ -37,8 +40,10 @@ pub struct DocState {
     pub(super) peer: PeerID,
 
     pub(super) frontiers: Frontiers,
-    pub(super) states: FxHashMap<ContainerIdx, State>,
+    pub(super) states: FxHashMap<ContainerIdx, LazyState>,
     pub(super) arena: SharedArena,
+    /// The flags of the text styles that are applied by their keys
+    pub(crate) text_style_config: StyleConfigMap,
 
     // txn related stuff
     in_txn: bool,
@@ -83,6 +88,10 @@ pub(crate) trait ContainerState: Clone {
     }
 }
 
+/// The states decoded from a snapshot are kept encoded until they are accessed
+/// for the first time. So the states should be accessed by [LazyLoad::get_mut].
+pub(crate) type LazyState = LazyLoad<ContainerStateLoader, State>;
+
 #[allow(clippy::enum_variant_names)]
 #[enum_dispatch(ContainerState)]
 #[derive(EnumAsInner, Clone, Debug)]
@@ -118,6 +127,7 @@ impl DocState {
         Self {
             peer,
             arena,
+            text_style_config: Default::default(),
             frontiers: Frontiers::default(),
             states: FxHashMap::default(),
             in_txn: false,
@@ -145,8 +155,36 @@ impl DocState {
         self.event_recorder.recording_diff
     }
 
+    /// Clone the version and the container states, without the txn and the recorded events
+    pub(crate) fn fork(&self) -> Self {
+        assert!(!self.in_txn);
+        Self {
+            peer: self.peer,
+            frontiers: self.frontiers.clone(),
+            states: self.states.clone(),
+            arena: self.arena.clone(),
+            text_style_config: self.text_style_config.clone(),
+            in_txn: false,
+            changed_idx_in_txn: Default::default(),
+            event_recorder: Default::default(),
+        }
+    }
+
+    /// Replace the version and the container states with the ones of `other`.
+    /// No event is recorded, so it should only be used when the doc isn't recording.
+    pub(crate) fn replace_with(&mut self, other: &DocState) {
+        assert!(!self.in_txn);
+        assert!(!self.is_recording());
+        self.frontiers = other.frontiers.clone();
+        self.states = other.states.clone();
+    }
+
+    /// Replace the peer id by a random one that is different from the current one
     pub fn refresh_peer_id(&mut self) {
-        self.peer = DefaultRandom.next_u64();
+        let old = self.peer;
+        while self.peer == old {
+            self.peer = DefaultRandom.next_u64();
+        }
     }
 
     /// Take all the diffs that are recorded and convert them to events.
@@ -257,7 +295,8 @@ impl DocState {
                     let state = self
                         .states
                         .entry(diff.idx)
-                        .or_insert_with(|| create_state(idx));
+                        .or_insert_with(|| LazyLoad::new_dst(create_state(idx)))
+                        .get_mut();
                     let state_diff = state.to_diff();
                     if diff.diff.is_none() && state_diff.is_empty() {
                         // empty diff, skip it
@@ -297,7 +336,11 @@ impl DocState {
                 continue;
             };
             let idx = diff.idx;
-            let state = self.states.entry(idx).or_insert_with(|| create_state(idx));
+            let state = self
+                .states
+                .entry(idx)
+                .or_insert_with(|| LazyLoad::new_dst(create_state(idx)))
+                .get_mut();
 
             if self.in_txn {
                 state.start_txn();
@@ -338,7 +381,8 @@ impl DocState {
         let state = self
             .states
             .entry(op.container)
-            .or_insert_with(|| create_state(op.container));
+            .or_insert_with(|| LazyLoad::new_dst(create_state(op.container)))
+            .get_mut();
 
         if self.in_txn {
             state.start_txn();
@@ -357,7 +401,11 @@ impl DocState {
     #[inline]
     pub(crate) fn abort_txn(&mut self) {
         for container_idx in std::mem::take(&mut self.changed_idx_in_txn) {
-            self.states.get_mut(&container_idx).unwrap().abort_txn();
+            self.states
+                .get_mut(&container_idx)
+                .unwrap()
+                .get_mut()
+                .abort_txn();
         }
 
         self.in_txn = false;
@@ -365,7 +413,11 @@ impl DocState {
 
     pub(crate) fn commit_txn(&mut self, new_frontiers: Frontiers, diff: Option<InternalDocDiff>) {
         for container_idx in std::mem::take(&mut self.changed_idx_in_txn) {
-            self.states.get_mut(&container_idx).unwrap().commit_txn();
+            self.states
+                .get_mut(&container_idx)
+                .unwrap()
+                .get_mut()
+                .commit_txn();
         }
 
         self.in_txn = false;
@@ -378,19 +430,20 @@ impl DocState {
     #[inline]
     #[allow(unused)]
     pub(super) fn get_state_mut(&mut self, idx: ContainerIdx) -> Option<&mut State> {
-        self.states.get_mut(&idx)
+        self.states.get_mut(&idx).map(|x| x.get_mut())
     }
 
-    #[inline]
-    #[allow(unused)]
-    pub(super) fn get_state(&self, idx: ContainerIdx) -> Option<&State> {
-        self.states.get(&idx)
+    /// Decode all the states that are still encoded
+    pub(crate) fn load_all_states(&mut self) {
+        for state in self.states.values_mut() {
+            state.get_mut();
+        }
     }
 
     pub(crate) fn get_value_by_idx(&mut self, container_idx: ContainerIdx) -> LoroValue {
         self.states
             .get_mut(&container_idx)
-            .map(|x| x.get_value())
+            .map(|x| x.get_mut().get_value())
             .unwrap_or_else(|| container_idx.get_type().default_value())
     }
 
@@ -402,13 +455,18 @@ impl DocState {
     /// If the state is not empty.
     pub(super) fn init_with_states_and_version(
         &mut self,
-        states: FxHashMap<ContainerIdx, State>,
+        states: FxHashMap<ContainerIdx, LazyState>,
         frontiers: Frontiers,
     ) {
         assert!(self.states.is_empty(), "overriding states");
         self.pre_txn(Default::default(), false);
         self.states = states;
         for (idx, state) in self.states.iter() {
+            // the parents of the encoded states are already registered when decoding the snapshot
+            let LazyLoad::Dst(state) = state else {
+                continue;
+            };
+
             for child_id in state.get_child_containers() {
                 let child_idx = self.arena.register_container(&child_id);
                 self.arena.set_parent(child_idx, Some(*idx));
@@ -423,7 +481,7 @@ impl DocState {
                     idx,
                     bring_back: false,
                     is_container_deleted: false,
-                    diff: Some(state.to_diff().into()),
+                    diff: Some(state.get_mut().to_diff().into()),
                 })
                 .collect();
             self.record_diff(InternalDocDiff {
@@ -460,18 +518,19 @@ impl DocState {
         let idx = idx.unwrap();
         self.states
             .entry(idx)
-            .or_insert_with(|| State::new_richtext(idx))
+            .or_insert_with(|| LazyLoad::new_dst(State::new_richtext(idx)))
+            .get_mut()
             .as_richtext_state_mut()
     }
 
     #[inline(always)]
-    pub(crate) fn with_state<F, R>(&self, idx: ContainerIdx, f: F) -> R
+    pub(crate) fn with_state<F, R>(&mut self, idx: ContainerIdx, f: F) -> R
     where
         F: FnOnce(&State) -> R,
     {
-        let state = self.states.get(&idx);
+        let state = self.states.get_mut(&idx);
         if let Some(state) = state {
-            f(state)
+            f(state.get_mut())
         } else {
             f(&create_state(idx))
         }
@@ -484,7 +543,7 @@ impl DocState {
     {
         let state = self.states.get_mut(&idx);
         if let Some(state) = state {
-            f(state)
+            f(state.get_mut())
         } else {
             f(&mut create_state(idx))
         }
@@ -546,7 +605,7 @@ impl DocState {
         let Some(state) = self.states.get_mut(&container) else {
             return container.get_type().default_value();
         };
-        let value = state.get_value();
+        let value = state.get_mut().get_value();
         let cid_str =
             LoroValue::String(Arc::new(format!("idx:{}, id:{}", container.to_index(), id)));
         match value {
@@ -607,7 +666,7 @@ impl DocState {
         let Some(state) = self.states.get_mut(&container) else {
             return container.get_type().default_value();
         };
-        let value = state.get_value();
+        let value = state.get_mut().get_value();
         match value {
             LoroValue::Container(_) => unreachable!(),
             LoroValue::List(mut list) => {
@@ -673,6 +732,9 @@ impl DocState {
                     // omit event form deleted container
                     continue;
                 }
+                if is_version_tags(&self.arena, container_diff.idx) {
+                    continue;
+                }
                 let Some((last_container_diff, _)) = containers.get_mut(&container_diff.idx) else {
                     if let Some(path) = self.get_path(container_diff.idx) {
                         containers.insert(container_diff.idx, (container_diff.diff.unwrap(), path));
@@ -723,7 +785,7 @@ impl DocState {
     }
 
     // the container may be override, so it may return None
-    fn get_path(&self, idx: ContainerIdx) -> Option<Vec<(ContainerID, Index)>> {
+    fn get_path(&mut self, idx: ContainerIdx) -> Option<Vec<(ContainerID, Index)>> {
         debug_log::group!("GET PATH {:?}", idx);
         let mut ans = Vec::new();
         let mut idx = idx;
@@ -731,7 +793,7 @@ impl DocState {
             let id = self.arena.idx_to_id(idx).unwrap();
             debug_log::debug_dbg!(&id);
             if let Some(parent_idx) = self.arena.get_parent(idx) {
-                let parent_state = self.states.get(&parent_idx).unwrap();
+                let parent_state = self.states.get_mut(&parent_idx).unwrap().get_mut();
                 let Some(prop) = parent_state.get_child_index(&id) else {
                     debug_log::group_end!();
                     return None;
@@ -757,7 +819,7 @@ fn bring_back_sub_container(
     queue: &mut Vec<InternalContainerDiff>,
     mark_bring_back: &mut FxHashSet<ContainerIdx>,
     all_idx: &FxHashSet<ContainerIdx>,
-    states: &mut FxHashMap<ContainerIdx, State>,
+    states: &mut FxHashMap<ContainerIdx, LazyState>,
     idx2state: &mut FxHashMap<ContainerIdx, Diff>,
     arena: &SharedArena,
 ) {
@@ -776,7 +838,7 @@ fn bring_back_sub_container(
                                 // If the state is not empty, add this to queue and check
                                 // whether there are sub-containers created by it recursively
                                 // and finally cache the state
-                                let diff = state.to_diff();
+                                let diff = state.get_mut().to_diff();
                                 if !diff.is_empty() {
                                     queue.push(InternalContainerDiff {
                                         idx,
@@ -808,7 +870,7 @@ fn bring_back_sub_container(
                     if all_idx.contains(&idx) {
                         mark_bring_back.insert(idx);
                     } else if let Some(state) = states.get_mut(&idx) {
-                        let diff = state.to_diff();
+                        let diff = state.get_mut().to_diff();
                         if !diff.is_empty() {
                             queue.push(InternalContainerDiff {
                                 idx,
@@ -29,12 +29,27 @@ pub struct ListState {
 
 impl Clone for ListState {
     fn clone(&self) -> Self {
+        // `BTree::clone` doesn't keep the slots of the internal nodes in their parents,
+        // so the list is rebuilt from its elements instead
+        let list: BTree<ListImpl> = self.list.iter().cloned().collect();
+        let mut child_container_to_leaf = FxHashMap::default();
+        let mut cursor = (!list.is_empty()).then(|| Cursor {
+            leaf: list.first_leaf().unwrap_leaf().into(),
+            offset: 0,
+        });
+        while let Some(c) = cursor {
+            if let LoroValue::Container(id) = &list.get_elem(c.leaf).unwrap().v {
+                child_container_to_leaf.insert(id.clone(), c.leaf);
+            }
+            cursor = list.next_elem(c);
+        }
+
         Self {
             idx: self.idx,
-            list: self.list.clone(),
+            list,
             in_txn: false,
             undo_stack: Vec::new(),
-            child_container_to_leaf: Default::default(),
+            child_container_to_leaf,
         }
     }
 }
@@ -479,4 +494,29 @@ mod test {
         assert_eq!(list.get_child_container_index(&id("x")), Some(0));
         assert_eq!(list.get_child_container_index(&id("abc")), Some(2));
     }
+
+    #[test]
+    fn edit_cloned_state() {
+        let mut list = ListState::new(ContainerIdx::from_index_and_type(
+            0,
+            loro_common::ContainerType::List,
+        ));
+        fn id(i: usize) -> ContainerID {
+            ContainerID::new_root(&i.to_string(), crate::ContainerType::List)
+        }
+        for i in 0..1000 {
+            list.insert(0, LoroValue::Container(id(i)));
+        }
+
+        let mut cloned = list.clone();
+        assert_eq!(cloned.get_child_container_index(&id(999)), Some(0));
+        assert_eq!(cloned.get_child_container_index(&id(0)), Some(999));
+        for i in 0..100 {
+            cloned.insert(i * 7, LoroValue::Bool(true));
+            list.insert(i * 7, LoroValue::Bool(true));
+        }
+
+        assert_eq!(cloned.get_value(), list.get_value());
+        assert_eq!(cloned.get_child_container_index(&id(0)), Some(1099));
+    }
 }
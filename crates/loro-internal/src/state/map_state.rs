@@ -24,7 +24,7 @@ pub struct MapState {
 
 impl ContainerState for MapState {
     fn apply_diff_and_convert(&mut self, diff: InternalDiff, arena: &SharedArena) -> Diff {
-        let InternalDiff::Map(delta) = diff else {
+        let InternalDiff::Map(mut delta) = diff else {
             unreachable!()
         };
 
@@ -35,6 +35,9 @@ impl ContainerState for MapState {
             }
 
             let old = self.map.insert(key.clone(), value.clone());
+            delta
+                .old_values
+                .insert(key.clone(), old.as_ref().and_then(|x| x.value.clone()));
             self.store_txn_snapshot(key.clone(), old);
         }
 
@@ -107,6 +110,8 @@ impl ContainerState for MapState {
     fn to_diff(&mut self) -> Diff {
         Diff::NewMap(crate::delta::MapDelta {
             updated: self.map.clone(),
+            // the diff is applied to an empty state
+            old_values: self.map.keys().map(|k| (k.clone(), None)).collect(),
         })
     }
 
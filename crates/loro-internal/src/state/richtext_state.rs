@@ -3,7 +3,7 @@ use std::{ops::Range, sync::Arc};
 use fxhash::FxHashMap;
 use generic_btree::rle::{HasLength, Mergeable};
 use loro_common::{Counter, LoroResult, LoroValue, PeerID, ID};
-use loro_preload::{CommonArena, EncodedRichtextState, TempArena, TextRanges};
+use loro_preload::{EncodedRichtextState, TextRanges};
 
 use crate::{
     arena::SharedArena,
@@ -538,8 +538,8 @@ impl RichtextState {
             styles,
             is_style_start,
         }: EncodedRichtextState,
-        state_arena: &TempArena,
-        common: &CommonArena,
+        keywords: &[InternalString],
+        peer_ids: &[PeerID],
         arena: &SharedArena,
     ) {
         assert!(self.is_empty());
@@ -568,9 +568,9 @@ impl RichtextState {
                     loader.push(RichtextStateChunk::new_style(
                         Arc::new(StyleOp {
                             lamport: style_compact.lamport,
-                            peer: common.peer_ids[style_compact.peer_idx as usize],
+                            peer: peer_ids[style_compact.peer_idx as usize],
                             cnt: style_compact.counter as Counter,
-                            key: state_arena.keywords[style_compact.key_idx as usize].clone(),
+                            key: keywords[style_compact.key_idx as usize].clone(),
                             value: style_compact.value.clone(),
                             info: TextStyleInfoFlag::from_byte(style_compact.style_info),
                         }),
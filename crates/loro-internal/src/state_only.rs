@@ -0,0 +1,438 @@
+//! A document that only keeps the state.
+//!
+//! [crate::LoroDoc] keeps the whole history in its [crate::OpLog], which is necessary to merge
+//! concurrent changes and to time travel. Read-mostly consumers like renderers and exporters
+//! only need the latest state, so [StateOnlyDoc] drops the history to save memory.
+//!
+//! Without the history, a change can only be applied when it's based on the current version,
+//! i.e. its deps equal the current frontiers. Then its ops can be applied to the state
+//! as they are. The changes whose deps are missing wait until their deps arrive.
+//!
+//! A change that is concurrent to the current version can't be applied, neither can the
+//! changes that depend on it. They're kept as pending changes until the doc is re-synced
+//! by [StateOnlyDoc::resync_from_snapshot] with a snapshot that includes them.
+
+use std::sync::{Arc, Mutex};
+
+use loro_common::{ContainerType, HasCounterSpan, HasIdSpan, LoroResult, LoroValue};
+use rle::{HasLength, Sliceable};
+
+use crate::{
+    arena::SharedArena,
+    change::{Change, Lamport},
+    container::{idx::ContainerIdx, IntoContainerId},
+    encoding::{decode_changes, encode_snapshot::decode_state_only, parse_header, EncodeMode},
+    op::{RawOp, RemoteOp},
+    state::DocState,
+    txn::Transaction,
+    version::Frontiers,
+    ListHandler, LoroError, MapHandler, TextHandler, TreeHandler, VersionVector,
+};
+
+pub struct StateOnlyDoc {
+    state: Arc<Mutex<DocState>>,
+    arena: SharedArena,
+    /// It's always `None`. The handlers of this doc are read-only
+    txn: Arc<Mutex<Option<Transaction>>>,
+    vv: VersionVector,
+    next_lamport: Lamport,
+    /// The changes whose deps are not applied yet, or that are concurrent to the current version
+    pending: Vec<Change<RemoteOp<'static>>>,
+}
+
+impl Default for StateOnlyDoc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateOnlyDoc {
+    pub fn new() -> Self {
+        let arena = SharedArena::default();
+        Self {
+            state: Arc::new(Mutex::new(DocState::new(arena.clone()))),
+            arena,
+            txn: Arc::new(Mutex::new(None)),
+            vv: Default::default(),
+            next_lamport: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Load the state from the snapshot exported by [crate::LoroDoc::export_snapshot].
+    /// The history in the snapshot is not kept.
+    pub fn from_snapshot(bytes: &[u8]) -> LoroResult<Self> {
+        let (_, mode, input, _) = parse_header(bytes)?;
+        if mode != EncodeMode::Snapshot {
+            return Err(LoroError::DecodeError(
+                "Invalid encode mode".to_string().into(),
+            ));
+        }
+
+        let mut doc = Self::new();
+        let (vv, next_lamport) = decode_state_only(&mut doc.state.lock().unwrap(), &input)?;
+        doc.vv = vv;
+        doc.next_lamport = next_lamport;
+        Ok(doc)
+    }
+
+    /// Re-sync the doc from the snapshot exported by [crate::LoroDoc::export_snapshot], e.g. after
+    /// [LoroError::ConcurrentChange] is returned.
+    ///
+    /// The pending changes that are not included in the snapshot are applied on top of it.
+    /// The handlers got from this doc before are invalidated.
+    pub fn resync_from_snapshot(&mut self, bytes: &[u8]) -> LoroResult<()> {
+        let doc = Self::from_snapshot(bytes)?;
+        let pending = std::mem::take(&mut self.pending);
+        *self = doc;
+        self.apply_changes(pending)
+    }
+
+    /// Apply the updates exported by [crate::LoroDoc::export_from].
+    ///
+    /// The changes that are already applied are skipped, and the changes whose deps
+    /// are missing are kept until their deps are imported.
+    ///
+    /// It returns [LoroError::ConcurrentChange] if a pending change is concurrent to the
+    /// current version. The other changes are still applied. The doc needs to be re-synced
+    /// by [StateOnlyDoc::resync_from_snapshot] to apply the concurrent change and the
+    /// changes depending on it.
+    pub fn import(&mut self, bytes: &[u8]) -> LoroResult<()> {
+        let changes = decode_changes(bytes)?;
+        self.apply_changes(changes)
+    }
+
+    fn apply_changes(&mut self, mut changes: Vec<Change<RemoteOp<'static>>>) -> LoroResult<()> {
+        changes.append(&mut self.pending);
+        let mut concurrent = None;
+        loop {
+            let mut applied = false;
+            let mut iter = std::mem::take(&mut changes).into_iter();
+            while let Some(change) = iter.next() {
+                let known = self.vv.get(&change.id.peer).copied().unwrap_or(0);
+                if change.ctr_end() <= known {
+                    continue;
+                }
+
+                let change = if change.id.counter < known {
+                    change.slice((known - change.id.counter) as usize, change.atom_len())
+                } else {
+                    change
+                };
+                if !change.deps.iter().all(|dep| self.vv.includes_id(*dep)) {
+                    changes.push(change);
+                    continue;
+                }
+
+                if !is_same_frontiers(&change.deps, &self.state.lock().unwrap().frontiers) {
+                    concurrent.get_or_insert(change.id);
+                    changes.push(change);
+                    continue;
+                }
+
+                if let Err(e) = self.apply_change(change) {
+                    changes.extend(iter);
+                    self.pending = changes;
+                    return Err(e);
+                }
+
+                applied = true;
+            }
+
+            if !applied {
+                break;
+            }
+        }
+
+        self.pending = changes;
+        match concurrent {
+            Some(id) => Err(LoroError::ConcurrentChange { id }),
+            None => Ok(()),
+        }
+    }
+
+    fn apply_change(&mut self, change: Change<RemoteOp>) -> LoroResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let lamport = self.next_lamport;
+        state.start_txn(Default::default(), false);
+        for op in change.ops.iter() {
+            let raw_op = RawOp {
+                id: change.id.inc(op.counter - change.id.counter),
+                lamport: lamport + (op.counter - change.id.counter) as Lamport,
+                container: self.arena.register_container(&op.container),
+                content: op.content.clone(),
+            };
+            let op = self.arena.convert_raw_op(&raw_op);
+            if let Err(e) = state.apply_local_op(&raw_op, &op) {
+                state.abort_txn();
+                return Err(e);
+            }
+        }
+
+        state.commit_txn(Frontiers::from_id(change.id_last()), None);
+        self.vv.extend_to_include_last_id(change.id_last());
+        self.next_lamport = lamport + change.atom_len() as Lamport;
+        Ok(())
+    }
+
+    /// The version of the applied changes
+    #[inline]
+    pub fn vv(&self) -> &VersionVector {
+        &self.vv
+    }
+
+    #[inline]
+    pub fn frontiers(&self) -> Frontiers {
+        self.state.lock().unwrap().frontiers.clone()
+    }
+
+    /// The number of the changes that are waiting for their deps or for a re-sync
+    #[inline]
+    pub fn pending_changes_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    #[inline]
+    pub fn get_deep_value(&self) -> LoroValue {
+        self.state.lock().unwrap().get_deep_value()
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_text<I: IntoContainerId>(&self, id: I) -> TextHandler {
+        let idx = self.get_container_idx(id, ContainerType::Text);
+        TextHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_list<I: IntoContainerId>(&self, id: I) -> ListHandler {
+        let idx = self.get_container_idx(id, ContainerType::List);
+        ListHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_map<I: IntoContainerId>(&self, id: I) -> MapHandler {
+        let idx = self.get_container_idx(id, ContainerType::Map);
+        MapHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    /// The returned handler is read-only
+    #[inline]
+    pub fn get_tree<I: IntoContainerId>(&self, id: I) -> TreeHandler {
+        let idx = self.get_container_idx(id, ContainerType::Tree);
+        TreeHandler::new(Arc::downgrade(&self.txn), idx, Arc::downgrade(&self.state))
+    }
+
+    fn get_container_idx<I: IntoContainerId>(&self, id: I, c_type: ContainerType) -> ContainerIdx {
+        let id = id.into_container_id(&self.arena, c_type);
+        self.arena.register_container(&id)
+    }
+}
+
+fn is_same_frontiers(a: &Frontiers, b: &Frontiers) -> bool {
+    a.len() == b.len() && a.iter().all(|id| b.contains(id))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{container::richtext::TextStyleInfoFlag, LoroDoc, StateOnlyDoc, ToJson};
+    use loro_common::{ContainerType, LoroError, LoroValue};
+
+    fn edit(doc: &LoroDoc, i: i32) {
+        let text = doc.get_text("text");
+        text.insert_(text.len_unicode(), &"abc".repeat(i as usize + 1))
+            .unwrap();
+        if i % 2 == 1 {
+            text.delete_(1, 2).unwrap();
+            text.mark_(0, 2, "bold", true.into(), TextStyleInfoFlag::BOLD)
+                .unwrap();
+        }
+        let map = doc.get_map("map");
+        map.insert_("key", i.into()).unwrap();
+        let list = map
+            .insert_container_("list", ContainerType::List)
+            .unwrap()
+            .into_list()
+            .unwrap();
+        list.insert_(0, i.into()).unwrap();
+        let tree = doc.get_tree("tree");
+        let node = tree.create_().unwrap();
+        tree.get_meta(node).unwrap().insert_("i", i.into()).unwrap();
+        doc.commit_then_renew();
+    }
+
+    /// The order of the tree nodes in the deep value is not stable
+    fn sorted(mut value: LoroValue) -> LoroValue {
+        let map = Arc::make_mut(value.as_map_mut().unwrap());
+        if let Some(LoroValue::List(nodes)) = map.get_mut("tree") {
+            Arc::make_mut(nodes).sort_by_key(|node| node.as_map().unwrap()["id"].to_json());
+        }
+        value
+    }
+
+    #[test]
+    fn apply_sequential_updates() {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_peer_id(1).unwrap();
+        let mut state_doc = StateOnlyDoc::new();
+        for i in 0..10 {
+            let vv = doc.oplog_vv();
+            edit(&doc, i);
+            state_doc.import(&doc.export_from(&vv)).unwrap();
+            assert_eq!(
+                sorted(state_doc.get_deep_value()),
+                sorted(doc.get_deep_value())
+            );
+        }
+
+        assert_eq!(state_doc.vv(), &doc.oplog_vv());
+        assert_eq!(state_doc.frontiers(), doc.oplog_frontiers());
+        assert_eq!(
+            state_doc.get_text("text").get_richtext_value(),
+            doc.get_text("text").get_richtext_value()
+        );
+        // the handlers are read-only
+        assert!(state_doc.get_text("text").insert_(0, "a").is_err());
+
+        // the changes from another peer based on the latest version
+        let remote = LoroDoc::new_auto_commit();
+        remote.set_peer_id(2).unwrap();
+        remote.import(&doc.export_snapshot()).unwrap();
+        let vv = remote.oplog_vv();
+        edit(&remote, 10);
+        // the known changes are skipped
+        state_doc
+            .import(&remote.export_from(&Default::default()))
+            .unwrap();
+        state_doc.import(&remote.export_from(&vv)).unwrap();
+        assert_eq!(
+            sorted(state_doc.get_deep_value()),
+            sorted(remote.get_deep_value())
+        );
+    }
+
+    #[test]
+    fn load_from_snapshot() {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_peer_id(1).unwrap();
+        for i in 0..5 {
+            edit(&doc, i);
+        }
+
+        let mut state_doc = StateOnlyDoc::from_snapshot(&doc.export_snapshot()).unwrap();
+        assert_eq!(
+            sorted(state_doc.get_deep_value()),
+            sorted(doc.get_deep_value())
+        );
+        assert_eq!(state_doc.vv(), &doc.oplog_vv());
+        let vv = doc.oplog_vv();
+        edit(&doc, 5);
+        state_doc.import(&doc.export_from(&vv)).unwrap();
+        assert_eq!(
+            sorted(state_doc.get_deep_value()),
+            sorted(doc.get_deep_value())
+        );
+        assert!(StateOnlyDoc::from_snapshot(&doc.export_from(&vv)).is_err());
+    }
+
+    #[test]
+    fn pending_and_concurrent_changes() {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_peer_id(1).unwrap();
+        let mut updates = Vec::new();
+        for i in 0..3 {
+            let vv = doc.oplog_vv();
+            edit(&doc, i);
+            updates.push(doc.export_from(&vv));
+        }
+
+        let mut state_doc = StateOnlyDoc::new();
+        state_doc.import(&updates[2]).unwrap();
+        state_doc.import(&updates[1]).unwrap();
+        assert_eq!(state_doc.pending_changes_len(), 2);
+        assert_eq!(state_doc.get_deep_value(), LoroDoc::new().get_deep_value());
+        state_doc.import(&updates[0]).unwrap();
+        assert_eq!(state_doc.pending_changes_len(), 0);
+        assert_eq!(
+            sorted(state_doc.get_deep_value()),
+            sorted(doc.get_deep_value())
+        );
+
+        // a change that is concurrent to the current version is rejected
+        let remote = LoroDoc::new_auto_commit();
+        remote.set_peer_id(2).unwrap();
+        remote.import(&updates[0]).unwrap();
+        let vv = remote.oplog_vv();
+        remote
+            .get_map("map")
+            .insert_("key", "remote".into())
+            .unwrap();
+        remote.commit_then_renew();
+        let err = state_doc.import(&remote.export_from(&vv)).unwrap_err();
+        assert!(matches!(err, LoroError::ConcurrentChange { .. }));
+        assert_eq!(state_doc.pending_changes_len(), 1);
+        assert_eq!(state_doc.get_map("map").get("key"), Some(LoroValue::I32(2)));
+    }
+
+    #[test]
+    fn concurrent_changes_wait_for_a_resync() {
+        let a = LoroDoc::new_auto_commit();
+        a.set_peer_id(1).unwrap();
+        let b = LoroDoc::new_auto_commit();
+        b.set_peer_id(2).unwrap();
+        edit(&a, 0);
+        b.import(&a.export_snapshot()).unwrap();
+        let mut state_doc = StateOnlyDoc::from_snapshot(&a.export_snapshot()).unwrap();
+
+        // the peers edit concurrently
+        let vv_a = a.oplog_vv();
+        edit(&a, 1);
+        let vv_b = b.oplog_vv();
+        edit(&b, 2);
+        state_doc.import(&a.export_from(&vv_a)).unwrap();
+        let err = state_doc.import(&b.export_from(&vv_b)).unwrap_err();
+        assert!(matches!(err, LoroError::ConcurrentChange { .. }));
+        assert_eq!(state_doc.pending_changes_len(), 1);
+
+        // the changes depending on the concurrent change are kept too
+        let vv_b = b.oplog_vv();
+        edit(&b, 3);
+        let err = state_doc.import(&b.export_from(&vv_b)).unwrap_err();
+        assert!(matches!(err, LoroError::ConcurrentChange { .. }));
+        assert_eq!(state_doc.pending_changes_len(), 2);
+
+        // the changes based on the current version are still applied
+        let vv_a = a.oplog_vv();
+        edit(&a, 4);
+        assert!(state_doc.import(&a.export_from(&vv_a)).is_err());
+        assert_eq!(state_doc.get_map("map").get("key"), Some(LoroValue::I32(4)));
+
+        a.import(&b.export_from(&Default::default())).unwrap();
+        let snapshot = a.export_snapshot();
+        let vv_a = a.oplog_vv();
+        edit(&a, 5);
+        assert!(state_doc.import(&a.export_from(&vv_a)).is_err());
+        assert_eq!(state_doc.pending_changes_len(), 3);
+
+        // the change after the snapshot is applied on top of it
+        state_doc.resync_from_snapshot(&snapshot).unwrap();
+        assert_eq!(state_doc.pending_changes_len(), 0);
+        assert_eq!(state_doc.vv(), &a.oplog_vv());
+        assert_eq!(
+            sorted(state_doc.get_deep_value()),
+            sorted(a.get_deep_value())
+        );
+
+        let vv_a = a.oplog_vv();
+        edit(&a, 6);
+        state_doc.import(&a.export_from(&vv_a)).unwrap();
+        assert_eq!(
+            sorted(state_doc.get_deep_value()),
+            sorted(a.get_deep_value())
+        );
+    }
+}
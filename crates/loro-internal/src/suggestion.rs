@@ -0,0 +1,405 @@
+//! Suggestion branches.
+//!
+//! A [SuggestionBranch] is a fork of a [LoroDoc] created from the version of its state.
+//! Reviewers propose edits on the branch without touching the main line. Every change
+//! on the branch after the base version is a suggestion, which can be accepted to be
+//! imported into the main doc, or rejected to be discarded from the branch.
+//!
+//! The consecutive commits of a peer may be merged into one change by the [crate::OpLog],
+//! so they are listed as one suggestion.
+
+use fxhash::FxHashMap;
+use loro_common::{Counter, HasCounterSpan, HasIdSpan, LoroError, LoroResult, PeerID, ID};
+use rle::HasLength;
+
+use crate::{
+    change::{Change, Lamport, Timestamp},
+    version::Frontiers,
+    LoroDoc, VersionVector,
+};
+
+/// A fork of a [LoroDoc] to propose edits on, created by [LoroDoc::create_suggestion].
+///
+/// It remembers which suggestions are accepted, so they are not listed again.
+pub struct SuggestionBranch {
+    name: String,
+    base: Frontiers,
+    doc: LoroDoc,
+    /// The version of the base and the accepted suggestions
+    accepted: VersionVector,
+}
+
+/// The part of a change on the branch that is not accepted yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    /// The id of the first op, pass it to [SuggestionBranch::accept] or [SuggestionBranch::reject]
+    pub id: ID,
+    /// The number of atom ops
+    pub len: usize,
+    /// The lamport of the first op
+    pub lamport: Lamport,
+    /// The timestamp of the change in seconds
+    pub timestamp: Timestamp,
+    /// The ops it depends on, they may be accepted or in the base version already
+    pub deps: Frontiers,
+}
+
+impl SuggestionBranch {
+    pub(crate) fn new(doc: &LoroDoc, name: String) -> LoroResult<Self> {
+        doc.commit_then_renew();
+        let base = doc.state_frontiers();
+        let oplog = doc.oplog().lock().unwrap();
+        let base_vv = oplog.dag.frontiers_to_vv(&base).unwrap();
+        let bytes = oplog.export_between(&Default::default(), &base_vv);
+        drop(oplog);
+        let branch = LoroDoc::new_auto_commit();
+        branch.import(&bytes)?;
+        Ok(Self {
+            name,
+            base,
+            doc: branch,
+            accepted: base_vv,
+        })
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version of the main doc that the branch is created from
+    #[inline]
+    pub fn base(&self) -> &Frontiers {
+        &self.base
+    }
+
+    /// The forked doc to edit the suggestions on.
+    ///
+    /// Its handlers, subscriptions and config are kept when a suggestion is rejected.
+    #[inline]
+    pub fn doc(&self) -> &LoroDoc {
+        &self.doc
+    }
+
+    /// The suggestions that are not accepted yet, in the causal order
+    pub fn suggestions(&self) -> Vec<Suggestion> {
+        self.doc.commit_then_renew();
+        let oplog = self.doc.oplog().lock().unwrap();
+        let mut ans = Vec::new();
+        for changes in oplog.changes().values() {
+            for change in changes.iter() {
+                if let Some(start) = self.pending_start(change) {
+                    let offset = start - change.id.counter;
+                    ans.push(Suggestion {
+                        id: change.id.inc(offset),
+                        len: change.atom_len() - offset as usize,
+                        lamport: change.lamport + offset as Lamport,
+                        timestamp: change.timestamp,
+                        deps: if offset == 0 {
+                            change.deps.clone()
+                        } else {
+                            Frontiers::from_id(change.id.inc(offset - 1))
+                        },
+                    });
+                }
+            }
+        }
+
+        ans.sort_by_key(|x| (x.lamport, x.id.peer));
+        ans
+    }
+
+    /// Accept the suggestion that contains `id` by importing it into `doc`.
+    /// The suggestions it depends on are accepted too.
+    pub fn accept(&mut self, doc: &LoroDoc, id: ID) -> LoroResult<()> {
+        self.doc.commit_then_renew();
+        let oplog = self.doc.oplog().lock().unwrap();
+        let Some(change) = oplog.lookup_change(id) else {
+            return Err(not_found(id));
+        };
+
+        let vv = oplog
+            .dag
+            .frontiers_to_vv(&Frontiers::from_id(change.id_last()))
+            .unwrap();
+        let bytes = oplog.export_between(&doc.oplog_vv(), &vv);
+        drop(oplog);
+        doc.import(&bytes)?;
+        self.accepted.merge(&vv);
+        Ok(())
+    }
+
+    /// Accept all the suggestions by importing them into `doc`
+    pub fn accept_all(&mut self, doc: &LoroDoc) -> LoroResult<()> {
+        doc.import(&self.doc.export_from(&doc.oplog_vv()))?;
+        self.accepted = self.doc.oplog_vv();
+        Ok(())
+    }
+
+    /// Reject the suggestion that contains `id`. It's discarded from the branch,
+    /// along with the suggestions that depend on it.
+    ///
+    /// The history of the branch is truncated in place, so its handlers and subscriptions
+    /// stay valid. If the changes of its own peer are discarded, it uses a new peer id
+    /// afterwards so that their ids are never reused.
+    pub fn reject(&mut self, id: ID) -> LoroResult<()> {
+        self.doc.commit_then_renew();
+        let oplog = self.doc.oplog().lock().unwrap();
+        let Some((rejected, rejected_lamport)) = oplog.lookup_change(id).and_then(|change| {
+            self.pending_start(change).map(|start| {
+                (
+                    ID::new(id.peer, start),
+                    change.lamport + (start - change.id.counter) as Lamport,
+                )
+            })
+        }) else {
+            return if self.accepted.includes_id(id) {
+                Err(LoroError::ArgErr(
+                    format!("{} is accepted or in the base version", id).into_boxed_str(),
+                ))
+            } else {
+                Err(not_found(id))
+            };
+        };
+
+        // Walk forward from the rejected change in the causal order. A change is discarded
+        // if it depends on a discarded op, and so are the following changes of its peer.
+        let mut later: Vec<&Change> = oplog
+            .changes()
+            .values()
+            .flat_map(|changes| changes.iter())
+            .filter(|change| change.lamport + change.atom_len() as Lamport > rejected_lamport)
+            .collect();
+        later.sort_unstable_by_key(|change| change.lamport);
+        // peer -> the first discarded counter
+        let mut discarded: FxHashMap<PeerID, Counter> = FxHashMap::default();
+        discarded.insert(rejected.peer, rejected.counter);
+        for change in later {
+            if discarded.contains_key(&change.id.peer) {
+                continue;
+            }
+
+            if change
+                .deps
+                .iter()
+                .any(|dep| matches!(discarded.get(&dep.peer), Some(&start) if dep.counter >= start))
+            {
+                discarded.insert(change.id.peer, change.id.counter);
+            }
+        }
+
+        let mut kept = oplog.vv().clone();
+        for (peer, start) in discarded {
+            kept.insert(peer, start);
+        }
+
+        drop(oplog);
+        self.doc.truncate(&kept)
+    }
+
+    /// The start counter of the part of the change that is not accepted
+    fn pending_start(&self, change: &Change) -> Option<Counter> {
+        let start = self
+            .accepted
+            .get(&change.id.peer)
+            .copied()
+            .unwrap_or(0)
+            .max(change.id.counter);
+        (start < change.ctr_end()).then_some(start)
+    }
+}
+
+fn not_found(id: ID) -> LoroError {
+    LoroError::NotFoundError(format!("Cannot find the suggestion {}", id).into_boxed_str())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        container::richtext::{StyleConfigMap, TextStyleInfoFlag},
+        LoroDoc, SuggestionBranch,
+    };
+    use loro_common::{LoroError, ID};
+
+    fn text_of(doc: &LoroDoc) -> String {
+        doc.get_text("text")
+            .get_value()
+            .as_string()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Make the suggestions on the branch. The timestamps are far apart, so that the commits
+    /// are not merged into one change.
+    fn suggest(branch: &SuggestionBranch) {
+        let doc = branch.doc();
+        doc.set_peer_id(2).unwrap();
+        let text = doc.get_text("text");
+        text.insert_(5, ",").unwrap();
+        doc.commit_with(None, Some(10_000), true);
+        text.insert_(12, "!").unwrap();
+        doc.commit_with(None, Some(20_000), true);
+        text.delete_(0, 5).unwrap();
+        doc.commit_with(None, Some(30_000), true);
+    }
+
+    fn new_doc() -> LoroDoc {
+        let doc = LoroDoc::new_auto_commit();
+        doc.set_peer_id(1).unwrap();
+        doc.get_text("text").insert_(0, "hello world").unwrap();
+        doc.commit_with(None, Some(0), true);
+        doc
+    }
+
+    #[test]
+    fn accept_and_reject_suggestions() {
+        let doc = new_doc();
+        let mut branch = doc.create_suggestion("review").unwrap();
+        assert_eq!(branch.name(), "review");
+        assert_eq!(branch.base(), &doc.oplog_frontiers());
+        suggest(&branch);
+        assert_eq!(text_of(branch.doc()), ", world!");
+        assert_eq!(text_of(&doc), "hello world");
+
+        let suggestions = branch.suggestions();
+        assert_eq!(
+            suggestions
+                .iter()
+                .map(|x| (x.id, x.len, x.timestamp))
+                .collect::<Vec<_>>(),
+            vec![
+                (ID::new(2, 0), 1, 10_000),
+                (ID::new(2, 1), 1, 20_000),
+                (ID::new(2, 2), 5, 30_000),
+            ]
+        );
+        assert_eq!(suggestions[0].deps, ID::new(1, 10).into());
+
+        // the main line keeps moving
+        doc.get_text("text").insert_(0, ">").unwrap();
+        doc.commit_then_renew();
+        branch.accept(&doc, ID::new(2, 0)).unwrap();
+        assert_eq!(text_of(&doc), ">hello, world");
+        assert_eq!(branch.suggestions().len(), 2);
+
+        // the suggestion depending on the rejected one is discarded too
+        branch.reject(ID::new(2, 1)).unwrap();
+        assert!(branch.suggestions().is_empty());
+        assert_eq!(text_of(branch.doc()), "hello, world");
+        assert_ne!(branch.doc().peer_id(), 2);
+        assert!(matches!(
+            branch.reject(ID::new(2, 0)),
+            Err(LoroError::ArgErr(_))
+        ));
+        assert!(matches!(
+            branch.reject(ID::new(2, 1)),
+            Err(LoroError::NotFoundError(_))
+        ));
+
+        branch.doc().get_text("text").insert_(0, "oh, ").unwrap();
+        assert_eq!(branch.suggestions().len(), 1);
+        branch.accept_all(&doc).unwrap();
+        assert_eq!(text_of(&doc), ">oh, hello, world");
+        assert!(branch.suggestions().is_empty());
+    }
+
+    #[test]
+    fn edit_the_branch_after_a_reject() {
+        let doc = new_doc();
+        let mut branch = doc.create_suggestion("review").unwrap();
+        let mut config = StyleConfigMap::new();
+        config.insert("highlight".into(), TextStyleInfoFlag::COMMENT);
+        branch.doc().config_text_style(config);
+        let events = Arc::new(Mutex::new(0));
+        let events_clone = events.clone();
+        branch.doc().subscribe_root(Arc::new(move |_| {
+            *events_clone.lock().unwrap() += 1;
+        }));
+        let text = branch.doc().get_text("text");
+        suggest(&branch);
+
+        *events.lock().unwrap() = 0;
+        branch.reject(ID::new(2, 1)).unwrap();
+        assert_eq!(text_of(branch.doc()), "hello, world");
+        assert_eq!(*events.lock().unwrap(), 1);
+        assert_eq!(
+            branch.doc().text_style_config().get("highlight"),
+            TextStyleInfoFlag::COMMENT
+        );
+
+        // the handler acquired before the reject still works
+        text.insert_(0, "oh, ").unwrap();
+        branch.doc().commit_then_renew();
+        assert_eq!(text_of(branch.doc()), "oh, hello, world");
+        assert_eq!(*events.lock().unwrap(), 2);
+        assert_eq!(branch.suggestions().len(), 2);
+        branch.accept_all(&doc).unwrap();
+        assert_eq!(text_of(&doc), "oh, hello, world");
+    }
+
+    #[test]
+    fn accept_with_deps() {
+        let doc = new_doc();
+        let mut branch = doc.create_suggestion("review").unwrap();
+        suggest(&branch);
+        branch.accept(&doc, ID::new(2, 1)).unwrap();
+        assert_eq!(text_of(&doc), "hello, world!");
+        let suggestions = branch.suggestions();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].id, ID::new(2, 2));
+    }
+
+    #[test]
+    fn branch_from_detached_state() {
+        let mut doc = new_doc();
+        let v1 = doc.oplog_frontiers();
+        doc.get_text("text").insert_(0, "x").unwrap();
+        doc.commit_then_renew();
+        doc.checkout(&v1).unwrap();
+
+        let branch = doc.create_suggestion("old").unwrap();
+        assert_eq!(branch.base(), &v1);
+        assert_eq!(text_of(branch.doc()), "hello world");
+        assert_eq!(branch.doc().oplog_frontiers(), v1);
+    }
+
+    #[test]
+    fn reject_discards_the_dependents_of_other_peers() {
+        let doc = new_doc();
+        let mut branch = doc.create_suggestion("review").unwrap();
+        branch.doc().set_peer_id(2).unwrap();
+        branch.doc().get_text("text").insert_(0, "A").unwrap();
+        branch.doc().commit_then_renew();
+
+        // peer 3 edits on top of the suggestion of peer 2
+        let reviewer = LoroDoc::new_auto_commit();
+        reviewer.set_peer_id(3).unwrap();
+        reviewer.import(&branch.doc().export_snapshot()).unwrap();
+        reviewer.get_text("text").insert_(0, "B").unwrap();
+        reviewer.commit_then_renew();
+        // peer 4 edits concurrently
+        let concurrent = LoroDoc::new_auto_commit();
+        concurrent.set_peer_id(4).unwrap();
+        concurrent.import(&doc.export_snapshot()).unwrap();
+        concurrent.get_text("text").insert_(11, "C").unwrap();
+        concurrent.commit_then_renew();
+        branch
+            .doc()
+            .import(&reviewer.export_from(&Default::default()))
+            .unwrap();
+        branch
+            .doc()
+            .import(&concurrent.export_from(&Default::default()))
+            .unwrap();
+        assert_eq!(text_of(branch.doc()), "BAhello worldC");
+        assert_eq!(branch.suggestions().len(), 3);
+
+        branch.reject(ID::new(2, 0)).unwrap();
+        assert_eq!(text_of(branch.doc()), "hello worldC");
+        let suggestions = branch.suggestions();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].id, ID::new(4, 0));
+    }
+}
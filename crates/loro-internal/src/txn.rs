@@ -24,6 +24,7 @@ use crate::{
     op::{Op, RawOp, RawOpContent},
     span::HasIdSpan,
     version::Frontiers,
+    version_tag::{is_version_tags, VERSION_TAGS_CONTAINER},
     InternalString, LoroError, LoroValue,
 };
 
@@ -54,6 +55,8 @@ pub struct Transaction {
     finished: bool,
     on_commit: Option<OnCommitFn>,
     timestamp: Option<Timestamp>,
+    /// Whether the reserved container of the version tags can be edited
+    pub(crate) write_version_tags: bool,
 }
 
 /// We can infer local events directly from the local behavior. This enum is used to
@@ -90,6 +93,8 @@ pub(super) enum EventHint {
     Map {
         key: InternalString,
         value: Option<LoroValue>,
+        /// The value before this op, used by the event
+        old_value: Option<LoroValue>,
     },
     Tree(SmallVec<[TreeDiffItem; 2]>),
     MarkEnd,
@@ -224,6 +229,7 @@ impl Transaction {
             finished: false,
             on_commit: None,
             timestamp: None,
+            write_version_tags: false,
         }
     }
 
@@ -298,7 +304,8 @@ impl Transaction {
             None
         };
 
-        let last_id = change.id_last();
+        // It's different from the frontiers of the oplog when the state is detached
+        let new_frontiers = Frontiers::from_id(change.id_last());
         if let Err(err) = oplog.import_local_change(change, true) {
             drop(state);
             drop(oplog);
@@ -307,7 +314,7 @@ impl Transaction {
         }
 
         state.commit_txn(
-            Frontiers::from_id(last_id),
+            new_frontiers.clone(),
             diff.map(|arr| InternalDocDiff {
                 local: true,
                 origin: self.origin.clone(),
@@ -322,7 +329,7 @@ impl Transaction {
                         .collect(),
                 ),
                 from_checkout: false,
-                new_version: Cow::Borrowed(oplog.frontiers()),
+                new_version: Cow::Owned(new_frontiers),
             }),
         );
         drop(state);
@@ -348,6 +355,16 @@ impl Transaction {
             });
         }
 
+        if !self.write_version_tags && is_version_tags(&self.arena, container) {
+            return Err(LoroError::ArgErr(
+                format!(
+                    "{} is reserved for the version tags",
+                    VERSION_TAGS_CONTAINER
+                )
+                .into_boxed_str(),
+            ));
+        }
+
         let len = content.content_len();
         let raw_op = RawOp {
             id: ID {
@@ -426,8 +443,8 @@ impl Transaction {
     where
         F: FnOnce(&State) -> R,
     {
-        let state = self.state.lock().unwrap();
-        f(state.get_state(idx).unwrap())
+        let mut state = self.state.lock().unwrap();
+        f(state.get_state_mut(idx).unwrap())
     }
 
     pub fn next_id(&self) -> ID {
@@ -593,16 +610,24 @@ fn change_to_diff(
                         diff: Diff::List(Delta::new().retain(s.start() as usize).delete(s.len())),
                     });
                 }
-                EventHint::Map { key, value } => ans.push(TxnContainerDiff {
+                EventHint::Map {
+                    key,
+                    value,
+                    old_value,
+                } => ans.push(TxnContainerDiff {
                     idx: op.container,
-                    diff: Diff::NewMap(crate::delta::MapDelta::new().with_entry(
-                        key,
-                        MapValue {
-                            counter: op.counter,
-                            value,
-                            lamport: (lamport, peer),
-                        },
-                    )),
+                    diff: Diff::NewMap(
+                        crate::delta::MapDelta::new()
+                            .with_old_value(key.clone(), old_value)
+                            .with_entry(
+                                key,
+                                MapValue {
+                                    counter: op.counter,
+                                    value,
+                                    lamport: (lamport, peer),
+                                },
+                            ),
+                    ),
                 }),
                 EventHint::Tree(tree_diff) => {
                     ans.push(TxnContainerDiff {
@@ -13,6 +13,14 @@ impl<Src: Default, Dst: From<Src>> LazyLoad<Src, Dst> {
         LazyLoad::Dst(dst)
     }
 
+    /// Return `None` if it's not loaded yet
+    pub fn get(&self) -> Option<&Dst> {
+        match self {
+            LazyLoad::Src(_) => None,
+            LazyLoad::Dst(dst) => Some(dst),
+        }
+    }
+
     pub fn get_mut(&mut self) -> &mut Dst {
         match self {
             LazyLoad::Src(src) => {
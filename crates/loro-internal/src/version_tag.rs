@@ -0,0 +1,156 @@
+//! Named version tags.
+//!
+//! The tags are stored in a reserved map of the doc, so they are replicated with
+//! the changes and encoded in the snapshots like the other containers. The value of a tag
+//! is the list of the ids in its frontiers. Its author and timestamp come from the change
+//! that sets it.
+//!
+//! The id of the map, [VERSION_TAGS_CONTAINER], is outside of the ids that the containers
+//! of the users can have. The map is hidden from the values and the events of the state,
+//! and it can only be edited by [crate::LoroDoc::tag] and its siblings.
+
+use std::sync::Arc;
+
+use fxhash::FxHashMap;
+use loro_common::{ContainerType, Counter, HasCounterSpan, LoroValue, PeerID, ID};
+
+use crate::{
+    arena::SharedArena,
+    change::{Change, Lamport, Timestamp},
+    container::idx::ContainerIdx,
+    delta::MapValue,
+    op::InnerContent,
+    version::Frontiers,
+    InternalString, VersionVector,
+};
+
+pub use loro_common::VERSION_TAGS_CONTAINER;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionTag {
+    pub name: String,
+    pub frontiers: Frontiers,
+    /// The peer that created the tag
+    pub author: PeerID,
+    pub timestamp: Timestamp,
+}
+
+pub(crate) fn frontiers_to_value(frontiers: &Frontiers) -> LoroValue {
+    LoroValue::List(Arc::new(
+        frontiers.iter().map(|id| id.to_string().into()).collect(),
+    ))
+}
+
+fn value_to_frontiers(value: &LoroValue) -> Option<Frontiers> {
+    value
+        .as_list()?
+        .iter()
+        .map(|id| ID::try_from(id.as_string()?.as_str()).ok())
+        .collect()
+}
+
+pub(crate) fn is_version_tags(arena: &SharedArena, idx: ContainerIdx) -> bool {
+    idx.get_type() == ContainerType::Map
+        && matches!(arena.idx_to_id(idx), Some(id) if id == VERSION_TAGS_CONTAINER)
+}
+
+/// The latest value of each tag in the [crate::OpLog].
+///
+/// It's read from the history rather than the state, so the tags created after
+/// the version of a detached state are still available. The ops are scanned lazily,
+/// each op only once.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct VersionTagStore {
+    /// The idx of the version tags container, it's `None` until the container is registered
+    idx: Option<ContainerIdx>,
+    /// The ops that are scanned
+    scanned: VersionVector,
+    tags: FxHashMap<InternalString, (MapValue, Timestamp)>,
+}
+
+impl VersionTagStore {
+    /// Scan the changes that are inserted into the [crate::OpLog] since the last update
+    pub(crate) fn update(&mut self, changes: &FxHashMap<PeerID, Vec<Change>>, arena: &SharedArena) {
+        if self.idx.is_none() {
+            self.idx = arena.id_to_idx(&VERSION_TAGS_CONTAINER);
+        }
+
+        for (peer, changes) in changes.iter() {
+            let start = self.scanned.get(peer).copied().unwrap_or(0);
+            let Some(last) = changes.last() else {
+                continue;
+            };
+            if last.ctr_end() <= start {
+                continue;
+            }
+
+            if let Some(idx) = self.idx {
+                let i = changes.partition_point(|change| change.ctr_end() <= start);
+                for change in changes[i..].iter() {
+                    self.apply_change(change, idx, start, arena);
+                }
+            }
+
+            self.scanned.insert(*peer, last.ctr_end());
+        }
+    }
+
+    /// Apply the ops of the change that are not before `start`
+    fn apply_change(
+        &mut self,
+        change: &Change,
+        idx: ContainerIdx,
+        start: Counter,
+        arena: &SharedArena,
+    ) {
+        for op in change.ops.iter() {
+            if op.container != idx || op.counter < start {
+                continue;
+            }
+
+            let InnerContent::Map(set) = &op.content else {
+                unreachable!()
+            };
+            let value = MapValue {
+                counter: op.counter,
+                value: set.value.and_then(|value| arena.get_value(value as usize)),
+                lamport: (
+                    change.lamport + (op.counter - change.id.counter) as Lamport,
+                    change.id.peer,
+                ),
+            };
+            match self.tags.get(&set.key) {
+                Some((old, _)) if old.lamport > value.lamport => {}
+                _ => {
+                    self.tags.insert(set.key.clone(), (value, change.timestamp));
+                }
+            }
+        }
+    }
+
+    /// Get the latest version tags, sorted by their timestamps
+    pub(crate) fn tags(&self) -> Vec<VersionTag> {
+        let mut ans: Vec<VersionTag> = self
+            .tags
+            .iter()
+            .filter_map(|(name, (value, timestamp))| to_tag(name, value, *timestamp))
+            .collect();
+        ans.sort_by(|a, b| (a.timestamp, &a.name).cmp(&(b.timestamp, &b.name)));
+        ans
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<VersionTag> {
+        let name: InternalString = name.into();
+        let (value, timestamp) = self.tags.get(&name)?;
+        to_tag(&name, value, *timestamp)
+    }
+}
+
+fn to_tag(name: &InternalString, value: &MapValue, timestamp: Timestamp) -> Option<VersionTag> {
+    Some(VersionTag {
+        name: name.to_string(),
+        frontiers: value_to_frontiers(value.value.as_ref()?)?,
+        author: value.lamport.1,
+        timestamp,
+    })
+}
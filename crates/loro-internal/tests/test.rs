@@ -2,7 +2,9 @@ use std::sync::{atomic::AtomicBool, Arc, Mutex};
 
 use loro_common::{ContainerID, ContainerType, LoroValue, ID};
 use loro_internal::{
-    container::richtext::TextStyleInfoFlag, version::Frontiers, ApplyDiff, LoroDoc, ToJson,
+    container::richtext::{StyleConfigMap, TextChange, TextChangeKind, TextStyleInfoFlag},
+    version::Frontiers,
+    ApplyDiff, LoroDoc, ToJson,
 };
 use serde_json::json;
 
@@ -310,6 +312,266 @@ fn test_from_snapshot() {
     assert!(matches!(d, loro_common::LoroError::DecodeError(..)));
 }
 
+#[test]
+fn test_snapshot_at() {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    a.get_text("text").insert_(0, "hello").unwrap();
+    a.get_map("map").insert_("key", 1.into()).unwrap();
+    a.commit_then_renew();
+    let v1 = a.oplog_frontiers();
+    let v1_value = a.get_deep_value();
+    a.get_text("text").insert_(5, " world").unwrap();
+    a.get_map("map").insert_("key", 2.into()).unwrap();
+    a.commit_then_renew();
+
+    let snapshot = a.export_snapshot_at(&v1).unwrap();
+    // the state of the source doc is untouched
+    assert_eq!(
+        a.get_deep_value().to_json_value(),
+        json!({"text": "hello world", "map": {"key": 2}})
+    );
+    assert!(!a.is_detached());
+
+    let b = LoroDoc::from_snapshot(&snapshot).unwrap();
+    assert_eq!(b.get_deep_value(), v1_value);
+    assert_eq!(b.oplog_frontiers(), v1);
+    assert_eq!(b.state_frontiers(), v1);
+
+    // the archived doc can still be synced to the latest version
+    b.import(&a.export_from(&b.oplog_vv())).unwrap();
+    assert_eq!(b.get_deep_value(), a.get_deep_value());
+
+    let err = a
+        .export_snapshot_at(&Frontiers::from(vec![ID::new(2, 0)]))
+        .unwrap_err();
+    assert!(matches!(err, loro_common::LoroError::NotFoundError(..)));
+}
+
+#[test]
+fn test_checksum() {
+    let a = LoroDoc::new_auto_commit();
+    a.get_text("text").insert_(0, "hello").unwrap();
+    a.commit_then_renew();
+    for (version, bytes) in (1..=loro_internal::ENCODE_SCHEMA_VERSION).flat_map(|v| {
+        [
+            (v, a.export_snapshot_with_schema(v).unwrap()),
+            (
+                v,
+                a.export_from_with_schema(&Default::default(), v).unwrap(),
+            ),
+        ]
+    }) {
+        // bit flip
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let err = LoroDoc::new().import(&corrupted).unwrap_err();
+        assert!(matches!(
+            err,
+            loro_common::LoroError::ChecksumMismatch { .. }
+        ));
+
+        // truncated
+        let err = LoroDoc::new()
+            .import(&bytes[..bytes.len() - 1])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            loro_common::LoroError::ChecksumMismatch { .. }
+        ));
+
+        if version != 1 {
+            continue;
+        }
+
+        // data of schema v0 has no checksum, it can still be imported
+        let mut legacy = bytes[..6].to_vec();
+        legacy[4] = 0;
+        legacy.extend_from_slice(&bytes[10..]);
+        let b = LoroDoc::new();
+        b.import(&legacy).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+    }
+}
+
+/// The data exported with every supported schema version must stay importable.
+/// These fixtures are frozen, they must not be regenerated.
+#[test]
+fn test_schema_fixtures() {
+    let expected_small = json!({"map": {"key": "value"}, "text": "hello"});
+    let expected_medium = json!({"list": (0..40).collect::<Vec<_>>(), "text": "world"});
+    let expected_large = json!({"text": "0123456789".repeat(2100)});
+    macro_rules! fixture {
+        ($name:literal, $expected:expr) => {
+            (
+                $name,
+                &include_bytes!(concat!("./fixtures/schema/", $name, ".bin"))[..],
+                &$expected,
+            )
+        };
+    }
+
+    #[allow(unused_mut)]
+    let mut fixtures: Vec<(&str, &[u8], &serde_json::Value)> = vec![
+        fixture!("v0/updates", expected_small),
+        fixture!("v0/rle_updates", expected_medium),
+        fixture!("v0/compressed_updates", expected_large),
+        fixture!("v0/snapshot", expected_medium),
+        fixture!("v1/updates", expected_small),
+        fixture!("v1/rle_updates", expected_medium),
+        fixture!("v1/compressed_updates", expected_large),
+        fixture!("v1/snapshot", expected_medium),
+        fixture!("v2/updates", expected_small),
+        fixture!("v2/rle_updates", expected_medium),
+        fixture!("v2/compressed_updates", expected_large),
+        fixture!("v2/snapshot", expected_medium),
+        fixture!("v2/snapshot_deflate", expected_medium),
+        fixture!("v3/updates", expected_small),
+        fixture!("v3/rle_updates", expected_medium),
+        fixture!("v3/compressed_updates", expected_large),
+        fixture!("v3/snapshot", expected_medium),
+    ];
+    #[cfg(feature = "lz4")]
+    fixtures.push(fixture!("v2/snapshot_lz4", expected_medium));
+    #[cfg(feature = "zstd")]
+    fixtures.push(fixture!("v2/snapshot_zstd", expected_medium));
+    #[cfg(not(feature = "lz4"))]
+    {
+        let (_, bytes, _) = fixture!("v2/snapshot_lz4", expected_medium);
+        let err = LoroDoc::new().import(bytes).unwrap_err();
+        assert!(matches!(err, loro_common::LoroError::DecodeError(_)));
+    }
+    for (name, bytes, expected) in fixtures {
+        let doc = LoroDoc::new();
+        doc.import(bytes)
+            .unwrap_or_else(|e| panic!("failed to import {}: {}", name, e));
+        assert_eq!(&doc.get_deep_value().to_json_value(), expected, "{}", name);
+        if name.contains("snapshot") {
+            let doc = LoroDoc::from_snapshot(bytes).unwrap();
+            assert_eq!(&doc.get_deep_value().to_json_value(), expected, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_decode_header_info() {
+    use loro_internal::{decode_header_info, PayloadMode};
+    let info = decode_header_info(include_bytes!("./fixtures/schema/v0/updates.bin")).unwrap();
+    assert_eq!(info.schema_version, 0);
+    assert_eq!(info.mode, PayloadMode::Updates);
+    assert_eq!(info.codec, None);
+    assert_eq!(info.checksum, None);
+    let info = decode_header_info(include_bytes!("./fixtures/schema/v1/snapshot.bin")).unwrap();
+    assert_eq!(info.schema_version, 1);
+    assert_eq!(info.mode, PayloadMode::Snapshot);
+    assert!(info.checksum.is_some());
+    let bytes = include_bytes!("./fixtures/schema/v2/snapshot_deflate.bin");
+    let info = decode_header_info(bytes).unwrap();
+    assert_eq!(info.codec, Some(1));
+    assert!(info.payload_len > bytes.len());
+
+    let doc = LoroDoc::new_auto_commit();
+    doc.get_text("text").insert_(0, "123").unwrap();
+    doc.commit_then_renew();
+    let info = decode_header_info(&doc.export_from(&Default::default())).unwrap();
+    assert_eq!(info.schema_version, loro_internal::ENCODE_SCHEMA_VERSION);
+    assert!(info.signed_spans.is_empty());
+    assert!(decode_header_info(&[1, 2, 3, 4, 5, 6, 7]).is_err());
+}
+
+#[test]
+fn test_compression_codec() {
+    use loro_internal::{CompressionCodec, Deflate, NoCompression};
+
+    let a = LoroDoc::new_auto_commit();
+    let text = a.get_text("text");
+    for i in 0..100 {
+        text.insert_(0, &format!("hello {} ", i)).unwrap();
+        a.commit_then_renew();
+    }
+
+    #[allow(unused_mut)]
+    let mut codecs: Vec<Box<dyn CompressionCodec>> =
+        vec![Box::new(NoCompression), Box::new(Deflate::default())];
+    #[cfg(feature = "lz4")]
+    codecs.push(Box::new(loro_internal::Lz4));
+    #[cfg(feature = "zstd")]
+    codecs.push(Box::new(loro_internal::Zstd::default()));
+    let raw_snapshot_len = a.export_snapshot_with_codec(&NoCompression).unwrap().len();
+    for codec in codecs.iter() {
+        let snapshot = a.export_snapshot_with_codec(codec.as_ref()).unwrap();
+        assert_eq!(snapshot[6], codec.id());
+        if codec.id() != NoCompression.id() {
+            assert!(snapshot.len() < raw_snapshot_len);
+        }
+        let b = LoroDoc::from_snapshot(&snapshot).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+
+        let updates = a
+            .export_from_with_codec(&Default::default(), codec.as_ref())
+            .unwrap();
+        assert_eq!(updates[6], codec.id());
+        let b = LoroDoc::new();
+        b.import(&updates).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+    }
+
+    // unknown codec
+    let mut bytes = a.export_snapshot();
+    bytes[6] = 0xff;
+    let err = LoroDoc::new().import(&bytes).unwrap_err();
+    assert!(matches!(err, loro_common::LoroError::DecodeError(_)));
+
+    // the decompressed size prepended by lz4 is too large to be allocated
+    #[cfg(feature = "lz4")]
+    {
+        let mut bytes = a.export_snapshot_with_codec(&loro_internal::Lz4).unwrap();
+        bytes[11..15].copy_from_slice(&u32::MAX.to_le_bytes());
+        let checksum = crc32fast::hash(&bytes[11..]);
+        bytes[7..11].copy_from_slice(&checksum.to_le_bytes());
+        let err = LoroDoc::new().import(&bytes).unwrap_err();
+        assert!(matches!(err, loro_common::LoroError::DecodeError(_)));
+    }
+}
+
+#[test]
+fn test_schema_negotiation() {
+    use loro_internal::{negotiate_schema_version, ENCODE_SCHEMA_VERSION};
+
+    assert_eq!(negotiate_schema_version(0), 0);
+    assert_eq!(
+        negotiate_schema_version(ENCODE_SCHEMA_VERSION),
+        ENCODE_SCHEMA_VERSION
+    );
+    assert_eq!(negotiate_schema_version(u8::MAX), ENCODE_SCHEMA_VERSION);
+
+    let a = LoroDoc::new_auto_commit();
+    a.get_text("text").insert_(0, "hello").unwrap();
+    a.commit_then_renew();
+    let old_peer_version = negotiate_schema_version(0);
+    let updates = a
+        .export_from_with_schema(&Default::default(), old_peer_version)
+        .unwrap();
+    assert_eq!(updates[4], 0);
+    let snapshot = a.export_snapshot_with_schema(old_peer_version).unwrap();
+    assert_eq!(snapshot[4], 0);
+    for bytes in [updates, snapshot] {
+        let b = LoroDoc::new();
+        b.import(&bytes).unwrap();
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+    }
+
+    // unknown versions
+    let err = a
+        .export_from_with_schema(&Default::default(), ENCODE_SCHEMA_VERSION + 1)
+        .unwrap_err();
+    assert!(matches!(err, loro_common::LoroError::ArgErr(_)));
+    let mut future = a.export_snapshot();
+    future[4] = ENCODE_SCHEMA_VERSION + 1;
+    let err = LoroDoc::new().import(&future).unwrap_err();
+    assert!(matches!(err, loro_common::LoroError::DecodeError(_)));
+}
+
 #[test]
 fn test_pending() {
     let a = LoroDoc::new_auto_commit();
@@ -397,6 +659,36 @@ fn import() {
     .unwrap_or_default();
 }
 
+#[test]
+fn import_from_the_middle_of_a_change() {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    text.insert_(0, "a").unwrap();
+    doc.commit_then_renew();
+    let v1 = doc.oplog_frontiers();
+    text.delete_(0, 1).unwrap();
+    doc.commit_then_renew();
+    let other = LoroDoc::new_auto_commit();
+    other.set_peer_id(2).unwrap();
+    other.import(&doc.export_snapshot()).unwrap();
+    other.get_text("text").insert_(0, "x").unwrap();
+    other.commit_then_renew();
+    doc.import(&other.export_from(&doc.oplog_vv())).unwrap();
+    text.insert_(0, "a").unwrap();
+    doc.commit_then_renew();
+
+    // the first change of peer 1 ends before the exported changes of peer 1
+    let new_doc = LoroDoc::new();
+    new_doc
+        .import(&doc.export_snapshot_at(&v1).unwrap())
+        .unwrap();
+    new_doc
+        .import(&doc.export_from(&new_doc.oplog_vv()))
+        .unwrap();
+    assert_eq!(new_doc.get_deep_value(), doc.get_deep_value());
+}
+
 #[test]
 fn test_timestamp() {
     let doc = LoroDoc::new();
@@ -681,3 +973,1150 @@ fn tree_checkout() {
         })
         .unwrap();
 }
+
+#[test]
+fn subscribe_local_update() {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let updates_clone = updates.clone();
+    let sub = a.subscribe_local_update(Arc::new(move |bytes| {
+        updates_clone.lock().unwrap().push(bytes.to_vec());
+    }));
+
+    a.get_text("text").insert_(0, "hello").unwrap();
+    a.commit_then_renew();
+    assert_eq!(
+        updates.lock().unwrap().last().unwrap(),
+        &a.export_from(&Default::default())
+    );
+
+    // the imported changes are not included
+    let b = LoroDoc::new_auto_commit();
+    b.set_peer_id(2).unwrap();
+    b.get_map("map").insert_("key", 1.into()).unwrap();
+    b.commit_then_renew();
+    a.import(&b.export_snapshot()).unwrap();
+    assert_eq!(updates.lock().unwrap().len(), 1);
+    let vv = a.oplog_vv();
+    a.get_text("text").insert_(5, " world").unwrap();
+    a.commit_then_renew();
+    assert_eq!(updates.lock().unwrap().last().unwrap(), &a.export_from(&vv));
+
+    // empty commits don't emit updates
+    a.commit_then_renew();
+    assert_eq!(updates.lock().unwrap().len(), 2);
+
+    // replay the updates on a doc that already has the imported changes
+    let c = LoroDoc::new_auto_commit();
+    c.import(&b.export_snapshot()).unwrap();
+    for update in updates.lock().unwrap().iter() {
+        c.import(update).unwrap();
+    }
+    assert_eq!(c.get_deep_value(), a.get_deep_value());
+
+    // explicit transactions
+    let d = LoroDoc::new();
+    let count = Arc::new(Mutex::new(0));
+    let count_clone = count.clone();
+    d.subscribe_local_update(Arc::new(move |_| {
+        *count_clone.lock().unwrap() += 1;
+    }));
+    let mut txn = d.txn().unwrap();
+    d.get_list("list").insert(&mut txn, 0, 1.into()).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(*count.lock().unwrap(), 1);
+
+    a.unsubscribe(sub);
+    a.get_text("text").insert_(0, "!").unwrap();
+    a.commit_then_renew();
+    assert_eq!(updates.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn decode_snapshot_state_without_doc() {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    let text = a.get_text("text");
+    text.insert_(0, "hello 世界").unwrap();
+    text.mark_(0, 5, "bold", true.into(), TextStyleInfoFlag::BOLD)
+        .unwrap();
+    text.insert_(8, "!").unwrap();
+    let map = a.get_map("map");
+    map.insert_("key", 1.into()).unwrap();
+    map.insert_("deleted", 2.into()).unwrap();
+    map.delete_("deleted").unwrap();
+    let list = map
+        .insert_container_("list", ContainerType::List)
+        .unwrap()
+        .into_list()
+        .unwrap();
+    list.insert_(0, "a".into()).unwrap();
+    list.insert_container_(1, ContainerType::Text)
+        .unwrap()
+        .into_text()
+        .unwrap()
+        .insert_(0, "nested")
+        .unwrap();
+    let tree = a.get_tree("tree");
+    let root = tree.create_().unwrap();
+    let child = tree.create_and_mov_(root).unwrap();
+    tree.get_meta(child)
+        .unwrap()
+        .insert_("name", "child".into())
+        .unwrap();
+    let deleted = tree.create_().unwrap();
+    tree.delete_(deleted).unwrap();
+    a.commit_then_renew();
+
+    let snapshot = a.export_snapshot();
+    assert_eq!(
+        loro_internal::decode_snapshot_state(&snapshot, None).unwrap(),
+        a.get_deep_value()
+    );
+    assert_eq!(
+        loro_internal::decode_snapshot_state(&snapshot, Some("map")).unwrap(),
+        a.get_deep_value().as_map().unwrap()["map"]
+    );
+    assert!(loro_internal::decode_snapshot_state(&snapshot, Some("unknown")).is_err());
+    assert!(
+        loro_internal::decode_snapshot_state(&a.export_from(&Default::default()), None).is_err()
+    );
+}
+
+#[test]
+fn decode_state_of_a_container_containing_itself() {
+    use loro_preload::{
+        CommonArena, EncodedAppState, EncodedContainerState, FinalPhase, MapEntry, TempArena,
+    };
+    use std::borrow::Cow;
+
+    let child = ContainerID::Normal {
+        peer: 1,
+        counter: 0,
+        container_type: ContainerType::Map,
+    };
+    // both the root map and the child map have the child map as the value of "key"
+    let entry = MapEntry {
+        key: 0,
+        value: 1,
+        peer: 0,
+        counter: 0,
+        lamport: 0,
+    };
+    let common = CommonArena {
+        peer_ids: Cow::Owned(vec![1]),
+        container_ids: vec![
+            ContainerID::new_root("map", ContainerType::Map),
+            child.clone(),
+        ],
+    };
+    let app_state = EncodedAppState {
+        frontiers: vec![],
+        states: vec![
+            EncodedContainerState::Map(vec![entry.clone()]),
+            EncodedContainerState::Map(vec![entry]),
+        ],
+        parents: vec![None, Some(0)],
+    };
+    let arena = TempArena {
+        keywords: vec!["key".into()],
+        values: vec![LoroValue::Container(child)],
+        ..Default::default()
+    };
+    let bytes = FinalPhase {
+        common: Cow::Owned(common.encode()),
+        app_state: Cow::Owned(app_state.encode()),
+        state_arena: Cow::Owned(arena.encode()),
+        oplog_extra_arena: Cow::Owned(vec![]),
+        oplog: Cow::Owned(vec![]),
+        checkpoints: Cow::Owned(vec![]),
+    }
+    .encode();
+    assert!(loro_preload::decode_state(&bytes).is_err());
+    assert!(loro_preload::decode_root_state(&bytes, "map").is_err());
+}
+
+#[test]
+fn diff_between_versions() {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let map = doc.get_map("map");
+    map.insert_("a", 1.into()).unwrap();
+    let text = map
+        .insert_container_("text", ContainerType::Text)
+        .unwrap()
+        .into_text()
+        .unwrap();
+    text.insert_(0, "hello").unwrap();
+    doc.commit_then_renew();
+    let v1 = doc.oplog_frontiers();
+    let value_1 = doc.get_deep_value();
+
+    text.insert_(5, " world").unwrap();
+    text.delete_(0, 1).unwrap();
+    map.delete_("a").unwrap();
+    doc.get_list("list").push_("x".into()).unwrap();
+    doc.commit_then_renew();
+    let v2 = doc.oplog_frontiers();
+    let value_2 = doc.get_deep_value();
+
+    let emitted = Arc::new(AtomicBool::new(false));
+    let emitted_cloned = emitted.clone();
+    doc.subscribe_root(Arc::new(move |_| {
+        emitted_cloned.store(true, std::sync::atomic::Ordering::Relaxed);
+    }));
+    let apply = |mut value: LoroValue, diffs: Vec<loro_internal::ContainerDiff>| {
+        for diff in diffs {
+            value.apply(
+                &diff.path.iter().map(|x| x.1.clone()).collect(),
+                &[diff.diff],
+            );
+        }
+        value
+    };
+    let diff = doc.diff(&v1, &v2).unwrap();
+    assert!(diff.iter().any(|d| d.id == text.id() && !d.path.is_empty()));
+    assert_eq!(apply(value_1.clone(), diff), value_2);
+    let reverted = apply(value_2.clone(), doc.diff(&v2, &v1).unwrap());
+    assert_eq!(
+        reverted.as_map().unwrap()["map"],
+        value_1.as_map().unwrap()["map"]
+    );
+    assert_eq!(
+        reverted.as_map().unwrap()["list"],
+        LoroValue::List(Default::default())
+    );
+    assert!(doc.diff(&v2, &v2).unwrap().is_empty());
+    assert_eq!(
+        apply(
+            LoroDoc::new().get_deep_value(),
+            doc.diff(&Default::default(), &v2).unwrap()
+        ),
+        value_2
+    );
+    assert!(doc.diff(&v1, &ID::new(2, 0).into()).is_err());
+
+    // the doc is untouched
+    assert!(!emitted.load(std::sync::atomic::Ordering::Relaxed));
+    assert_eq!(doc.state_frontiers(), v2);
+    assert_eq!(doc.get_deep_value(), value_2);
+}
+
+#[test]
+fn apply_diff_to_another_doc() {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_cloned = events.clone();
+    a.subscribe_root(Arc::new(move |event| {
+        events_cloned.lock().unwrap().push(event.container.clone());
+    }));
+    let map = a.get_map("map");
+    let text = map
+        .insert_container_("text", ContainerType::Text)
+        .unwrap()
+        .into_text()
+        .unwrap();
+    text.insert_(0, "hello world").unwrap();
+    text.mark_(0, 5, "bold", true.into(), TextStyleInfoFlag::BOLD)
+        .unwrap();
+    let list = a.get_list("list");
+    list.push_(1.into()).unwrap();
+    list.insert_container_(1, ContainerType::Map)
+        .unwrap()
+        .into_map()
+        .unwrap()
+        .insert_("k", "v".into())
+        .unwrap();
+    let tree = a.get_tree("tree");
+    let root = tree.create_().unwrap();
+    let child = tree.create_and_mov_(root).unwrap();
+    tree.get_meta(child)
+        .unwrap()
+        .insert_("name", "child".into())
+        .unwrap();
+    a.commit_then_renew();
+    let v1 = a.oplog_frontiers();
+    let value_1 = a.get_deep_value();
+
+    // mirror `a` into `b` by the events
+    let b = LoroDoc::new_auto_commit();
+    b.set_peer_id(2).unwrap();
+    let mut container_remap = Default::default();
+    b.apply_diff_with_remap(
+        &std::mem::take(&mut *events.lock().unwrap()),
+        &mut container_remap,
+    )
+    .unwrap();
+    let check = |doc: &LoroDoc| {
+        for name in ["map", "list"] {
+            assert_eq!(
+                doc.get_deep_value().as_map().unwrap()[name],
+                a.get_deep_value().as_map().unwrap()[name]
+            );
+        }
+        assert_eq!(
+            doc.get_text(
+                doc.get_map("map")
+                    .get_("text")
+                    .unwrap()
+                    .into_container()
+                    .unwrap()
+                    .id()
+            )
+            .get_richtext_value(),
+            text.get_richtext_value()
+        );
+        let tree = doc.get_tree("tree");
+        let nodes = tree.nodes();
+        assert_eq!(nodes.len(), 2);
+        let child = nodes
+            .iter()
+            .find(|&&node| tree.parent(node) != Some(None))
+            .unwrap();
+        assert!(nodes.contains(&tree.parent(*child).unwrap().unwrap()));
+        assert_eq!(
+            tree.get_meta(*child).unwrap().get("name"),
+            Some("child".into())
+        );
+    };
+    check(&b);
+
+    // the new containers created in `b` are remapped when applying the following diffs
+    text.insert_(11, "!").unwrap();
+    text.delete_(0, 1).unwrap();
+    map.delete_("missing").unwrap();
+    list.delete_(0, 1).unwrap();
+    tree.delete_(root).unwrap();
+    a.commit_then_renew();
+    b.apply_diff_with_remap(
+        &std::mem::take(&mut *events.lock().unwrap()),
+        &mut container_remap,
+    )
+    .unwrap();
+    assert_eq!(
+        b.get_deep_value().as_map().unwrap()["map"],
+        a.get_deep_value().as_map().unwrap()["map"]
+    );
+    assert_eq!(
+        b.get_deep_value().as_map().unwrap()["list"],
+        a.get_deep_value().as_map().unwrap()["list"]
+    );
+    assert!(b.get_tree("tree").nodes().is_empty());
+
+    // the doc at v1 from the full diff
+    let c = LoroDoc::new_auto_commit();
+    c.apply_diff(&a.diff(&Default::default(), &v1).unwrap())
+        .unwrap();
+    for name in ["map", "list"] {
+        assert_eq!(
+            c.get_deep_value().as_map().unwrap()[name],
+            value_1.as_map().unwrap()[name]
+        );
+    }
+    assert_eq!(c.get_tree("tree").nodes().len(), 2);
+
+    // revert the changes after v1 in `a`
+    let v2 = a.oplog_frontiers();
+    a.apply_diff(&a.diff(&v2, &v1).unwrap()).unwrap();
+    assert_eq!(
+        a.get_deep_value().as_map().unwrap()["map"],
+        value_1.as_map().unwrap()["map"]
+    );
+    assert_eq!(
+        a.get_deep_value().as_map().unwrap()["list"],
+        value_1.as_map().unwrap()["list"]
+    );
+}
+
+#[test]
+fn apply_diff_with_text_style_config() {
+    let a = LoroDoc::new_auto_commit();
+    let text = a.get_text("text");
+    text.insert_(0, "ab cd ef").unwrap();
+    text.mark_(0, 2, "link", true.into(), TextStyleInfoFlag::LINK)
+        .unwrap();
+    text.mark_(3, 5, "highlight", true.into(), TextStyleInfoFlag::LINK)
+        .unwrap();
+    text.mark_(6, 8, "em", true.into(), TextStyleInfoFlag::BOLD)
+        .unwrap();
+    a.commit_then_renew();
+
+    let b = LoroDoc::new_auto_commit();
+    let mut config = StyleConfigMap::default();
+    config.insert("highlight".into(), TextStyleInfoFlag::LINK);
+    b.config_text_style(config);
+    b.apply_diff(&a.diff(&Default::default(), &a.oplog_frontiers()).unwrap())
+        .unwrap();
+    // the configured styles don't expand, the others are treated as bold
+    let text = b.get_text("text");
+    text.insert_(8, "!").unwrap();
+    text.insert_(5, "!").unwrap();
+    text.insert_(2, "!").unwrap();
+    assert_eq!(
+        text.get_richtext_value().to_json_value(),
+        json!([
+            {"insert": "ab", "attributes": {"link": true}},
+            {"insert": "! "},
+            {"insert": "cd", "attributes": {"highlight": true}},
+            {"insert": "! "},
+            {"insert": "ef!", "attributes": {"em": true}},
+        ])
+    );
+
+    // the detached doc is read-only
+    let mut c = LoroDoc::new_auto_commit();
+    c.checkout(&Default::default()).unwrap();
+    assert!(c
+        .apply_diff(&a.diff(&Default::default(), &a.oplog_frontiers()).unwrap())
+        .is_err());
+}
+
+#[test]
+fn map_event_old_values() {
+    /// (key, old value, new value)
+    type Records = Arc<Mutex<Vec<(String, Option<LoroValue>, Option<LoroValue>)>>>;
+    fn record(doc: &LoroDoc) -> Records {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let records_cloned = records.clone();
+        doc.subscribe_root(Arc::new(move |event| {
+            let loro_internal::event::Diff::NewMap(delta) = &event.container.diff else {
+                return;
+            };
+            let mut records = records_cloned.lock().unwrap();
+            for (key, value) in delta.updated.iter() {
+                records.push((
+                    key.to_string(),
+                    delta.old_values[key].clone(),
+                    value.value.clone(),
+                ));
+            }
+        }));
+        records
+    }
+
+    let mut a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    let local = record(&a);
+    let map = a.get_map("map");
+    map.insert_("k", 1.into()).unwrap();
+    a.commit_then_renew();
+    let v1 = a.oplog_frontiers();
+    map.insert_("k", 2.into()).unwrap();
+    a.commit_then_renew();
+    map.delete_("k").unwrap();
+    a.commit_then_renew();
+    // the value before the whole txn is kept
+    map.insert_("j", 1.into()).unwrap();
+    map.insert_("j", 2.into()).unwrap();
+    a.commit_then_renew();
+    assert_eq!(
+        std::mem::take(&mut *local.lock().unwrap()),
+        vec![
+            ("k".into(), None, Some(1.into())),
+            ("k".into(), Some(1.into()), Some(2.into())),
+            ("k".into(), Some(2.into()), None),
+            ("j".into(), None, Some(2.into())),
+        ]
+    );
+
+    // remote events
+    let b = LoroDoc::new_auto_commit();
+    b.get_map("map").insert_("j", "b".into()).unwrap();
+    b.commit_then_renew();
+    let remote = record(&b);
+    b.import(&a.export_from(&Default::default())).unwrap();
+    // a's change has the greater lamport
+    let records = std::mem::take(&mut *remote.lock().unwrap());
+    assert!(records.contains(&("j".into(), Some("b".into()), Some(2.into()))));
+
+    // checkout events
+    a.checkout(&v1).unwrap();
+    let mut records = std::mem::take(&mut *local.lock().unwrap());
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        records,
+        vec![
+            ("j".into(), Some(2.into()), None),
+            ("k".into(), None, Some(1.into())),
+        ]
+    );
+}
+
+#[test]
+fn text_blame() {
+    let mut a = LoroDoc::new();
+    a.set_peer_id(1).unwrap();
+    let text = a.get_text("text");
+    let mut txn = a.txn().unwrap();
+    txn.set_timestamp(100);
+    text.insert(&mut txn, 0, "hello").unwrap();
+    txn.commit().unwrap();
+
+    let b = LoroDoc::new();
+    b.set_peer_id(2).unwrap();
+    b.import(&a.export_snapshot()).unwrap();
+    let b_text = b.get_text("text");
+    let mut txn = b.txn().unwrap();
+    txn.set_timestamp(200);
+    b_text.insert(&mut txn, 5, " world").unwrap();
+    b_text.delete(&mut txn, 0, 1).unwrap();
+    b_text
+        .mark(&mut txn, 0, 3, "bold", true.into(), TextStyleInfoFlag::BOLD)
+        .unwrap();
+    txn.commit().unwrap();
+    a.import(&b.export_from(&a.oplog_vv())).unwrap();
+
+    assert_eq!(text.get_value().as_string().unwrap().as_str(), "ello world");
+    let expected = vec![(0..4, 1, 1, 100), (4..10, 2, 5, 200)];
+    assert_eq!(text.blame(&a.oplog().lock().unwrap()).unwrap(), expected);
+    assert_eq!(b_text.blame(&b.oplog().lock().unwrap()).unwrap(), expected);
+
+    // historical versions
+    let oplog = a.oplog().lock().unwrap().clone();
+    assert_eq!(
+        text.blame_at(&oplog, &ID::new(1, 4).into()).unwrap(),
+        vec![(0..5, 1, 0, 100)]
+    );
+    assert_eq!(
+        text.blame_at(&oplog, &ID::new(1, 2).into()).unwrap(),
+        vec![(0..3, 1, 0, 100)]
+    );
+    assert!(text.blame_at(&oplog, &ID::new(3, 0).into()).is_err());
+    a.checkout(&ID::new(2, 5).into()).unwrap();
+    assert_eq!(
+        text.blame(&oplog).unwrap(),
+        vec![(0..5, 1, 0, 100), (5..11, 2, 5, 200)]
+    );
+}
+
+#[test]
+fn text_changes_between() {
+    let doc = LoroDoc::new();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    let mut txn = doc.txn().unwrap();
+    txn.set_timestamp(100);
+    text.insert(&mut txn, 0, "hello world").unwrap();
+    txn.commit().unwrap();
+    let v1 = doc.oplog_frontiers();
+
+    let b = LoroDoc::new();
+    b.set_peer_id(2).unwrap();
+    b.import(&doc.export_snapshot()).unwrap();
+    let b_text = b.get_text("text");
+    let mut txn = b.txn().unwrap();
+    txn.set_timestamp(200);
+    // delete "world" backward
+    for i in (6..11).rev() {
+        b_text.delete(&mut txn, i, 1).unwrap();
+    }
+    b_text.insert(&mut txn, 6, "loro").unwrap();
+    txn.commit().unwrap();
+    doc.import(&b.export_from(&doc.oplog_vv())).unwrap();
+    let v2 = doc.oplog_frontiers();
+
+    let oplog = doc.oplog().lock().unwrap();
+    let changes = text.changes_between(&oplog, &v1, &v2).unwrap();
+    let simplify = |changes: Vec<TextChange>| {
+        changes
+            .into_iter()
+            .map(|c| (c.kind, c.text, c.id, c.lamport, c.timestamp))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        simplify(changes),
+        vec![
+            (
+                TextChangeKind::Unchanged,
+                "hello ".into(),
+                ID::new(1, 0),
+                0,
+                100
+            ),
+            (
+                TextChangeKind::Inserted,
+                "loro".into(),
+                ID::new(2, 5),
+                16,
+                200
+            ),
+            (
+                TextChangeKind::Deleted,
+                "world".into(),
+                ID::new(2, 4),
+                15,
+                200
+            ),
+        ]
+    );
+
+    // the other direction
+    let changes = text.changes_between(&oplog, &v2, &v1).unwrap();
+    assert_eq!(
+        simplify(changes),
+        vec![
+            (
+                TextChangeKind::Unchanged,
+                "hello ".into(),
+                ID::new(1, 0),
+                0,
+                100
+            ),
+            (
+                TextChangeKind::Deleted,
+                "loro".into(),
+                ID::new(2, 5),
+                16,
+                200
+            ),
+            (
+                TextChangeKind::Inserted,
+                "world".into(),
+                ID::new(1, 6),
+                6,
+                100
+            ),
+        ]
+    );
+
+    let changes = text.changes_between(&oplog, &v2, &v2).unwrap();
+    assert_eq!(
+        simplify(changes),
+        vec![
+            (
+                TextChangeKind::Unchanged,
+                "hello ".into(),
+                ID::new(1, 0),
+                0,
+                100
+            ),
+            (
+                TextChangeKind::Unchanged,
+                "loro".into(),
+                ID::new(2, 5),
+                16,
+                200
+            ),
+        ]
+    );
+}
+
+#[test]
+fn text_changes_between_with_overlapping_deletions() {
+    let doc = LoroDoc::new();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    let mut txn = doc.txn().unwrap();
+    txn.set_timestamp(100);
+    text.insert(&mut txn, 0, "hello world").unwrap();
+    txn.commit().unwrap();
+    let v1 = doc.oplog_frontiers();
+
+    // "lo wo", "o" and "world" are deleted concurrently
+    let snapshot = doc.export_snapshot();
+    for (peer, pos, len) in [(2, 3, 5), (3, 4, 1), (4, 6, 5)] {
+        let remote = LoroDoc::new();
+        remote.set_peer_id(peer).unwrap();
+        remote.import(&snapshot).unwrap();
+        let mut txn = remote.txn().unwrap();
+        txn.set_timestamp(peer as i64 * 100);
+        remote.get_text("text").delete(&mut txn, pos, len).unwrap();
+        txn.commit().unwrap();
+        doc.import(&remote.export_from(&Default::default()))
+            .unwrap();
+    }
+
+    let v2 = doc.oplog_frontiers();
+    let oplog = doc.oplog().lock().unwrap();
+    let changes = text
+        .changes_between(&oplog, &v1, &v2)
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.kind, c.text, c.id.peer))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![
+            (TextChangeKind::Unchanged, "hel".into(), 1),
+            (TextChangeKind::Deleted, "lo ".into(), 2),
+            // "wo" is deleted by both peer 2 and peer 4
+            (TextChangeKind::Deleted, "world".into(), 4),
+        ]
+    );
+}
+
+#[test]
+fn version_tags() {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    let text = a.get_text("text");
+    text.insert_(0, "v1").unwrap();
+    a.tag("v1").unwrap();
+    text.insert_(2, " v2").unwrap();
+    a.tag("v2").unwrap();
+    let v2 = a.oplog_frontiers();
+    text.insert_(5, " draft").unwrap();
+    a.commit_then_renew();
+
+    let tags = a.tags();
+    assert_eq!(
+        tags.iter()
+            .map(|tag| (tag.name.as_str(), tag.author))
+            .collect::<Vec<_>>(),
+        vec![("v1", 1), ("v2", 1)]
+    );
+    assert_eq!(tags[0].frontiers, ID::new(1, 1).into());
+    // the tag change itself is not included in the tagged version
+    assert_eq!(a.get_tag("v2").unwrap().frontiers, ID::new(1, 5).into());
+    assert_ne!(v2, ID::new(1, 5).into());
+
+    // the tags are replicated by snapshots and updates
+    let mut b = LoroDoc::new_auto_commit();
+    b.import(&a.export_snapshot()).unwrap();
+    assert_eq!(b.tags(), tags);
+    let c = LoroDoc::new_auto_commit();
+    c.import(&a.export_from(&Default::default())).unwrap();
+    assert_eq!(c.tags(), tags);
+
+    b.checkout_tag("v1").unwrap();
+    assert_eq!(
+        b.get_text("text").get_value().as_string().unwrap().as_str(),
+        "v1"
+    );
+    // the tags created after the checked out version are still available
+    assert_eq!(b.tags(), tags);
+    b.checkout_tag("v2").unwrap();
+    assert_eq!(
+        b.get_text("text").get_value().as_string().unwrap().as_str(),
+        "v1 v2"
+    );
+    assert!(b.checkout_tag("v3").is_err());
+    // the detached doc is read-only
+    assert!(b.tag("v3").is_err());
+
+    a.delete_tag("v1").unwrap();
+    a.tag_at("first", &ID::new(1, 0).into()).unwrap();
+    assert!(a.tag_at("unknown", &ID::new(2, 0).into()).is_err());
+    a.tag_at("v2", &ID::new(1, 1).into()).unwrap();
+    let names = a
+        .tags()
+        .into_iter()
+        .map(|tag| (tag.name, tag.frontiers))
+        .collect::<Vec<_>>();
+    assert_eq!(names.len(), 2);
+    assert!(names.contains(&("first".into(), ID::new(1, 0).into())));
+    assert!(names.contains(&("v2".into(), ID::new(1, 1).into())));
+}
+
+#[test]
+fn version_tags_are_hidden_from_the_state() {
+    let a = LoroDoc::new_auto_commit();
+    a.set_peer_id(1).unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    a.subscribe_root(Arc::new(move |event| {
+        events_clone
+            .lock()
+            .unwrap()
+            .push(event.container.id.clone());
+    }));
+    a.get_text("text").insert_(0, "v1").unwrap();
+    a.tag("v1").unwrap();
+    a.commit_then_renew();
+
+    assert_eq!(a.get_deep_value().to_json_value(), json!({"text": "v1"}));
+    assert_eq!(a.get_deep_value_with_id().as_map().unwrap().len(), 1);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![ContainerID::new_root("text", ContainerType::Text)]
+    );
+    let snapshot = a.export_snapshot();
+    assert_eq!(
+        loro_internal::decode_snapshot_state(&snapshot, None).unwrap(),
+        a.get_deep_value()
+    );
+
+    // the users cannot overwrite the tags
+    let map = a.get_map(loro_internal::version_tag::VERSION_TAGS_CONTAINER);
+    assert!(map.insert_("v1", "oops".into()).is_err());
+    assert!(map.delete_("v1").is_err());
+    a.commit_then_renew();
+    assert_eq!(a.get_tag("v1").unwrap().frontiers, ID::new(1, 1).into());
+
+    assert!(a.set_peer_id(u64::MAX).is_err());
+
+    let b = LoroDoc::new_auto_commit();
+    b.import(&snapshot).unwrap();
+    assert_eq!(b.get_deep_value(), a.get_deep_value());
+    assert_eq!(b.tags(), a.tags());
+
+    // the root containers can have any name
+    let map = a.get_map("__version_tags");
+    map.insert_("v1", "value".into()).unwrap();
+    a.commit_then_renew();
+    assert_eq!(
+        a.get_deep_value().to_json_value(),
+        json!({"text": "v1", "__version_tags": {"v1": "value"}})
+    );
+    assert_eq!(a.get_tag("v1").unwrap().frontiers, ID::new(1, 1).into());
+    assert_eq!(
+        loro_internal::decode_snapshot_state(&a.export_snapshot(), Some("__version_tags")).unwrap(),
+        a.get_deep_value().as_map().unwrap()["__version_tags"]
+    );
+
+    // reading the tags doesn't commit the pending ops
+    a.get_text("text").insert_(0, "x").unwrap();
+    let vv = a.oplog_vv();
+    assert_eq!(a.tags().len(), 1);
+    assert!(a.get_tag("v1").is_some());
+    assert_eq!(a.oplog_vv(), vv);
+}
+
+#[test]
+fn checkout_after_importing_concurrent_changes() {
+    let mut doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    text.insert_(0, "hello").unwrap();
+    doc.commit_then_renew();
+    let v1 = doc.oplog_frontiers();
+    let b = LoroDoc::new_auto_commit();
+    b.import(&doc.export_snapshot()).unwrap();
+    b.get_text("text").insert_(5, "!").unwrap();
+    b.commit_then_renew();
+    text.insert_(5, " world").unwrap();
+    doc.commit_then_renew();
+    doc.checkout(&v1).unwrap();
+    doc.import(&b.export_snapshot()).unwrap();
+    let f = doc.oplog_frontiers();
+    let bf = b.oplog_frontiers();
+    doc.checkout(&bf).unwrap();
+    assert_eq!(text.get_value().as_string().unwrap().as_str(), "hello!");
+    doc.checkout(&f).unwrap();
+    assert_eq!(text.len_unicode(), "hello world!".len());
+}
+
+#[test]
+fn edit_on_detached_state() {
+    let mut doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    text.insert_(0, "hello").unwrap();
+    doc.commit_then_renew();
+    let v1 = doc.oplog_frontiers();
+    text.insert_(5, " world").unwrap();
+    doc.commit_then_renew();
+    let latest = doc.oplog_frontiers();
+
+    doc.checkout(&v1).unwrap();
+    assert!(text.insert_(5, "!").is_err());
+
+    doc.set_detached_editing(true);
+    // peer 1 has changes after v1, so its changes from v1 wouldn't be sequential
+    let peer = doc.peer_id();
+    assert_ne!(peer, 1);
+    text.insert_(5, "!").unwrap();
+    doc.commit_then_renew();
+    assert!(doc.is_detached());
+    assert_eq!(text.get_value().as_string().unwrap().as_str(), "hello!");
+    let change = doc
+        .oplog()
+        .lock()
+        .unwrap()
+        .get_change_at(ID::new(peer, 0))
+        .unwrap()
+        .clone();
+    assert_eq!(&change.deps, &v1);
+    assert_eq!(doc.oplog_frontiers().len(), 2);
+
+    // the concurrent branch is merged with the latest version
+    doc.checkout_to_latest();
+    assert!(!doc.is_detached());
+    let value = text.get_value();
+    let merged = value.as_string().unwrap();
+    assert_eq!(merged.len(), "hello world!".len());
+    assert!(merged.starts_with("hello"));
+    let other = LoroDoc::new();
+    other.import(&doc.export_snapshot()).unwrap();
+    assert_eq!(other.get_deep_value(), doc.get_deep_value());
+
+    // checking out a version including all the changes of the peer keeps the peer
+    doc.checkout(&latest).unwrap();
+    assert_ne!(doc.peer_id(), peer);
+    let peer = doc.peer_id();
+    text.insert_(0, "x").unwrap();
+    doc.commit_then_renew();
+    doc.checkout(&doc.oplog_frontiers()).unwrap();
+    assert_eq!(doc.peer_id(), peer);
+    doc.set_detached_editing(false);
+    assert!(text.insert_(0, "x").is_err());
+
+    // explicit transactions on a detached state
+    let mut doc = LoroDoc::new();
+    let text = doc.get_text("text");
+    let mut txn = doc.txn().unwrap();
+    text.insert(&mut txn, 0, "abc").unwrap();
+    txn.commit().unwrap();
+    let v1 = doc.oplog_frontiers();
+    doc.checkout(&Frontiers::default()).unwrap();
+    assert!(doc.txn().is_err());
+    doc.set_detached_editing(true);
+    let mut txn = doc.txn().unwrap();
+    text.insert(&mut txn, 0, "x").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(text.get_value().as_string().unwrap().as_str(), "x");
+    doc.checkout(&v1).unwrap();
+    assert_eq!(text.get_value().as_string().unwrap().as_str(), "abc");
+    doc.attach();
+    assert_eq!(text.len_unicode(), 4);
+}
+
+#[test]
+fn events_of_the_edits_on_detached_state() {
+    let mut doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    text.insert_(0, "hello").unwrap();
+    doc.commit_then_renew();
+    let v1 = doc.oplog_frontiers();
+    text.insert_(5, " world").unwrap();
+    doc.commit_then_renew();
+    doc.checkout(&v1).unwrap();
+    doc.set_detached_editing(true);
+
+    let versions = Arc::new(Mutex::new(Vec::new()));
+    let versions_clone = versions.clone();
+    doc.subscribe_root(Arc::new(move |event| {
+        versions_clone
+            .lock()
+            .unwrap()
+            .push((event.doc.from.clone(), event.doc.to.clone()));
+    }));
+    text.insert_(5, "!").unwrap();
+    doc.commit_then_renew();
+    // the event is about the detached state rather than the latest version
+    assert_eq!(
+        *versions.lock().unwrap(),
+        vec![(v1, ID::new(doc.peer_id(), 0).into())]
+    );
+    assert_eq!(doc.state_frontiers(), ID::new(doc.peer_id(), 0).into());
+    assert_ne!(doc.oplog_frontiers(), doc.state_frontiers());
+}
+
+#[test]
+fn checkout_forward_from_the_middle_of_a_change() {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    for (pos, s) in [(0, "a"), (0, "b"), (1, "c"), (0, "d"), (2, "e")] {
+        text.insert_(pos, s).unwrap();
+        doc.commit_then_renew();
+    }
+
+    let mut new_doc = LoroDoc::new();
+    new_doc.import(&doc.export_snapshot()).unwrap();
+    new_doc.checkout(&ID::new(1, 0).into()).unwrap();
+    new_doc.checkout(&ID::new(1, 1).into()).unwrap();
+    doc.get_map("map").insert_("k", 1.into()).unwrap();
+    doc.commit_then_renew();
+    new_doc
+        .import(&doc.export_from(&new_doc.oplog_vv()))
+        .unwrap();
+    // the doc is checked out from the middle of the only change of peer 1
+    new_doc.checkout_to_latest();
+    assert_eq!(new_doc.get_deep_value(), doc.get_deep_value());
+}
+
+#[test]
+fn checkout_with_checkpoints() {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let other = LoroDoc::new_auto_commit();
+    other.set_peer_id(2).unwrap();
+    let mut versions = vec![Frontiers::default()];
+    for i in 0..40 {
+        let text = doc.get_text("text");
+        text.insert_(text.len_unicode() / 2, &i.to_string())
+            .unwrap();
+        if i % 3 == 0 {
+            text.delete_(0, 1).unwrap();
+        }
+        doc.get_map("map")
+            .insert_(&(i % 5).to_string(), i.into())
+            .unwrap();
+        doc.get_list("list").insert_(0, i.into()).unwrap();
+        doc.commit_then_renew();
+        if i % 7 == 0 {
+            other.import(&doc.export_from(&other.oplog_vv())).unwrap();
+            other.get_text("text").insert_(0, "x").unwrap();
+            other.get_list("list").insert_(1, "y".into()).unwrap();
+            other.commit_then_renew();
+            versions.push(other.oplog_frontiers());
+            doc.import(&other.export_from(&doc.oplog_vv())).unwrap();
+        }
+        versions.push(doc.oplog_frontiers());
+    }
+
+    let mut cached = LoroDoc::new();
+    cached.import(&doc.export_snapshot()).unwrap();
+    cached.set_checkpoint_interval(Some(10));
+    let mut expected = LoroDoc::new();
+    expected.import(&doc.export_snapshot()).unwrap();
+    let order = (0..versions.len())
+        .rev()
+        .chain(0..versions.len())
+        .chain((0..versions.len()).map(|i| i * 7 % versions.len()));
+    for (n, i) in order.enumerate() {
+        if n % 5 == 0 {
+            // the new changes invalidate the cache of the diff calculator
+            doc.get_map("map").insert_("n", (n as i32).into()).unwrap();
+            doc.commit_then_renew();
+            cached.import(&doc.export_from(&cached.oplog_vv())).unwrap();
+            expected
+                .import(&doc.export_from(&expected.oplog_vv()))
+                .unwrap();
+            cached.checkout_to_latest();
+            expected.checkout_to_latest();
+            assert_eq!(cached.get_deep_value(), doc.get_deep_value());
+        }
+
+        cached.checkout(&versions[i]).unwrap();
+        expected.checkout(&versions[i]).unwrap();
+        assert_eq!(cached.get_deep_value(), expected.get_deep_value(), "{}", i);
+    }
+
+    let checkpoints = cached.oplog().lock().unwrap().checkpoint_versions();
+    assert!(checkpoints.len() > 3);
+    cached.checkout_to_latest();
+    assert_eq!(cached.get_deep_value(), doc.get_deep_value());
+
+    // the events of the checkout are still emitted when there are subscribers
+    let value = Arc::new(Mutex::new(LoroValue::Map(Default::default())));
+    let value_clone = value.clone();
+    cached.subscribe_root(Arc::new(move |event| {
+        value_clone.lock().unwrap().apply(
+            &event.container.path.iter().map(|x| x.1.clone()).collect(),
+            std::slice::from_ref(&event.container.diff),
+        );
+    }));
+    *value.lock().unwrap() = cached.get_deep_value();
+    cached.checkout(&versions[3]).unwrap();
+    expected.checkout(&versions[3]).unwrap();
+    assert_eq!(*value.lock().unwrap(), expected.get_deep_value());
+
+    cached.set_checkpoint_interval(None);
+    assert!(cached
+        .oplog()
+        .lock()
+        .unwrap()
+        .checkpoint_versions()
+        .is_empty());
+}
+
+#[test]
+fn least_recently_used_checkpoints_are_evicted() {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    let mut versions = vec![Frontiers::default()];
+    for i in 0..100 {
+        text.insert_(i, "x").unwrap();
+        doc.commit_then_renew();
+        versions.push(doc.oplog_frontiers());
+    }
+
+    let mut cached = LoroDoc::new();
+    cached.import(&doc.export_snapshot()).unwrap();
+    cached.set_checkpoint_interval(Some(25));
+    cached.set_max_checkpoints(2);
+    let checkpoints = |doc: &LoroDoc| {
+        let mut versions = doc.oplog().lock().unwrap().checkpoint_versions();
+        versions.sort_by_key(|f| f[0].counter);
+        versions
+    };
+
+    // the latest version is recorded before the checkout
+    cached.checkout(&versions[30]).unwrap();
+    assert_eq!(
+        checkpoints(&cached),
+        vec![versions[30].clone(), versions[100].clone()]
+    );
+
+    // the new change is replayed on top of the checkpoint of the latest version
+    text.insert_(0, "y").unwrap();
+    doc.commit_then_renew();
+    cached.import(&doc.export_from(&cached.oplog_vv())).unwrap();
+    cached.checkout_to_latest();
+    assert_eq!(cached.get_deep_value(), doc.get_deep_value());
+
+    // the checkpoint at 30 is the least recently used one
+    cached.checkout(&versions[20]).unwrap();
+    cached.checkout(&versions[65]).unwrap();
+    assert_eq!(
+        checkpoints(&cached),
+        vec![versions[65].clone(), versions[100].clone()]
+    );
+    assert_eq!(cached.get_text("text").len_unicode(), 65);
+
+    cached.set_max_checkpoints(1);
+    assert_eq!(checkpoints(&cached), vec![versions[65].clone()]);
+}
+
+#[test]
+fn checkpoints_in_snapshot() {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(1).unwrap();
+    let text = doc.get_text("text");
+    let mut versions = vec![Frontiers::default()];
+    for i in 0..60 {
+        text.insert_(i, "x").unwrap();
+        if i % 10 == 0 {
+            text.mark_(0, i + 1, "bold", (i as i32).into(), TextStyleInfoFlag::BOLD)
+                .unwrap();
+        }
+        doc.get_map("map").insert_("i", (i as i32).into()).unwrap();
+        doc.commit_then_renew();
+        versions.push(doc.oplog_frontiers());
+    }
+
+    let mut cached = LoroDoc::new();
+    cached.import(&doc.export_snapshot()).unwrap();
+    cached.set_checkpoint_interval(Some(20));
+    cached.checkout(&versions[30]).unwrap();
+    cached.checkout_to_latest();
+    let checkpoints = |doc: &LoroDoc| {
+        let mut versions = doc.oplog().lock().unwrap().checkpoint_versions();
+        versions.sort_by_key(|f| f[0].counter);
+        versions
+    };
+    assert_eq!(checkpoints(&cached).len(), 2);
+    let bytes = cached.export_snapshot_with_checkpoints().unwrap();
+
+    // the checkpoints are ignored if they're not enabled
+    let ignored = LoroDoc::new();
+    ignored.import(&bytes).unwrap();
+    assert!(checkpoints(&ignored).is_empty());
+    assert_eq!(ignored.get_deep_value(), doc.get_deep_value());
+
+    let mut loaded = LoroDoc::new();
+    loaded.set_checkpoint_interval(Some(20));
+    loaded.import(&bytes).unwrap();
+    assert_eq!(checkpoints(&loaded), checkpoints(&cached));
+    assert_eq!(loaded.get_deep_value(), doc.get_deep_value());
+    for i in [30, 45, 35, 60] {
+        loaded.checkout(&versions[i]).unwrap();
+        let expected = LoroDoc::new();
+        expected
+            .import(&doc.export_snapshot_at(&versions[i]).unwrap())
+            .unwrap();
+        assert_eq!(loaded.get_deep_value(), expected.get_deep_value(), "{}", i);
+        assert_eq!(
+            loaded.get_text("text").get_richtext_value(),
+            expected.get_text("text").get_richtext_value(),
+            "{}",
+            i
+        );
+    }
+}
@@ -11,6 +11,9 @@ use serde::{Deserialize, Serialize};
 ///
 /// - When only the state is needed, the `oplog` and `oplog_extra_arena` can be ignored.
 /// - When only the oplog is needed, the `app_state` can be ignored. (state_arena is still needed).
+///
+/// The `checkpoints` are optional. They're only written when they're not empty, so the
+/// snapshots without them stay the same, and the decoders that don't know them ignore them.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FinalPhase<'a> {
     #[serde(borrow)]
@@ -23,6 +26,8 @@ pub struct FinalPhase<'a> {
     pub oplog_extra_arena: Cow<'a, [u8]>, // -> TempArena<'a>. Cannot have full history if this is dropped
     #[serde(borrow)]
     pub oplog: Cow<'a, [u8]>, // -> OpLog. Can be ignored if we only need state
+    #[serde(borrow)]
+    pub checkpoints: Cow<'a, [u8]>, // -> EncodedCheckpoints. Can be empty
 }
 
 impl<'a> FinalPhase<'a> {
@@ -34,6 +39,7 @@ impl<'a> FinalPhase<'a> {
                 + self.state_arena.len()
                 + self.oplog_extra_arena.len()
                 + self.oplog.len()
+                + self.checkpoints.len()
                 + 10,
         );
 
@@ -47,6 +53,10 @@ impl<'a> FinalPhase<'a> {
         bytes.put_slice(&self.oplog_extra_arena);
         leb::write_unsigned(&mut bytes, self.oplog.len() as u64);
         bytes.put_slice(&self.oplog);
+        if !self.checkpoints.is_empty() {
+            leb::write_unsigned(&mut bytes, self.checkpoints.len() as u64);
+            bytes.put_slice(&self.checkpoints);
+        }
         bytes.to_vec()
     }
 
@@ -71,6 +81,14 @@ impl<'a> FinalPhase<'a> {
 
         let len = leb::read_unsigned(bytes, &mut index) as usize;
         let oplog = &bytes[index..index + len];
+        index += len;
+
+        let checkpoints = if index < bytes.len() {
+            let len = leb::read_unsigned(bytes, &mut index) as usize;
+            &bytes[index..index + len]
+        } else {
+            &[]
+        };
 
         Ok(FinalPhase {
             common: Cow::Borrowed(common),
@@ -78,6 +96,7 @@ impl<'a> FinalPhase<'a> {
             state_arena: Cow::Borrowed(state_arena),
             oplog_extra_arena: Cow::Borrowed(additional_arena),
             oplog: Cow::Borrowed(oplog),
+            checkpoints: Cow::Borrowed(checkpoints),
         })
     }
 
@@ -87,6 +106,7 @@ impl<'a> FinalPhase<'a> {
         println!("state_arena: {}", self.state_arena.len());
         println!("additional_arena: {}", self.oplog_extra_arena.len());
         println!("oplog: {}", self.oplog.len());
+        println!("checkpoints: {}", self.checkpoints.len());
     }
 }
 
@@ -129,6 +149,39 @@ impl<'a> EncodedAppState<'a> {
     }
 }
 
+/// The cached states of a doc for fast checkout
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncodedCheckpoints<'a> {
+    #[serde(borrow)]
+    pub checkpoints: Vec<EncodedCheckpoint<'a>>,
+}
+
+impl<'a> EncodedCheckpoints<'a> {
+    pub fn encode(&self) -> Vec<u8> {
+        to_vec(self).unwrap()
+    }
+
+    pub fn decode(data: &'a FinalPhase) -> Result<EncodedCheckpoints<'a>, LoroError> {
+        if data.checkpoints.is_empty() {
+            return Ok(Default::default());
+        }
+
+        serde_columnar::from_bytes(&data.checkpoints)
+            .map_err(|e| LoroError::DecodeError(e.to_string().into_boxed_str()))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncodedCheckpoint<'a> {
+    /// The states are in the same order as the `container_ids` of [CommonArena]
+    #[serde(borrow)]
+    pub app_state: EncodedAppState<'a>,
+    /// The text is empty. The text ranges of the states point to the `state_arena` of the snapshot
+    #[serde(borrow)]
+    pub arena: TempArena<'a>,
+    pub peer_ids: Vec<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EncodedContainerState<'a> {
     Map(Vec<MapEntry>),
@@ -244,11 +297,6 @@ impl<'a> TempArena<'a> {
     }
 }
 
-/// returns a deep LoroValue that wraps the whole state
-pub fn decode_state(_bytes: &[u8]) -> LoroValue {
-    unimplemented!()
-}
-
 mod leb {
     use bytes::{BufMut, BytesMut};
     pub const CONTINUATION_BIT: u8 = 1 << 7;
@@ -1,2 +1,4 @@
 mod encode;
+mod value;
 pub use encode::*;
+pub use value::{decode_root_state, decode_state};
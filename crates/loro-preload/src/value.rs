@@ -0,0 +1,229 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use loro_common::{ContainerID, InternalString, LoroError, LoroResult, LoroValue, TreeID};
+
+use crate::{
+    CommonArena, EncodedAppState, EncodedContainerState, EncodedRichtextState, FinalPhase,
+    TempArena, TextRanges,
+};
+
+/// The max depth of the nested containers, so that a malformed snapshot cannot overflow the stack
+const MAX_DEPTH: usize = 1024;
+
+/// Returns a deep LoroValue that wraps the whole state.
+///
+/// `bytes` is the encoded [FinalPhase] of a snapshot. Only the state and its arenas
+/// are decoded, the oplog is skipped. The version tags are not included, because they're not
+/// in a root container.
+pub fn decode_state(bytes: &[u8]) -> LoroResult<LoroValue> {
+    let data = FinalPhase::decode(bytes)?;
+    let decoder = StateDecoder::new(&data)?;
+    let mut ans = HashMap::new();
+    for (i, id) in decoder.common.container_ids.iter().enumerate() {
+        if let ContainerID::Root { name, .. } = id {
+            ans.insert(name.to_string(), decoder.deep_value(i, &mut Vec::new())?);
+        }
+    }
+
+    Ok(LoroValue::Map(Arc::new(ans.into_iter().collect())))
+}
+
+/// Returns the deep value of the root container named `name`. See [decode_state].
+pub fn decode_root_state(bytes: &[u8], name: &str) -> LoroResult<LoroValue> {
+    let data = FinalPhase::decode(bytes)?;
+    let decoder = StateDecoder::new(&data)?;
+    let index = decoder
+        .common
+        .container_ids
+        .iter()
+        .position(|id| matches!(id, ContainerID::Root { name: n, .. } if n.as_ref() == name))
+        .ok_or_else(|| {
+            LoroError::NotFoundError(format!("Root container {}", name).into_boxed_str())
+        })?;
+    decoder.deep_value(index, &mut Vec::new())
+}
+
+struct StateDecoder<'a> {
+    common: CommonArena<'a>,
+    arena: TempArena<'a>,
+    states: Vec<EncodedContainerState<'a>>,
+    /// container id -> index in `common.container_ids` and `states`
+    container_lookup: HashMap<ContainerID, usize>,
+}
+
+impl<'a> StateDecoder<'a> {
+    fn new(data: &'a FinalPhase<'a>) -> LoroResult<Self> {
+        let common = CommonArena::decode(data)?;
+        let arena = TempArena::decode_state_arena(data)?;
+        let states = EncodedAppState::decode(data)?.states;
+        if states.len() != common.container_ids.len() {
+            return Err(decode_error(
+                "The number of states mismatches the containers",
+            ));
+        }
+
+        let container_lookup = common
+            .container_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect();
+        Ok(Self {
+            common,
+            arena,
+            states,
+            container_lookup,
+        })
+    }
+
+    /// `path` is the indexes of the containers being decoded, from the root to the parent
+    fn deep_value(&self, index: usize, path: &mut Vec<usize>) -> LoroResult<LoroValue> {
+        if path.contains(&index) {
+            return Err(decode_error("The container contains itself"));
+        }
+        if path.len() >= MAX_DEPTH {
+            return Err(decode_error("The containers are nested too deep"));
+        }
+
+        path.push(index);
+        let ans = self.deep_value_inner(index, path);
+        path.pop();
+        ans
+    }
+
+    fn deep_value_inner(&self, index: usize, path: &mut Vec<usize>) -> LoroResult<LoroValue> {
+        let ans = match &self.states[index] {
+            EncodedContainerState::Map(entries) => {
+                let mut map = HashMap::with_capacity(entries.len());
+                for entry in entries.iter() {
+                    // 0 means the entry is deleted
+                    if entry.value == 0 {
+                        continue;
+                    }
+
+                    let value = self.resolve(self.value(entry.value - 1)?, path)?;
+                    map.insert(self.keyword(entry.key)?.to_string(), value);
+                }
+
+                LoroValue::Map(Arc::new(map.into_iter().collect()))
+            }
+            EncodedContainerState::List(values) => {
+                let mut list = Vec::with_capacity(values.len());
+                for &x in values.iter() {
+                    list.push(self.resolve(self.value(x)?, path)?);
+                }
+
+                LoroValue::List(Arc::new(list))
+            }
+            EncodedContainerState::Richtext(richtext) => {
+                LoroValue::String(Arc::new(self.text(richtext)?))
+            }
+            EncodedContainerState::Tree((nodes, deleted)) => {
+                let deleted = deleted
+                    .iter()
+                    .map(|&x| self.tree_id(x))
+                    .collect::<LoroResult<HashSet<_>>>()?;
+                let mut list = Vec::with_capacity(nodes.len());
+                for &(target, parent) in nodes.iter() {
+                    let target = self.tree_id(target)?;
+                    if deleted.contains(&target) || TreeID::is_unexist_root(Some(target)) {
+                        continue;
+                    }
+
+                    let parent = match parent {
+                        None => LoroValue::Null,
+                        Some(0) => TreeID::delete_root().unwrap().to_string().into(),
+                        Some(p) => self.tree_id(p)?.to_string().into(),
+                    };
+                    let mut node = HashMap::with_capacity(3);
+                    node.insert("id".to_string(), target.to_string().into());
+                    node.insert("parent".to_string(), parent);
+                    node.insert(
+                        "meta".to_string(),
+                        self.container_value(&target.associated_meta_container(), path)?,
+                    );
+                    list.push(LoroValue::Map(Arc::new(node.into_iter().collect())));
+                }
+
+                LoroValue::List(Arc::new(list))
+            }
+        };
+
+        Ok(ans)
+    }
+
+    /// Replace the container with its deep value
+    fn resolve(&self, value: &LoroValue, path: &mut Vec<usize>) -> LoroResult<LoroValue> {
+        match value {
+            LoroValue::Container(id) => self.container_value(id, path),
+            _ => Ok(value.clone()),
+        }
+    }
+
+    fn container_value(&self, id: &ContainerID, path: &mut Vec<usize>) -> LoroResult<LoroValue> {
+        match self.container_lookup.get(id) {
+            Some(&index) => self.deep_value(index, path),
+            None => Ok(id.container_type().default_value()),
+        }
+    }
+
+    fn text(&self, richtext: &EncodedRichtextState) -> LoroResult<String> {
+        let mut ans = String::new();
+        if richtext.text_bytes.is_empty() {
+            return Ok(ans);
+        }
+
+        let mut ranges = TextRanges::decode_iter(&richtext.text_bytes)?;
+        // the lengths of text ranges and style anchors are interleaved, only the text is needed
+        for &len in richtext.len.iter().step_by(2) {
+            for _ in 0..len {
+                let range = ranges
+                    .next()
+                    .ok_or_else(|| decode_error("Missing text range"))?;
+                let bytes = self
+                    .arena
+                    .text
+                    .get(range.start..range.start + range.len)
+                    .ok_or_else(|| decode_error("Text range out of bound"))?;
+                ans.push_str(std::str::from_utf8(bytes).map_err(|e| decode_error(&e.to_string()))?);
+            }
+        }
+
+        Ok(ans)
+    }
+
+    fn value(&self, index: usize) -> LoroResult<&LoroValue> {
+        self.arena
+            .values
+            .get(index)
+            .ok_or_else(|| decode_error("Value index out of bound"))
+    }
+
+    fn keyword(&self, index: usize) -> LoroResult<&InternalString> {
+        self.arena
+            .keywords
+            .get(index)
+            .ok_or_else(|| decode_error("Keyword index out of bound"))
+    }
+
+    /// The tree ids are indexed from 1, 0 is the deleted root
+    fn tree_id(&self, index: usize) -> LoroResult<TreeID> {
+        let &(peer, counter) = index
+            .checked_sub(1)
+            .and_then(|i| self.arena.tree_ids.get(i))
+            .ok_or_else(|| decode_error("Tree id index out of bound"))?;
+        let peer = *self
+            .common
+            .peer_ids
+            .get(peer as usize)
+            .ok_or_else(|| decode_error("Peer index out of bound"))?;
+        Ok(TreeID { peer, counter })
+    }
+}
+
+fn decode_error(msg: &str) -> LoroError {
+    LoroError::DecodeError(msg.into())
+}
@@ -0,0 +1,158 @@
+//! Incremental on-disk storage of a [LoroDoc].
+//!
+//! A document is stored in a directory with two files:
+//!
+//! - `snapshot.loro`: the snapshot of the doc at the last compaction
+//! - `updates.log`: the updates after the snapshot, appended as checksummed records
+//!
+//! Every [Storage::save] only appends the changes that are not persisted yet, instead of
+//! rewriting the whole doc. When the log grows beyond [StorageOptions], it's compacted
+//! into a new snapshot.
+//!
+//! The snapshot is replaced atomically by renaming, and the log is cleared after that.
+//! If the process crashes in between, the log contains the changes that are already in the
+//! snapshot, which are skipped when they are imported again.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use loro_common::LoroError;
+use loro_internal::{LoroDoc, VersionVector};
+use thiserror::Error;
+
+mod log;
+use log::UpdateLog;
+
+const SNAPSHOT_FILE: &str = "snapshot.loro";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.loro.tmp";
+const LOG_FILE: &str = "updates.log";
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("IO error ({0})")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Loro(#[from] LoroError),
+}
+
+/// When to compact the log into a snapshot
+#[derive(Debug, Clone)]
+pub struct StorageOptions {
+    /// The max number of records in the log
+    pub max_log_records: usize,
+    /// The max size of the log in bytes
+    pub max_log_bytes: u64,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        Self {
+            max_log_records: 1000,
+            max_log_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct Storage {
+    dir: PathBuf,
+    log: UpdateLog,
+    /// The version that is persisted
+    saved_vv: VersionVector,
+    options: StorageOptions,
+}
+
+impl Storage {
+    /// Open the storage in `dir` and load the doc from it.
+    /// The directory is created if it doesn't exist.
+    pub fn open(dir: impl AsRef<Path>) -> Result<(Self, LoroDoc), StorageError> {
+        Self::open_with_options(dir, StorageOptions::default())
+    }
+
+    pub fn open_with_options(
+        dir: impl AsRef<Path>,
+        options: StorageOptions,
+    ) -> Result<(Self, LoroDoc), StorageError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut doc = match fs::read(dir.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => LoroDoc::from_snapshot(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => LoroDoc::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let (log, records) = UpdateLog::open(&dir.join(LOG_FILE))?;
+        if !records.is_empty() {
+            doc.import_batch(&records)?;
+        }
+
+        let saved_vv = doc.oplog_vv();
+        Ok((
+            Self {
+                dir,
+                log,
+                saved_vv,
+                options,
+            },
+            doc,
+        ))
+    }
+
+    /// Append the changes that are not persisted yet to the log.
+    /// The log is compacted if it's too large after that.
+    ///
+    /// It should be called after each commit or import.
+    pub fn save(&mut self, doc: &LoroDoc) -> Result<(), StorageError> {
+        let vv = doc.oplog_vv();
+        if self.saved_vv.includes_vv(&vv) {
+            return Ok(());
+        }
+
+        self.log.append(&doc.export_from(&self.saved_vv))?;
+        self.saved_vv = vv;
+        if self.log.records() >= self.options.max_log_records
+            || self.log.len() >= self.options.max_log_bytes
+        {
+            self.compact(doc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the snapshot with the current doc and clear the log
+    pub fn compact(&mut self, doc: &LoroDoc) -> Result<(), StorageError> {
+        let vv = doc.oplog_vv();
+        let tmp = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&doc.export_snapshot())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, self.dir.join(SNAPSHOT_FILE))?;
+        sync_dir(&self.dir)?;
+        self.log.clear()?;
+        self.saved_vv = vv;
+        Ok(())
+    }
+
+    /// The number of the updates in the log since the last compaction
+    pub fn log_records(&self) -> usize {
+        self.log.records()
+    }
+
+    /// The version that is persisted
+    pub fn saved_version(&self) -> &VersionVector {
+        &self.saved_vv
+    }
+}
+
+/// Make sure the rename is persisted
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
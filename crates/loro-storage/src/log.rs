@@ -0,0 +1,152 @@
+//! The append-only log of the updates.
+//!
+//! Each record is
+//!
+//! ```text
+//! | len: u32 LE | crc32 of data: u32 LE | data |
+//! ```
+//!
+//! A crash in the middle of an append leaves a torn record at the end of the log.
+//! It's detected by the length or the checksum and truncated when the log is opened.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::StorageError;
+
+const RECORD_HEADER_LEN: usize = 8;
+
+pub(crate) struct UpdateLog {
+    file: File,
+    /// The number of the valid records
+    records: usize,
+    /// The length of the valid records in bytes
+    len: u64,
+}
+
+impl UpdateLog {
+    /// Open the log and read all its valid records. The torn tail is truncated.
+    pub(crate) fn open(path: &Path) -> Result<(Self, Vec<Vec<u8>>), StorageError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (records, len) = parse_records(&bytes);
+        if len as usize != bytes.len() {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        file.seek(SeekFrom::Start(len))?;
+        Ok((
+            Self {
+                file,
+                records: records.len(),
+                len,
+            },
+            records,
+        ))
+    }
+
+    /// Append a record and flush it to the disk
+    pub(crate) fn append(&mut self, data: &[u8]) -> Result<(), StorageError> {
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + data.len());
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        buf.extend_from_slice(data);
+        if let Err(e) = self
+            .file
+            .write_all(&buf)
+            .and_then(|_| self.file.sync_data())
+        {
+            // drop the partially written record so that the next append starts at a record boundary
+            let _ = self.file.set_len(self.len);
+            let _ = self.file.seek(SeekFrom::Start(self.len));
+            return Err(e.into());
+        }
+
+        self.records += 1;
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Remove all the records
+    pub(crate) fn clear(&mut self) -> Result<(), StorageError> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()?;
+        self.records = 0;
+        self.len = 0;
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn records(&self) -> usize {
+        self.records
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+/// Return the valid records and their total length in bytes.
+/// Parsing stops at the first incomplete or corrupted record.
+fn parse_records(mut bytes: &[u8]) -> (Vec<Vec<u8>>, u64) {
+    let mut records = Vec::new();
+    let mut len = 0;
+    while bytes.len() >= RECORD_HEADER_LEN {
+        let data_len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let Some(data) = bytes[RECORD_HEADER_LEN..].get(..data_len) else {
+            break;
+        };
+        if crc32fast::hash(data) != checksum {
+            break;
+        }
+
+        records.push(data.to_vec());
+        len += (RECORD_HEADER_LEN + data_len) as u64;
+        bytes = &bytes[RECORD_HEADER_LEN + data_len..];
+    }
+
+    (records, len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn torn_records_are_dropped() {
+        let mut bytes = Vec::new();
+        for data in [&b"hello"[..], b"", b"world"] {
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+
+        let (records, len) = parse_records(&bytes);
+        assert_eq!(records, vec![b"hello".to_vec(), vec![], b"world".to_vec()]);
+        assert_eq!(len as usize, bytes.len());
+        for cut in 0..bytes.len() {
+            let (records, len) = parse_records(&bytes[..cut]);
+            assert!(records.len() < 3);
+            assert!(len as usize <= cut);
+        }
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let (records, len) = parse_records(&bytes);
+        assert_eq!(records.len(), 2);
+        assert_eq!(len, 21);
+    }
+}
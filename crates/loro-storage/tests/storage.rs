@@ -0,0 +1,193 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use loro_internal::LoroDoc;
+use loro_storage::{Storage, StorageOptions};
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("loro-storage-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn file_len(&self, name: &str) -> u64 {
+        fs::metadata(self.0.join(name)).unwrap().len()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn never_compact() -> StorageOptions {
+    StorageOptions {
+        max_log_records: usize::MAX,
+        max_log_bytes: u64::MAX,
+    }
+}
+
+fn edit(doc: &LoroDoc, i: usize) {
+    let text = doc.get_text("text");
+    let len = text.len_unicode();
+    text.insert_(len, &i.to_string()).unwrap();
+    doc.get_map("map")
+        .insert_(&i.to_string(), (i as i32).into())
+        .unwrap();
+    doc.commit_then_renew();
+}
+
+fn text(doc: &LoroDoc) -> String {
+    doc.get_text("text")
+        .get_value()
+        .as_string()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn save_and_load() {
+    let dir = TempDir::new("load");
+    let (mut storage, mut doc) = Storage::open_with_options(&dir.0, never_compact()).unwrap();
+    doc.set_peer_id(1).unwrap();
+    doc.start_auto_commit();
+    for i in 0..10 {
+        edit(&doc, i);
+        storage.save(&doc).unwrap();
+    }
+    // nothing to save
+    storage.save(&doc).unwrap();
+    assert_eq!(storage.log_records(), 10);
+    assert!(!dir.0.join("snapshot.loro").exists());
+
+    // the remote changes are saved too
+    let remote = LoroDoc::new_auto_commit();
+    remote.set_peer_id(2).unwrap();
+    remote.get_list("list").push_(1.into()).unwrap();
+    remote.commit_then_renew();
+    doc.import(&remote.export_snapshot()).unwrap();
+    storage.save(&doc).unwrap();
+    assert_eq!(storage.log_records(), 11);
+    drop(storage);
+
+    let (storage, loaded) = Storage::open(&dir.0).unwrap();
+    assert_eq!(loaded.get_deep_value(), doc.get_deep_value());
+    assert_eq!(loaded.oplog_vv(), doc.oplog_vv());
+    assert_eq!(storage.saved_version(), &doc.oplog_vv());
+    assert_eq!(storage.log_records(), 11);
+}
+
+#[test]
+fn compaction() {
+    let dir = TempDir::new("compaction");
+    let options = StorageOptions {
+        max_log_records: 4,
+        max_log_bytes: u64::MAX,
+    };
+    let (mut storage, mut doc) = Storage::open_with_options(&dir.0, options.clone()).unwrap();
+    doc.set_peer_id(1).unwrap();
+    doc.start_auto_commit();
+    for i in 0..10 {
+        edit(&doc, i);
+        storage.save(&doc).unwrap();
+    }
+    // compacted after the 4th and the 8th saves
+    assert_eq!(storage.log_records(), 2);
+    assert!(dir.0.join("snapshot.loro").exists());
+    assert!(!dir.0.join("snapshot.loro.tmp").exists());
+    drop(storage);
+
+    let (mut storage, mut loaded) = Storage::open_with_options(&dir.0, options).unwrap();
+    assert_eq!(text(&loaded), "0123456789");
+    assert_eq!(loaded.get_deep_value(), doc.get_deep_value());
+
+    // keep editing after loading
+    loaded.set_peer_id(1).unwrap();
+    loaded.start_auto_commit();
+    edit(&loaded, 10);
+    storage.save(&loaded).unwrap();
+    storage.compact(&loaded).unwrap();
+    assert_eq!(storage.log_records(), 0);
+    assert_eq!(dir.file_len("updates.log"), 0);
+    drop(storage);
+
+    let (_, loaded_again) = Storage::open(&dir.0).unwrap();
+    assert_eq!(text(&loaded_again), "012345678910");
+}
+
+#[test]
+fn recover_from_torn_write() {
+    let dir = TempDir::new("torn");
+    let (mut storage, mut doc) = Storage::open_with_options(&dir.0, never_compact()).unwrap();
+    doc.set_peer_id(1).unwrap();
+    doc.start_auto_commit();
+    for i in 0..3 {
+        edit(&doc, i);
+        storage.save(&doc).unwrap();
+    }
+    drop(storage);
+    let valid_len = dir.file_len("updates.log");
+
+    // the process crashed in the middle of appending the 4th update
+    edit(&doc, 3);
+    let update = doc.export_from(&Default::default());
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(dir.0.join("updates.log"))
+        .unwrap();
+    file.write_all(&(update.len() as u32).to_le_bytes())
+        .unwrap();
+    file.write_all(&crc32fast::hash(&update).to_le_bytes())
+        .unwrap();
+    file.write_all(&update[..update.len() / 2]).unwrap();
+    drop(file);
+
+    let (mut storage, mut loaded) = Storage::open_with_options(&dir.0, never_compact()).unwrap();
+    assert_eq!(text(&loaded), "012");
+    assert_eq!(storage.log_records(), 3);
+    // the torn record is truncated
+    assert_eq!(dir.file_len("updates.log"), valid_len);
+
+    // the new records are appended after the valid ones
+    loaded.set_peer_id(1).unwrap();
+    loaded.start_auto_commit();
+    edit(&loaded, 3);
+    storage.save(&loaded).unwrap();
+    drop(storage);
+    let (storage, loaded) = Storage::open(&dir.0).unwrap();
+    assert_eq!(text(&loaded), "0123");
+    assert_eq!(storage.log_records(), 4);
+}
+
+#[test]
+fn crash_during_compaction() {
+    let dir = TempDir::new("crash-compaction");
+    let (mut storage, mut doc) = Storage::open_with_options(&dir.0, never_compact()).unwrap();
+    doc.set_peer_id(1).unwrap();
+    doc.start_auto_commit();
+    for i in 0..3 {
+        edit(&doc, i);
+        storage.save(&doc).unwrap();
+    }
+    drop(storage);
+
+    // the snapshot is written but the log is not cleared
+    fs::write(dir.0.join("snapshot.loro"), doc.export_snapshot()).unwrap();
+    let (storage, loaded) = Storage::open(&dir.0).unwrap();
+    assert_eq!(text(&loaded), "012");
+    assert_eq!(loaded.get_deep_value(), doc.get_deep_value());
+    assert_eq!(storage.log_records(), 3);
+
+    // a leftover temporary snapshot is ignored
+    fs::write(dir.0.join("snapshot.loro.tmp"), b"garbage").unwrap();
+    let (_, loaded) = Storage::open(&dir.0).unwrap();
+    assert_eq!(text(&loaded), "012");
+}
@@ -0,0 +1,23 @@
+//! A transport-agnostic protocol to sync [loro_internal::LoroDoc]s by version vectors.
+//!
+//! Each connection to a remote peer is driven by a [SyncSession]. The session only
+//! produces and consumes [Message]s, the user is responsible for sending them over
+//! a reliable and ordered transport (e.g. WebSocket, TCP or an in-memory channel).
+//!
+//! ```text
+//!  A                               B
+//!  |-- Version(vv_a) ------------->|
+//!  |<------------- Version(vv_b) --|
+//!  |-- Request(vv_a) ------------->|   if B has changes A doesn't have
+//!  |<------- Update(vv_b \ vv_a) --|
+//!  |-- Ack(vv_a') ---------------->|
+//! ```
+//!
+//! After the handshake, [SyncSession::poll_update] pushes the new local changes
+//! (or the changes imported from other peers) to the remote.
+
+mod message;
+mod session;
+
+pub use message::{Message, PROTOCOL_VERSION};
+pub use session::SyncSession;
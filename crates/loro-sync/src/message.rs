@@ -0,0 +1,84 @@
+use loro_common::{LoroError, LoroResult};
+use loro_internal::VersionVector;
+use serde::{Deserialize, Serialize};
+
+/// The version of the sync protocol. It's the first byte of every encoded [Message].
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// The messages exchanged between two [crate::SyncSession]s
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    /// Announce the version of the sender. It's the first message of a session.
+    Version {
+        vv: VersionVector,
+        /// The newest encoding schema version the sender can import
+        schema_version: u8,
+    },
+    /// Request the changes that are not included in `from`
+    Request { from: VersionVector },
+    /// The exported updates. The sender has all the changes in `vv`
+    Update { data: Vec<u8>, vv: VersionVector },
+    /// Acknowledge the received updates with the version after importing them
+    Ack { vv: VersionVector },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ans = vec![PROTOCOL_VERSION];
+        ans.extend_from_slice(&postcard::to_allocvec(self).unwrap());
+        ans
+    }
+
+    pub fn decode(bytes: &[u8]) -> LoroResult<Self> {
+        let Some((&version, bytes)) = bytes.split_first() else {
+            return Err(LoroError::DecodeError("Empty sync message".into()));
+        };
+        if version != PROTOCOL_VERSION {
+            return Err(LoroError::DecodeError(
+                format!(
+                    "Unsupported sync protocol version {}, expected {}",
+                    version, PROTOCOL_VERSION
+                )
+                .into_boxed_str(),
+            ));
+        }
+
+        postcard::from_bytes(bytes)
+            .map_err(|e| LoroError::DecodeError(e.to_string().into_boxed_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let mut vv = VersionVector::new();
+        vv.insert(1, 10);
+        vv.insert(u64::MAX, 3);
+        let messages = [
+            Message::Version {
+                vv: vv.clone(),
+                schema_version: 3,
+            },
+            Message::Request { from: vv.clone() },
+            Message::Update {
+                data: vec![1, 2, 3],
+                vv: vv.clone(),
+            },
+            Message::Ack { vv },
+        ];
+        for msg in messages {
+            assert_eq!(Message::decode(&msg.encode()).unwrap(), msg);
+        }
+
+        let mut bytes = Message::Ack {
+            vv: Default::default(),
+        }
+        .encode();
+        bytes[0] = PROTOCOL_VERSION + 1;
+        assert!(Message::decode(&bytes).is_err());
+        assert!(Message::decode(&[]).is_err());
+    }
+}
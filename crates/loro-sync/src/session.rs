@@ -0,0 +1,113 @@
+use loro_common::{LoroError, LoroResult};
+use loro_internal::{negotiate_schema_version, LoroDoc, VersionVector, ENCODE_SCHEMA_VERSION};
+
+use crate::Message;
+
+/// The sync state of the connection to one remote peer.
+///
+/// It tracks which changes the remote has, so that only the missing spans are sent.
+/// The messages must be delivered reliably and in order, otherwise the session should
+/// be dropped and a new one started.
+#[derive(Debug, Default)]
+pub struct SyncSession {
+    /// The schema version used to export the updates. It's known after the remote announces its version
+    schema_version: Option<u8>,
+    /// The version the remote is known to have
+    remote_vv: VersionVector,
+    /// `remote_vv` and the changes that are sent but not acknowledged yet
+    sent_vv: VersionVector,
+}
+
+impl SyncSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first message of the session, it should be sent to the remote as soon as
+    /// the connection is established.
+    pub fn start(&self, doc: &LoroDoc) -> Message {
+        Message::Version {
+            vv: doc.oplog_vv(),
+            schema_version: ENCODE_SCHEMA_VERSION,
+        }
+    }
+
+    /// Handle the message from the remote. Return the messages that should be sent back.
+    ///
+    /// # Err
+    ///
+    /// - The updates cannot be imported
+    /// - The remote sends updates before announcing its version
+    pub fn handle(&mut self, doc: &LoroDoc, msg: Message) -> LoroResult<Vec<Message>> {
+        let mut ans = Vec::new();
+        match msg {
+            Message::Version { vv, schema_version } => {
+                self.schema_version = Some(negotiate_schema_version(schema_version));
+                self.remote_vv.merge(&vv);
+                self.sent_vv.merge(&vv);
+                let local_vv = doc.oplog_vv();
+                if !local_vv.includes_vv(&vv) {
+                    ans.push(Message::Request { from: local_vv });
+                }
+            }
+            Message::Request { from } => {
+                self.ensure_started()?;
+                self.remote_vv.merge(&from);
+                self.sent_vv.merge(&from);
+                ans.extend(self.poll_update(doc));
+            }
+            Message::Update { data, vv } => {
+                self.ensure_started()?;
+                doc.import(&data)?;
+                self.remote_vv.merge(&vv);
+                self.sent_vv.merge(&vv);
+                ans.push(Message::Ack { vv: doc.oplog_vv() });
+            }
+            Message::Ack { vv } => {
+                self.remote_vv.merge(&vv);
+                self.sent_vv.merge(&vv);
+            }
+        }
+
+        Ok(ans)
+    }
+
+    /// Get the update of the changes the remote doesn't have and hasn't been sent.
+    ///
+    /// It should be called after the doc is changed, either by local edits or by
+    /// the imports from other peers. Return `None` if there is nothing to send or
+    /// the remote hasn't announced its version yet.
+    pub fn poll_update(&mut self, doc: &LoroDoc) -> Option<Message> {
+        let schema_version = self.schema_version?;
+        let vv = doc.oplog_vv();
+        if self.sent_vv.includes_vv(&vv) {
+            return None;
+        }
+
+        let data = doc
+            .export_from_with_schema(&self.sent_vv, schema_version)
+            .unwrap();
+        self.sent_vv.merge(&vv);
+        Some(Message::Update { data, vv })
+    }
+
+    /// The version the remote is known to have
+    pub fn remote_version(&self) -> &VersionVector {
+        &self.remote_vv
+    }
+
+    /// Whether the remote has acknowledged all the changes of the doc
+    pub fn is_synced(&self, doc: &LoroDoc) -> bool {
+        self.schema_version.is_some() && self.remote_vv.includes_vv(&doc.oplog_vv())
+    }
+
+    fn ensure_started(&self) -> LoroResult<()> {
+        if self.schema_version.is_none() {
+            return Err(LoroError::DecodeError(
+                "The remote sends updates before announcing its version".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use loro_internal::LoroDoc;
+use loro_sync::{Message, SyncSession};
+
+/// One end of a connection over in-memory channels
+struct Conn {
+    session: SyncSession,
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    sent: Vec<Message>,
+}
+
+impl Conn {
+    fn send(&mut self, msg: Message) {
+        self.tx.send(msg.encode()).unwrap();
+        self.sent.push(msg);
+    }
+
+    /// Handle all the received messages. Return whether anything is received
+    fn recv(&mut self, doc: &LoroDoc) -> bool {
+        let mut received = false;
+        while let Ok(bytes) = self.rx.try_recv() {
+            received = true;
+            let msg = Message::decode(&bytes).unwrap();
+            for reply in self.session.handle(doc, msg).unwrap() {
+                self.send(reply);
+            }
+        }
+        received
+    }
+
+    fn push(&mut self, doc: &LoroDoc) {
+        if let Some(msg) = self.session.poll_update(doc) {
+            self.send(msg);
+        }
+    }
+
+    fn updates_sent(&self) -> usize {
+        self.sent
+            .iter()
+            .filter(|x| matches!(x, Message::Update { .. }))
+            .count()
+    }
+}
+
+fn connect(a: &LoroDoc, b: &LoroDoc) -> (Conn, Conn) {
+    let (tx_a, rx_b) = channel();
+    let (tx_b, rx_a) = channel();
+    let mut conn_a = Conn {
+        session: SyncSession::new(),
+        tx: tx_a,
+        rx: rx_a,
+        sent: Vec::new(),
+    };
+    let mut conn_b = Conn {
+        session: SyncSession::new(),
+        tx: tx_b,
+        rx: rx_b,
+        sent: Vec::new(),
+    };
+    let msg = conn_a.session.start(a);
+    conn_a.send(msg);
+    let msg = conn_b.session.start(b);
+    conn_b.send(msg);
+    (conn_a, conn_b)
+}
+
+/// Deliver the messages until both sides are idle
+fn pump(a: &LoroDoc, conn_a: &mut Conn, b: &LoroDoc, conn_b: &mut Conn) {
+    loop {
+        let received_a = conn_a.recv(a);
+        let received_b = conn_b.recv(b);
+        if !received_a && !received_b {
+            break;
+        }
+    }
+}
+
+fn doc(peer: u64, text: &str) -> LoroDoc {
+    let doc = LoroDoc::new_auto_commit();
+    doc.set_peer_id(peer).unwrap();
+    doc.get_text("text").insert_(0, text).unwrap();
+    doc.commit_then_renew();
+    doc
+}
+
+#[test]
+fn initial_sync() {
+    let a = doc(1, "a");
+    let b = doc(2, "b");
+    let (mut conn_a, mut conn_b) = connect(&a, &b);
+    pump(&a, &mut conn_a, &b, &mut conn_b);
+    assert_eq!(a.get_deep_value(), b.get_deep_value());
+    assert_eq!(a.oplog_vv(), b.oplog_vv());
+    assert!(conn_a.session.is_synced(&a));
+    assert!(conn_b.session.is_synced(&b));
+    assert_eq!(conn_a.updates_sent(), 1);
+    assert_eq!(conn_b.updates_sent(), 1);
+
+    // nothing more to send
+    conn_a.push(&a);
+    conn_b.push(&b);
+    assert_eq!(conn_a.updates_sent(), 1);
+    assert_eq!(conn_b.updates_sent(), 1);
+}
+
+#[test]
+fn only_missing_spans_are_sent() {
+    let a = doc(1, "hello");
+    let b = LoroDoc::new_auto_commit();
+    b.set_peer_id(2).unwrap();
+    b.import(&a.export_snapshot()).unwrap();
+    a.get_text("text").insert_(5, " world").unwrap();
+    a.commit_then_renew();
+    let vv_b = b.oplog_vv();
+
+    let (mut conn_a, mut conn_b) = connect(&a, &b);
+    pump(&a, &mut conn_a, &b, &mut conn_b);
+    assert_eq!(
+        b.get_text("text").get_value().as_string().unwrap().as_str(),
+        "hello world"
+    );
+    assert_eq!(conn_b.updates_sent(), 0);
+    let updates: Vec<_> = conn_a
+        .sent
+        .iter()
+        .filter_map(|x| match x {
+            Message::Update { data, .. } => Some(data.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(updates, vec![a.export_from(&vv_b)]);
+}
+
+#[test]
+fn live_updates() {
+    let a = doc(1, "a");
+    let b = doc(2, "b");
+    let (mut conn_a, mut conn_b) = connect(&a, &b);
+    pump(&a, &mut conn_a, &b, &mut conn_b);
+
+    for i in 0..10 {
+        a.get_list("list").push_(i.into()).unwrap();
+        a.commit_then_renew();
+        conn_a.push(&a);
+        b.get_map("map").insert_(&i.to_string(), i.into()).unwrap();
+        b.commit_then_renew();
+        conn_b.push(&b);
+        pump(&a, &mut conn_a, &b, &mut conn_b);
+        assert_eq!(a.get_deep_value(), b.get_deep_value());
+    }
+
+    // the received changes are not echoed back
+    assert_eq!(conn_a.updates_sent(), 11);
+    assert_eq!(conn_b.updates_sent(), 11);
+    assert!(conn_a.session.is_synced(&a));
+    assert!(conn_b.session.is_synced(&b));
+}
+
+#[test]
+fn updates_are_relayed() {
+    // a <-> hub <-> c
+    let a = doc(1, "a");
+    let hub = doc(2, "hub");
+    let c = doc(3, "c");
+    let (mut a_to_hub, mut hub_to_a) = connect(&a, &hub);
+    let (mut hub_to_c, mut c_to_hub) = connect(&hub, &c);
+    for _ in 0..3 {
+        pump(&a, &mut a_to_hub, &hub, &mut hub_to_a);
+        hub_to_c.push(&hub);
+        pump(&hub, &mut hub_to_c, &c, &mut c_to_hub);
+        hub_to_a.push(&hub);
+    }
+    assert_eq!(a.get_deep_value(), c.get_deep_value());
+    assert_eq!(a.get_deep_value(), hub.get_deep_value());
+
+    c.get_text("text").insert_(0, "new ").unwrap();
+    c.commit_then_renew();
+    c_to_hub.push(&c);
+    pump(&hub, &mut hub_to_c, &c, &mut c_to_hub);
+    hub_to_a.push(&hub);
+    pump(&a, &mut a_to_hub, &hub, &mut hub_to_a);
+    assert_eq!(a.get_deep_value(), c.get_deep_value());
+    assert!(hub_to_a.session.is_synced(&hub));
+    assert!(hub_to_c.session.is_synced(&hub));
+}
+
+#[test]
+fn updates_before_handshake_are_rejected() {
+    let a = doc(1, "a");
+    let b = LoroDoc::new();
+    let mut session = SyncSession::new();
+    assert!(session.poll_update(&a).is_none());
+    let err = session
+        .handle(
+            &b,
+            Message::Update {
+                data: a.export_from(&Default::default()),
+                vv: a.oplog_vv(),
+            },
+        )
+        .unwrap_err();
+    assert!(matches!(err, loro_common::LoroError::DecodeError(_)));
+}